@@ -0,0 +1,158 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ggrs::{Config, GameStateCell, GgrsRequest, SessionBuilder, SyncTestSession};
+use serde::{Deserialize, Serialize};
+
+// sizes of the stub game state in bytes
+const STATE_SIZES: [usize; 3] = [64, 4 * 1024, 256 * 1024];
+// number of frames simulated per iteration
+const FRAMES: usize = 100;
+// how many frames a rollback resimulates
+const ROLLBACK_DEPTH: usize = 7;
+
+#[derive(Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+struct BenchInput {
+    inp: u32,
+}
+
+struct BenchConfig;
+
+impl Config for BenchConfig {
+    type Input = BenchInput;
+    type State = BenchState;
+    type Address = String;
+}
+
+#[derive(Clone)]
+struct BenchState {
+    frame: u32,
+    data: Vec<u8>,
+}
+
+impl BenchState {
+    fn new(state_size: usize) -> Self {
+        Self {
+            frame: 0,
+            data: vec![0; state_size],
+        }
+    }
+}
+
+struct BenchGame {
+    state: BenchState,
+}
+
+impl BenchGame {
+    fn new(state_size: usize) -> Self {
+        Self {
+            state: BenchState::new(state_size),
+        }
+    }
+
+    fn handle_requests(&mut self, requests: Vec<GgrsRequest<BenchConfig>>) {
+        for request in requests {
+            match request {
+                GgrsRequest::SaveGameState { cell, frame } => cell.save(
+                    frame,
+                    Some(self.state.clone()),
+                    Some(self.state.frame as u128),
+                ),
+                GgrsRequest::LoadGameState { cell, .. } => {
+                    self.state = cell.load().expect("No data found.")
+                }
+                GgrsRequest::AdvanceFrame { inputs } => {
+                    // touch the whole state, like a game updating all of its entities
+                    self.state.frame += 1;
+                    let inp = inputs[0].0.inp as u8;
+                    for byte in self.state.data.iter_mut() {
+                        *byte = byte.wrapping_add(inp);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn start_session(check_distance: usize) -> SyncTestSession<BenchConfig> {
+    SessionBuilder::<BenchConfig>::new()
+        .with_num_players(1)
+        .with_max_prediction_window(8)
+        .with_check_distance(check_distance)
+        .start_synctest_session()
+        .expect("Could not start the session.")
+}
+
+fn run_frames(sess: &mut SyncTestSession<BenchConfig>, game: &mut BenchGame) {
+    for i in 0..FRAMES {
+        sess.add_local_input(0, BenchInput { inp: i as u32 })
+            .expect("Could not add input.");
+        let requests = sess.advance_frame().expect("Could not advance the frame.");
+        game.handle_requests(requests);
+    }
+}
+
+fn bench_simulation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulation");
+    group.throughput(Throughput::Elements(FRAMES as u64));
+    for size in STATE_SIZES {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| {
+                // without a check distance, the session never rolls back
+                let mut sess = start_session(0);
+                let mut game = BenchGame::new(size);
+                run_frames(&mut sess, &mut game);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_save(c: &mut Criterion) {
+    let mut group = c.benchmark_group("save");
+    for size in STATE_SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            let game = BenchGame::new(size);
+            let cell = GameStateCell::default();
+            b.iter(|| cell.save(0, Some(game.state.clone()), Some(0)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    for size in STATE_SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            let cell = GameStateCell::default();
+            cell.save(0, Some(BenchState::new(size)), Some(0));
+            b.iter(|| cell.load())
+        });
+    }
+    group.finish();
+}
+
+fn bench_rollback(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rollback");
+    group.throughput(Throughput::Elements(FRAMES as u64));
+    for size in STATE_SIZES {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| {
+                // the sync test session rolls back by the check distance every frame
+                let mut sess = start_session(ROLLBACK_DEPTH);
+                let mut game = BenchGame::new(size);
+                run_frames(&mut sess, &mut game);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_simulation,
+    bench_save,
+    bench_load,
+    bench_rollback
+);
+criterion_main!(benches);
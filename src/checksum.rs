@@ -0,0 +1,139 @@
+const FNV_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+/// Computes a checksum over the given logical fields of your game state, to be passed to [`GameStateCell::save()`].
+///
+/// Hashing the raw memory of a state can include padding bytes, whose values are undefined and can differ between machines
+/// even if the logical state is identical. Passing the bytes of each field separately (e.g. `&x.to_le_bytes()`) avoids this.
+/// Fields are length-prefixed, so `[b"ab", b"c"]` and `[b"a", b"bc"]` result in different checksums. The result is the same on
+/// every platform, as long as the field bytes are (use explicit little-endian encodings for numbers).
+///
+/// [`GameStateCell::save()`]: crate::GameStateCell::save
+pub fn canonical_checksum(fields: &[&[u8]]) -> u128 {
+    // FNV-1a, 128 bit
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut write = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash ^= byte as u128;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    };
+    for field in fields {
+        write(&(field.len() as u64).to_le_bytes());
+        write(field);
+    }
+    hash
+}
+
+// Number of bytes shown before and after the first difference of two states
+const STATE_DIFF_CONTEXT: usize = 8;
+
+/// The first difference between two serialized game states, as found by [`diff_states()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDiff {
+    /// Byte offset of the first difference. If one state is a prefix of the other, this is the length of the shorter one.
+    pub offset: usize,
+    /// Byte offset at which the context windows `a` and `b` start.
+    pub context_start: usize,
+    /// Bytes of the first state around the difference.
+    pub a: Vec<u8>,
+    /// Bytes of the second state around the difference.
+    pub b: Vec<u8>,
+}
+
+/// Compares two serialized game states byte by byte and reports the first difference together with a few bytes of surrounding context,
+/// or `None` if both are identical. Serialize the states of two peers (or of a frame before and after a rollback) the same way
+/// and compare them to narrow down where a desync originates.
+pub fn diff_states(a: &[u8], b: &[u8]) -> Option<StateDiff> {
+    let offset = match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(offset) => offset,
+        None if a.len() == b.len() => return None,
+        None => a.len().min(b.len()),
+    };
+
+    let context_start = offset.saturating_sub(STATE_DIFF_CONTEXT);
+    let context_end = offset + STATE_DIFF_CONTEXT + 1;
+    let window =
+        |bytes: &[u8]| bytes[context_start.min(bytes.len())..context_end.min(bytes.len())].to_vec();
+
+    Some(StateDiff {
+        offset,
+        context_start,
+        a: window(a),
+        b: window(b),
+    })
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod checksum_tests {
+    use super::*;
+
+    #[test]
+    fn test_padding_does_not_affect_checksum() {
+        // raw memory of a `#[repr(C)] struct { a: u8, b: u32 }`: one byte of data, three bytes of padding, four bytes of data
+        let raw1 = [7u8, 0, 0, 0, 1, 2, 3, 4];
+        let raw2 = [7u8, 0xAB, 0xCD, 0xEF, 1, 2, 3, 4];
+        assert_ne!(canonical_checksum(&[&raw1]), canonical_checksum(&[&raw2]));
+
+        // hashing the logical fields only ignores the padding
+        let fields1: [&[u8]; 2] = [&raw1[..1], &raw1[4..]];
+        let fields2: [&[u8]; 2] = [&raw2[..1], &raw2[4..]];
+        assert_eq!(canonical_checksum(&fields1), canonical_checksum(&fields2));
+    }
+
+    #[test]
+    fn test_field_boundaries_matter() {
+        assert_ne!(
+            canonical_checksum(&[b"ab", b"c"]),
+            canonical_checksum(&[b"a", b"bc"])
+        );
+        assert_ne!(canonical_checksum(&[]), canonical_checksum(&[b""]));
+    }
+
+    #[test]
+    fn test_checksum_is_stable() {
+        // the checksum must never change between versions or platforms, so it is pinned to a value computed independently
+        assert_eq!(
+            canonical_checksum(&[b"ggrs", &5u32.to_le_bytes()]),
+            0x42dc709f294972672fc061ef3dda5ae5
+        );
+    }
+
+    #[test]
+    fn test_diff_states() {
+        let a: Vec<u8> = (0..32).collect();
+        assert_eq!(diff_states(&a, &a), None);
+
+        let mut b = a.clone();
+        b[20] = 0xFF;
+        let diff = diff_states(&a, &b).unwrap();
+        assert_eq!(diff.offset, 20);
+        assert_eq!(diff.context_start, 12);
+        assert_eq!(diff.a, (12..29).collect::<Vec<u8>>());
+        assert_eq!(diff.b[..8], a[12..20]);
+        assert_eq!(diff.b[8], 0xFF);
+        assert_eq!(diff.b[9..], a[21..29]);
+    }
+
+    #[test]
+    fn test_diff_states_at_edges() {
+        let a: Vec<u8> = (0..32).collect();
+
+        // the context is clipped at the start
+        let mut b = a.clone();
+        b[2] = 0xFF;
+        let diff = diff_states(&a, &b).unwrap();
+        assert_eq!((diff.offset, diff.context_start), (2, 0));
+        assert_eq!(diff.a, (0..11).collect::<Vec<u8>>());
+
+        // a state that is a prefix of the other differs where the shorter one ends
+        let diff = diff_states(&a, &a[..30]).unwrap();
+        assert_eq!((diff.offset, diff.context_start), (30, 22));
+        assert_eq!(diff.a, (22..32).collect::<Vec<u8>>());
+        assert_eq!(diff.b, (22..30).collect::<Vec<u8>>());
+    }
+}
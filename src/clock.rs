@@ -0,0 +1,88 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use instant::{Duration, Instant};
+use parking_lot::Mutex;
+
+/// A source of time for all timers of the network protocol, like disconnect timeouts, resends and keep-alive packets.
+/// Sessions use the [`SystemClock`] by default. Provide your own [`Clock`] via [`SessionBuilder::with_clock()`]
+/// to control the passing of time, e.g. with a [`MockClock`] in tests.
+///
+/// [`SessionBuilder::with_clock()`]: crate::SessionBuilder#method.with_clock
+#[cfg(feature = "sync-send")]
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current point in time.
+    fn now(&self) -> Instant;
+}
+
+/// A source of time for all timers of the network protocol, like disconnect timeouts, resends and keep-alive packets.
+/// Sessions use the [`SystemClock`] by default. Provide your own [`Clock`] via [`SessionBuilder::with_clock()`]
+/// to control the passing of time, e.g. with a [`MockClock`] in tests.
+///
+/// [`SessionBuilder::with_clock()`]: crate::SessionBuilder#method.with_clock
+#[cfg(not(feature = "sync-send"))]
+pub trait Clock: Debug {
+    /// Returns the current point in time.
+    fn now(&self) -> Instant;
+}
+
+/// The default [`Clock`], backed by [`Instant::now()`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A [`Clock`] that only moves forward when told to. All clones of a [`MockClock`] share the same time,
+/// so you can hand a clone to the [`SessionBuilder`] and advance the time from your test.
+///
+/// [`SessionBuilder`]: crate::SessionBuilder
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    /// Creates a new [`MockClock`], starting at the current point in time.
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Moves the time of this clock and all its clones forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock()
+    }
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_advances_clones() {
+        let clock = MockClock::new();
+        let clone = clock.clone();
+        let start = clock.now();
+        assert_eq!(clone.now(), start);
+
+        clone.advance(Duration::from_millis(250));
+        assert_eq!(clock.now(), start + Duration::from_millis(250));
+    }
+}
@@ -29,6 +29,39 @@ pub enum GgrsError {
     NotSynchronized,
     /// The spectator got so far behind the host that catching up is impossible.
     SpectatorTooFarBehind,
+    /// The gamestate of a frame that needs to be loaded for a rollback is not saved anymore, e.g. because the number of saved frames
+    /// has been limited with [`SessionBuilder::with_max_saved_frames()`] and the rollback reaches further back than that.
+    ///
+    /// [`SessionBuilder::with_max_saved_frames()`]: crate::SessionBuilder::with_max_saved_frames
+    StateNotSaved {
+        /// The frame that could not be loaded.
+        frame: Frame,
+    },
+    /// Saving a gamestate, loading it and saving it again resulted in a different checksum, so loading does not restore
+    /// everything that is saved. See [`SyncTestSession::verify_state_roundtrip()`].
+    ///
+    /// [`SyncTestSession::verify_state_roundtrip()`]: crate::SyncTestSession::verify_state_roundtrip
+    StateRoundtripMismatch {
+        /// The frame of the gamestate.
+        frame: Frame,
+        /// The checksum of the first save.
+        saved: u128,
+        /// The checksum of the save after loading.
+        reloaded: u128,
+    },
+    /// The socket for a session could not be created, e.g. because the port is already in use.
+    SocketError {
+        /// The reason given by the operating system.
+        info: String,
+    },
+    /// A checkpoint of a [`SyncTestSession`] could not be written or read, e.g. because of an I/O error, corrupted data
+    /// or a checkpoint written by an incompatible version of GGRS.
+    ///
+    /// [`SyncTestSession`]: crate::SyncTestSession
+    CheckpointError {
+        /// Further specifies why the checkpoint could not be written or read.
+        info: String,
+    },
 }
 
 impl Display for GgrsError {
@@ -65,6 +98,30 @@ impl Display for GgrsError {
                     "The spectator got so far behind the host that catching up is impossible."
                 )
             }
+            GgrsError::StateNotSaved { frame } => {
+                write!(
+                    f,
+                    "The gamestate of frame {} is not saved anymore and cannot be loaded.",
+                    frame
+                )
+            }
+            GgrsError::StateRoundtripMismatch {
+                frame,
+                saved,
+                reloaded,
+            } => {
+                write!(
+                    f,
+                    "Loading the gamestate of frame {} does not restore it: saved with checksum {}, but saved again after loading with checksum {}.",
+                    frame, saved, reloaded
+                )
+            }
+            GgrsError::SocketError { info } => {
+                write!(f, "Socket Error: {}", info)
+            }
+            GgrsError::CheckpointError { info } => {
+                write!(f, "Checkpoint Error: {}", info)
+            }
         }
     }
 }
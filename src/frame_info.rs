@@ -1,8 +1,10 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{Frame, NULL_FRAME};
 
 /// Represents the game state of your game for a single frame. The `data` holds the game state, `frame` indicates the associated frame number
 /// and `checksum` can additionally be provided for use during a `SyncTestSession`.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct GameState<S> {
     /// The frame to which this info belongs to.
     pub frame: Frame,
@@ -10,6 +12,12 @@ pub(crate) struct GameState<S> {
     pub data: Option<S>,
     /// The checksum of the gamestate.
     pub checksum: Option<u128>,
+    /// The checksum of the gameplay-relevant part of the gamestate. If given, desync detection compares this instead of `checksum`.
+    pub gameplay_checksum: Option<u128>,
+    /// Whether the user reported the state to be identical to the state of the previous frame.
+    pub unchanged: bool,
+    /// Whether the session uses checksums at all. If not, given checksums are discarded.
+    pub checksums_enabled: bool,
 }
 
 impl<S> Default for GameState<S> {
@@ -18,13 +26,16 @@ impl<S> Default for GameState<S> {
             frame: NULL_FRAME,
             data: None,
             checksum: None,
+            gameplay_checksum: None,
+            unchanged: false,
+            checksums_enabled: true,
         }
     }
 }
 
 /// Represents an input for a single player in a single frame. The associated frame is denoted with `frame`.
 /// You do not need to create this struct, but the sessions will provide a `Vec<PlayerInput>` for you during `advance_frame()`.
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct PlayerInput<I>
 where
     I: Copy + Clone + PartialEq,
@@ -1,12 +1,16 @@
 use crate::frame_info::PlayerInput;
 use crate::{Config, Frame, InputStatus, NULL_FRAME};
+use serde::{Deserialize, Serialize};
 use std::cmp;
 
-/// The length of the input queue. This describes the number of inputs GGRS can hold at the same time per player.
-const INPUT_QUEUE_LENGTH: usize = 128;
+/// The default length of the input queue. This describes the number of inputs GGRS can hold at the same time per player.
+pub(crate) const INPUT_QUEUE_LENGTH: usize = 128;
+/// The number of recently checked predictions the misprediction rate is computed from.
+const PREDICTION_HISTORY_LENGTH: u32 = 64;
 
 /// `InputQueue` handles inputs for a single player and saves them in a circular array. Valid Inputs are between `head` and `tail`.
-#[derive(Debug, Clone)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
 pub(crate) struct InputQueue<T>
 where
     T: Config,
@@ -20,24 +24,59 @@ where
     /// Denotes if we still are in the first frame, an edge case to be considered by some methods.
     first_frame: bool,
 
-    /// The last frame added by the user
+    /// The last frame added to the queue, including the input delay
     last_added_frame: Frame,
+    /// The frame of the last input given by the user, before applying the input delay
+    last_user_added_frame: Frame,
     /// The first frame in the queue that is known to be an incorrect prediction
     first_incorrect_frame: Frame,
     /// The last frame that has been requested. We make sure to never delete anything after this, as we would throw away important data.
     last_requested_frame: Frame,
 
-    /// The delay in frames by which inputs are sent back to the user. This can be set during initialization.
+    /// The delay in frames by which inputs are sent back to the user. When it changes while inputs are added, the queue
+    /// repeats the last input to fill the gap of an increased delay, or drops inputs until a decreased delay has caught up.
     frame_delay: usize,
 
     /// Our cyclic input queue
     inputs: Vec<PlayerInput<T::Input>>,
     /// A pre-allocated prediction we are going to use to return predictions from.
     prediction: PlayerInput<T::Input>,
+    /// bit `i` is set if the `i`-th most recently added input did not match its prediction. Inputs added before they had
+    /// to be predicted are checked against the prediction they would have gotten.
+    prediction_history: u64,
+    /// The number of inputs recorded in `prediction_history`.
+    predictions_checked: u32,
+}
+
+// not derived, since that would require `T` itself to be `Clone`
+impl<T: Config> Clone for InputQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            head: self.head,
+            tail: self.tail,
+            length: self.length,
+            first_frame: self.first_frame,
+            last_added_frame: self.last_added_frame,
+            last_user_added_frame: self.last_user_added_frame,
+            first_incorrect_frame: self.first_incorrect_frame,
+            last_requested_frame: self.last_requested_frame,
+            frame_delay: self.frame_delay,
+            inputs: self.inputs.clone(),
+            prediction: self.prediction,
+            prediction_history: self.prediction_history,
+            predictions_checked: self.predictions_checked,
+        }
+    }
 }
 
 impl<T: Config> InputQueue<T> {
     pub(crate) fn new() -> Self {
+        Self::with_length(INPUT_QUEUE_LENGTH)
+    }
+
+    /// Creates a queue that can hold `length` inputs at the same time.
+    pub(crate) fn with_length(length: usize) -> Self {
+        assert!(length > 0);
         Self {
             head: 0,
             tail: 0,
@@ -45,38 +84,87 @@ impl<T: Config> InputQueue<T> {
             frame_delay: 0,
             first_frame: true,
             last_added_frame: NULL_FRAME,
+            last_user_added_frame: NULL_FRAME,
             first_incorrect_frame: NULL_FRAME,
             last_requested_frame: NULL_FRAME,
             prediction: PlayerInput::blank_input(NULL_FRAME),
-            inputs: vec![PlayerInput::blank_input(NULL_FRAME); INPUT_QUEUE_LENGTH],
+            inputs: vec![PlayerInput::blank_input(NULL_FRAME); length],
+            prediction_history: 0,
+            predictions_checked: 0,
         }
     }
 
+    /// Places the empty queue so that it expects the input for `frame` next, as if all earlier inputs had been added
+    /// and all but the most recent one discarded. For testing only, e.g. to run a session close to the last frame it can advance to.
+    #[cfg(test)]
+    pub(crate) fn start_at_frame(&mut self, frame: Frame) {
+        assert!(self.first_frame);
+        // inputs are stored at the position of their frame modulo the capacity, and the most recent one is always kept
+        self.head = frame as usize % self.capacity();
+        self.tail = (self.head + self.capacity() - 1) % self.capacity();
+        self.length = 1;
+        self.inputs[self.tail].frame = frame - 1;
+        self.last_added_frame = frame - 1;
+        self.first_frame = false;
+    }
+
+    /// Returns the number of inputs this queue can hold at the same time.
+    pub(crate) fn capacity(&self) -> usize {
+        self.inputs.len()
+    }
+
     pub(crate) fn first_incorrect_frame(&self) -> Frame {
         self.first_incorrect_frame
     }
 
+    /// Returns true if the input for the given frame can be added without exceeding the length of the queue.
+    pub(crate) fn has_room_for(&self, frame: Frame) -> bool {
+        let next_frame = if self.first_frame {
+            0
+        } else {
+            self.last_added_frame + 1
+        };
+        let new_inputs = cmp::max(frame + self.frame_delay as Frame - next_frame + 1, 0) as usize;
+        self.length + new_inputs <= self.capacity()
+    }
+
     pub(crate) fn set_frame_delay(&mut self, delay: usize) {
         self.frame_delay = delay;
     }
 
+    pub(crate) fn frame_delay(&self) -> usize {
+        self.frame_delay
+    }
+
+    /// Returns the share of recently added inputs that did not match their (possibly hypothetical) prediction, between 0 and 1.
+    pub(crate) fn misprediction_rate(&self) -> f32 {
+        if self.predictions_checked == 0 {
+            return 0.0;
+        }
+        self.prediction_history.count_ones() as f32 / self.predictions_checked as f32
+    }
+
     pub(crate) fn reset_prediction(&mut self) {
         self.prediction.frame = NULL_FRAME;
         self.first_incorrect_frame = NULL_FRAME;
         self.last_requested_frame = NULL_FRAME;
     }
 
-    /// Returns a `PlayerInput`, but only if the input for the requested frame is confirmed.
-    /// In contrast to `input()`, this will not return a prediction if there is no confirmed input for the frame, but panic instead.
-    pub(crate) fn confirmed_input(&self, requested_frame: Frame) -> PlayerInput<T::Input> {
-        let offset = requested_frame as usize % INPUT_QUEUE_LENGTH;
+    /// Returns a `PlayerInput`, but only if the input for the requested frame is confirmed and has not been discarded yet.
+    /// In contrast to `input()`, this will not return a prediction if there is no confirmed input for the frame, but `None` instead.
+    pub(crate) fn confirmed_input(&self, requested_frame: Frame) -> Option<PlayerInput<T::Input>> {
+        if requested_frame < 0
+            || requested_frame > self.last_added_frame
+            || requested_frame < self.inputs[self.tail].frame
+        {
+            return None;
+        }
 
+        let offset = requested_frame as usize % self.capacity();
         if self.inputs[offset].frame == requested_frame {
-            return self.inputs[offset];
+            return Some(self.inputs[offset]);
         }
-
-        // the requested confirmed input should not be before a prediction. We should not have asked for a known incorrect frame.
-        panic!("SyncLayer::confirmed_input(): There is no confirmed input for the requested frame");
+        None
     }
 
     /// Discards confirmed frames up to given `frame` from the queue. All confirmed frames are guaranteed to be synchronized between players, so there is no need to save the inputs anymore.
@@ -95,11 +183,16 @@ impl<T: Config> InputQueue<T> {
             // we don't need to delete anything
         } else {
             let offset = (frame - (self.inputs[self.tail].frame)) as usize;
-            self.tail = (self.tail + offset) % INPUT_QUEUE_LENGTH;
+            self.tail = (self.tail + offset) % self.capacity();
             self.length -= offset;
         }
     }
 
+    /// Returns the most recently added input, or `None` if no input has been added yet.
+    pub(crate) fn last_confirmed_input(&self) -> Option<PlayerInput<T::Input>> {
+        self.confirmed_input(self.last_added_frame)
+    }
+
     /// Returns the game input of a single player for a given frame, if that input does not exist, we return a prediction instead.
     pub(crate) fn input(&mut self, requested_frame: Frame) -> (T::Input, InputStatus) {
         // No one should ever try to grab any input when we have a prediction error.
@@ -118,7 +211,7 @@ impl<T: Config> InputQueue<T> {
             let mut offset: usize = (requested_frame - self.inputs[self.tail].frame) as usize;
 
             if offset < self.length {
-                offset = (offset + self.tail) % INPUT_QUEUE_LENGTH;
+                offset = (offset + self.tail) % self.capacity();
                 assert!(self.inputs[offset].frame == requested_frame);
                 return (self.inputs[offset].input, InputStatus::Confirmed);
             }
@@ -130,7 +223,7 @@ impl<T: Config> InputQueue<T> {
             } else {
                 // basing new prediction frame from previously added frame
                 let previous_position = match self.head {
-                    0 => INPUT_QUEUE_LENGTH - 1,
+                    0 => self.capacity() - 1,
                     _ => self.head - 1,
                 };
                 self.prediction = self.inputs[previous_position];
@@ -148,12 +241,12 @@ impl<T: Config> InputQueue<T> {
     /// Adds an input frame to the queue. Will consider the set frame delay.
     pub(crate) fn add_input(&mut self, input: PlayerInput<T::Input>) -> Frame {
         // Verify that inputs are passed in sequentially by the user, regardless of frame delay.
-        if self.last_added_frame != NULL_FRAME
-            && input.frame + self.frame_delay as i32 != self.last_added_frame + 1
+        if self.last_user_added_frame != NULL_FRAME && input.frame != self.last_user_added_frame + 1
         {
             // drop the input if not given sequentially
             return NULL_FRAME;
         }
+        self.last_user_added_frame = input.frame;
 
         // Move the queue head to the correct point in preparation to input the frame into the queue.
         let new_frame = self.advance_queue_head(input.frame);
@@ -168,7 +261,7 @@ impl<T: Config> InputQueue<T> {
     /// Returns the frame number
     fn add_input_by_frame(&mut self, input: PlayerInput<T::Input>, frame_number: Frame) {
         let previous_position = match self.head {
-            0 => INPUT_QUEUE_LENGTH - 1,
+            0 => self.capacity() - 1,
             _ => self.head - 1,
         };
 
@@ -178,19 +271,32 @@ impl<T: Config> InputQueue<T> {
         // Add the frame to the back of the queue
         self.inputs[self.head] = input;
         self.inputs[self.head].frame = frame_number;
-        self.head = (self.head + 1) % INPUT_QUEUE_LENGTH;
+        self.head = (self.head + 1) % self.capacity();
         self.length += 1;
-        assert!(self.length <= INPUT_QUEUE_LENGTH);
+        assert!(self.length <= self.capacity());
         self.first_frame = false;
         self.last_added_frame = frame_number;
 
+        // Without a prediction, record whether repeating the previous input would have been right. Otherwise, a session that
+        // stopped predicting because of a high misprediction rate would never notice the inputs becoming predictable again.
+        if self.prediction.frame == NULL_FRAME && frame_number > 0 {
+            let would_mispredict = !self.inputs[previous_position].equal(&input, true);
+            self.record_prediction(would_mispredict);
+        }
+
         // We have been predicting. See if the inputs we've gotten match what we've been predicting. If so, don't worry about it.
         if self.prediction.frame != NULL_FRAME {
             assert!(frame_number == self.prediction.frame);
 
-            // Remember the first input which was incorrect so we can report it
-            if self.first_incorrect_frame == NULL_FRAME && !self.prediction.equal(&input, true) {
-                self.first_incorrect_frame = frame_number;
+            // Remember the first input which was incorrect so we can report it. Predictions after that are redone anyway.
+            if self.first_incorrect_frame == NULL_FRAME {
+                let mispredicted = !self.prediction.equal(&input, true);
+                if frame_number <= self.last_requested_frame {
+                    self.record_prediction(mispredicted);
+                }
+                if mispredicted {
+                    self.first_incorrect_frame = frame_number;
+                }
             }
 
             // If this input is the same frame as the last one requested and we still haven't found any mispredicted inputs, we can exit prediction mode.
@@ -205,10 +311,16 @@ impl<T: Config> InputQueue<T> {
         }
     }
 
+    fn record_prediction(&mut self, mispredicted: bool) {
+        self.prediction_history = (self.prediction_history << 1) | mispredicted as u64;
+        self.predictions_checked =
+            cmp::min(self.predictions_checked + 1, PREDICTION_HISTORY_LENGTH);
+    }
+
     /// Advances the queue head to the next frame and either drops inputs or fills the queue if the input delay has changed since the last frame.
     fn advance_queue_head(&mut self, mut input_frame: Frame) -> Frame {
         let previous_position = match self.head {
-            0 => INPUT_QUEUE_LENGTH - 1,
+            0 => self.capacity() - 1,
             _ => self.head - 1,
         };
 
@@ -233,7 +345,7 @@ impl<T: Config> InputQueue<T> {
         }
 
         let previous_position = match self.head {
-            0 => INPUT_QUEUE_LENGTH - 1,
+            0 => self.capacity() - 1,
             _ => self.head - 1,
         };
         assert!(input_frame == 0 || input_frame == self.inputs[previous_position].frame + 1);
@@ -324,4 +436,145 @@ mod input_queue_tests {
             assert_eq!(input_in_queue.inp, correct_input);
         }
     }
+
+    #[test]
+    fn test_delay_changed_while_adding() {
+        let mut queue = InputQueue::<TestConfig>::new();
+        queue.set_frame_delay(2);
+        for i in 0..4 {
+            assert_eq!(
+                queue.add_input(PlayerInput::new(i, TestInput { inp: i as u8 })),
+                i + 2
+            );
+        }
+
+        // an increased delay repeats the last input for the skipped frames
+        queue.set_frame_delay(4);
+        assert_eq!(
+            queue.add_input(PlayerInput::new(4, TestInput { inp: 4 })),
+            8
+        );
+        for frame in 6..8 {
+            assert!(queue.confirmed_input(frame).unwrap().input == TestInput { inp: 3 });
+        }
+        assert!(queue.confirmed_input(8).unwrap().input == TestInput { inp: 4 });
+
+        // a decreased delay drops inputs until the delayed frames catch up
+        queue.set_frame_delay(1);
+        for i in 5..8 {
+            assert_eq!(
+                queue.add_input(PlayerInput::new(i, TestInput { inp: i as u8 })),
+                NULL_FRAME
+            );
+        }
+        assert_eq!(
+            queue.add_input(PlayerInput::new(8, TestInput { inp: 8 })),
+            9
+        );
+
+        // inputs still have to be given sequentially
+        assert_eq!(
+            queue.add_input(PlayerInput::new(10, TestInput { inp: 10 })),
+            NULL_FRAME
+        );
+    }
+
+    #[test]
+    fn test_has_room_for() {
+        let mut queue = InputQueue::<TestConfig>::new();
+        assert!(queue.has_room_for(INPUT_QUEUE_LENGTH as Frame - 1));
+        assert!(!queue.has_room_for(INPUT_QUEUE_LENGTH as Frame));
+
+        for i in 0..INPUT_QUEUE_LENGTH as Frame {
+            assert!(queue.has_room_for(i));
+            queue.add_input(PlayerInput::new(i, TestInput { inp: 0 }));
+        }
+        assert!(!queue.has_room_for(INPUT_QUEUE_LENGTH as Frame));
+
+        queue.discard_confirmed_frames(1);
+        assert!(queue.has_room_for(INPUT_QUEUE_LENGTH as Frame));
+    }
+
+    #[test]
+    fn test_misprediction_rate() {
+        let mut queue = InputQueue::<TestConfig>::new();
+        assert_eq!(queue.misprediction_rate(), 0.0);
+        queue.add_input(PlayerInput::new(0, TestInput { inp: 0 }));
+
+        // the prediction repeats the last input, which turns out wrong
+        let (input, status) = queue.input(1);
+        assert!(input == TestInput { inp: 0 });
+        assert_eq!(status, InputStatus::Predicted);
+        queue.add_input(PlayerInput::new(1, TestInput { inp: 1 }));
+        assert_eq!(queue.first_incorrect_frame(), 1);
+        assert_eq!(queue.misprediction_rate(), 1.0);
+        queue.reset_prediction();
+
+        // this time, the prediction is correct
+        let (input, status) = queue.input(2);
+        assert!(input == TestInput { inp: 1 });
+        assert_eq!(status, InputStatus::Predicted);
+        queue.add_input(PlayerInput::new(2, TestInput { inp: 1 }));
+        assert_eq!(queue.first_incorrect_frame(), NULL_FRAME);
+        assert_eq!(queue.misprediction_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_misprediction_rate_without_predictions() {
+        let mut queue = InputQueue::<TestConfig>::new();
+        queue.add_input(PlayerInput::new(0, TestInput { inp: 0 }));
+        assert_eq!(queue.misprediction_rate(), 0.0);
+
+        // inputs that arrive in time are checked against the prediction they would have gotten
+        queue.add_input(PlayerInput::new(1, TestInput { inp: 1 }));
+        assert_eq!(queue.misprediction_rate(), 1.0);
+        queue.add_input(PlayerInput::new(2, TestInput { inp: 1 }));
+        assert_eq!(queue.misprediction_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_wraparound_matches_reference() {
+        use std::collections::VecDeque;
+
+        let mut queue = InputQueue::<TestConfig>::new();
+        // reference of all frames that should still be in the queue, oldest first
+        let mut reference = VecDeque::new();
+
+        for frame in 0..(3 * INPUT_QUEUE_LENGTH as Frame) {
+            let input = TestInput {
+                inp: (frame * 7 % 256) as u8,
+            };
+            assert_eq!(queue.add_input(PlayerInput::new(frame, input)), frame);
+            reference.push_back((frame, input));
+
+            // keep a varying amount of frames around, up to most of the queue
+            let retained = 1 + (frame * 13) % (INPUT_QUEUE_LENGTH as Frame - 8);
+            let discard_frame = frame - retained;
+            queue.discard_confirmed_frames(discard_frame);
+            while reference.front().is_some_and(|&(f, _)| f < discard_frame) {
+                let (f, _) = reference.pop_front().unwrap();
+                assert!(queue.confirmed_input(f).is_none());
+            }
+
+            // the oldest element in the queue is the oldest element of the reference
+            assert_eq!(queue.length, reference.len());
+            assert_eq!(queue.inputs[queue.tail].frame, reference[0].0);
+            assert_eq!(
+                queue.inputs[(queue.head + INPUT_QUEUE_LENGTH - 1) % INPUT_QUEUE_LENGTH].frame,
+                frame
+            );
+
+            // every element matches the reference
+            for &(f, expected) in reference.iter() {
+                let confirmed = queue
+                    .confirmed_input(f)
+                    .expect("input should be in the queue");
+                assert_eq!(confirmed.frame, f);
+                assert!(confirmed.input == expected);
+                let (input, status) = queue.input(f);
+                assert!(input == expected);
+                assert_eq!(status, InputStatus::Confirmed);
+            }
+        }
+    }
 }
@@ -7,19 +7,26 @@
 #![deny(missing_docs)]
 #![deny(rustdoc::broken_intra_doc_links)]
 //#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
-use std::{fmt::Debug, hash::Hash};
+use std::{fmt::Debug, hash::Hash, ops::BitOr};
 
+pub use checksum::{canonical_checksum, diff_states, StateDiff};
+pub use clock::{Clock, MockClock, SystemClock};
 pub use error::GgrsError;
+pub use network::bit_packing::{read_bits, write_bits};
 pub use network::messages::Message;
+pub use network::network_sim::NetworkSim;
 pub use network::network_stats::NetworkStats;
+pub use network::packet_log::{PacketDirection, PacketEvent, PacketKind};
 pub use network::udp_socket::UdpNonBlockingSocket;
 use serde::{de::DeserializeOwned, Serialize};
 pub use sessions::builder::SessionBuilder;
-pub use sessions::p2p_session::P2PSession;
+pub use sessions::p2p_session::{AdvanceDecision, ConfirmationInfo, FrameEvent, P2PSession};
 pub use sessions::p2p_spectator_session::SpectatorSession;
 pub use sessions::sync_test_session::SyncTestSession;
-pub use sync_layer::{GameStateAccessor, GameStateCell};
+pub use sync_layer::{estimate_memory, GameStateAccessor, GameStateCell};
 
+pub(crate) mod checksum;
+pub(crate) mod clock;
 pub(crate) mod error;
 pub(crate) mod frame_info;
 pub(crate) mod input_queue;
@@ -32,9 +39,12 @@ pub(crate) mod sessions {
     pub(crate) mod sync_test_session;
 }
 pub(crate) mod network {
+    pub(crate) mod bit_packing;
     pub(crate) mod compression;
     pub(crate) mod messages;
+    pub(crate) mod network_sim;
     pub(crate) mod network_stats;
+    pub(crate) mod packet_log;
     pub(crate) mod protocol;
     pub(crate) mod udp_socket;
 }
@@ -45,7 +55,8 @@ pub(crate) mod network {
 
 /// Internally, -1 represents no frame / invalid frame.
 pub const NULL_FRAME: i32 = -1;
-/// A frame is a single step of execution.
+/// A frame is a single step of execution. Sessions stop advancing at frame 2^30 - 1, which is about 200 days at 60 fps,
+/// and return [`GgrsError::InvalidRequest`] from `advance_frame()` instead of overflowing the frame counter.
 pub type Frame = i32;
 /// Each player is identified by a player handle.
 pub type PlayerHandle = usize;
@@ -54,7 +65,10 @@ pub type PlayerHandle = usize;
 // #   ENUMS   #
 // #############
 
-/// Desync detection by comparing checksums between peers.
+/// Desync detection by comparing checksums between peers. States saved with [`GameStateCell::save_with_gameplay_checksum()`]
+/// are compared by their gameplay checksum only.
+///
+/// [`GameStateCell::save_with_gameplay_checksum()`]: crate::GameStateCell#method.save_with_gameplay_checksum
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DesyncDetection {
     /// Desync detection is turned on with a specified interval rate given by the user.
@@ -66,17 +80,31 @@ pub enum DesyncDetection {
     Off,
 }
 
+/// What a [`P2PSession`] does when `advance_frame()` is called without input for one of the local players.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum MissingLocalInput {
+    /// `advance_frame()` returns [`GgrsError::InvalidRequest`]. This is the default.
+    #[default]
+    Error,
+    /// The default input is used for the player.
+    Zero,
+    /// The last input given for the player is repeated, or the default input if there is none yet.
+    RepeatLast,
+}
+
 /// Defines the three types of players that GGRS considers:
 /// - local players, who play on the local device,
 /// - remote players, who play on other devices and
 /// - spectators, who are remote players that do not contribute to the game input.
+///
 /// Both [`PlayerType::Remote`] and [`PlayerType::Spectator`] have a socket address associated with them.
-#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Default)]
 pub enum PlayerType<A>
 where
     A: Clone + PartialEq + Eq + Hash,
 {
     /// This player plays on the local device.
+    #[default]
     Local,
     /// This player plays on a remote device identified by the socket address.
     Remote(A),
@@ -84,12 +112,6 @@ where
     Spectator(A),
 }
 
-impl<A: Clone + PartialEq + Eq + Hash> Default for PlayerType<A> {
-    fn default() -> Self {
-        Self::Local
-    }
-}
-
 /// A session is always in one of these states. You can query the current state of a session via [`current_state`].
 ///
 /// [`current_state`]: P2PSession#method.current_state
@@ -99,6 +121,11 @@ pub enum SessionState {
     Synchronizing,
     /// When running, the session has synchronized and is ready to take and transmit player input.
     Running,
+    /// The synchronization with a remote client ran out of retries or time, see [`SessionBuilder::with_sync_timeout()`].
+    /// This state is terminal, the session has to be recreated to try again.
+    ///
+    /// [`SessionBuilder::with_sync_timeout()`]: crate::SessionBuilder::with_sync_timeout
+    Failed,
 }
 
 /// [`InputStatus`] will always be given together with player inputs when requested to advance the frame.
@@ -106,12 +133,52 @@ pub enum SessionState {
 pub enum InputStatus {
     /// The input of this player for this frame is an actual received input.
     Confirmed,
-    /// The input of this player for this frame is predicted.
+    /// The input of this player for this frame is predicted. GGRS predicts that a player repeats their last input. As long as no input
+    /// of that player is known, e.g. for the first frames of a session, the prediction is the [`Default`] value of your input type.
+    /// Remote inputs are never required to advance a frame, not even frame 0: if a prediction turns out wrong, GGRS rolls back to
+    /// the first mispredicted frame, and the state of frame 0 is always saved for that purpose.
     Predicted,
     /// The player has disconnected at or prior to this frame, so this input is a dummy.
     Disconnected,
 }
 
+/// The reason why a remote client has been disconnected, given with [`GgrsEvent::Disconnected`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The disconnect was requested locally, e.g. through [`P2PSession::disconnect_player()`].
+    Requested,
+    /// No packets have been received from the remote client within the disconnect timeout.
+    Timeout,
+    /// The remote client announced that it is shutting down the connection.
+    Shutdown,
+    /// The remote client did not follow the protocol, e.g. a spectator that stopped acknowledging inputs.
+    ProtocolError,
+}
+
+/// The reason why a remote client is incompatible, given with [`GgrsEvent::IncompatiblePeer`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IncompatibilityReason {
+    /// The remote client uses a different version of the GGRS network protocol.
+    ProtocolVersion,
+    /// The remote client uses a different protocol id, see [`SessionBuilder::with_protocol_id()`].
+    ProtocolId,
+    /// A single input of the remote client has a different size on the wire, usually because it has been built with a different
+    /// input type or [`Config::INPUT_BITS`].
+    InputSize {
+        /// The size of a local input in bits.
+        local: usize,
+        /// The size of a remote input in bits.
+        remote: usize,
+    },
+    /// The remote client has been set up for a different number of players.
+    NumPlayers {
+        /// The number of players of the local session.
+        local: usize,
+        /// The number of players of the remote session.
+        remote: usize,
+    },
+}
+
 /// Notifications that you can receive from the session. Handling them is up to the user.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GgrsEvent<T>
@@ -136,6 +203,8 @@ where
     Disconnected {
         /// The address of the endpoint.
         addr: T::Address,
+        /// Why the remote client has been disconnected.
+        reason: DisconnectReason,
     },
     /// The session has not received packets from the remote client for some time and will disconnect the remote in `disconnect_timeout` ms.
     NetworkInterrupted {
@@ -149,12 +218,61 @@ where
         /// The address of the endpoint.
         addr: T::Address,
     },
+    /// The remote client is incompatible, e.g. because it uses a different protocol id or input type, so the session will not
+    /// synchronize with it. See [`SessionBuilder::with_protocol_id()`].
+    IncompatiblePeer {
+        /// The address of the endpoint.
+        addr: T::Address,
+        /// Why the remote client is incompatible.
+        reason: IncompatibilityReason,
+    },
+    /// The synchronization with the remote client ran out of retries or time. The session is now in the terminal [`SessionState::Failed`].
+    SynchronizationFailed {
+        /// The address of the endpoint.
+        addr: T::Address,
+    },
     /// Sent out if GGRS recommends skipping a few frames to let clients catch up. If you receive this, consider waiting `skip_frames` number of frames.
     WaitRecommendation {
         /// Amount of frames recommended to be skipped in order to let other clients catch up.
         skip_frames: u32,
     },
-    /// Sent whenever GGRS locally detected a discrepancy between local and remote checksums
+    /// Sent whenever the session rolled back because of mispredicted inputs. The frames from `from_frame` up to (but excluding)
+    /// `to_frame` have been resimulated, so anything derived from them (e.g. rendering or audio) might be outdated.
+    /// Only sent if enabled with [`SessionBuilder::with_rollback_events()`].
+    ///
+    /// [`SessionBuilder::with_rollback_events()`]: crate::SessionBuilder#method.with_rollback_events
+    Rollback {
+        /// The first frame that has been resimulated, i.e. the frame that was loaded.
+        from_frame: Frame,
+        /// The frame the session has returned to after resimulating.
+        to_frame: Frame,
+    },
+    /// Sent when the input queue of a remote player is full, e.g. because the remote runs far ahead with a large input delay
+    /// while confirmed inputs are kept around for spectators. GGRS never drops confirmed inputs: the inputs from `frame` on
+    /// are held back and added to the queue as soon as older inputs have been discarded while advancing.
+    InputQueueOverflow {
+        /// The player whose input queue is full.
+        player: PlayerHandle,
+        /// The first frame whose input has been held back.
+        frame: Frame,
+    },
+    /// Sent once if the remote client repeatedly sent inputs that have been rejected, which a well-behaved client never does.
+    /// A [`P2PSession`] only accepts inputs of a remote player up to the current frame plus the length of the player's input
+    /// queue (see [`SessionBuilder::with_input_queue_length()`]), so a misbehaving client can't make it hold back an unbounded
+    /// number of inputs. Rejected inputs are not acknowledged, so they will be resent. Inputs that don't follow the previous ones
+    /// or can't be decoded are rejected as well. Consider disconnecting the player.
+    ///
+    /// [`SessionBuilder::with_input_queue_length()`]: crate::SessionBuilder#method.with_input_queue_length
+    SuspiciousPeer {
+        /// The address of the endpoint.
+        addr: T::Address,
+        /// The number of inputs rejected so far.
+        rejected_inputs: usize,
+    },
+    /// Sent whenever GGRS locally detected a discrepancy between local and remote checksums. Besides the checksums of the
+    /// gamestate, the checksums of the confirmed inputs that led to it are compared. If the input checksums match but the
+    /// gamestate checksums don't, the simulation diverged. If the input checksums differ, the peers disagree on the inputs
+    /// themselves, e.g. because an input does not survive serialization unchanged.
     DesyncDetected {
         /// Frame of the checksums
         frame: Frame,
@@ -162,6 +280,10 @@ where
         local_checksum: u128,
         /// remote checksum for the given frame
         remote_checksum: u128,
+        /// local checksum of the confirmed inputs of all players for the frame before the given frame
+        local_input_checksum: u128,
+        /// remote checksum of the confirmed inputs of all players for the frame before the given frame
+        remote_input_checksum: u128,
         /// remote address of the endpoint.
         addr: T::Address,
     },
@@ -180,6 +302,8 @@ where
         frame: Frame,
     },
     /// You should load the gamestate in the `cell` provided to you. The given `frame` is a sanity check: The gamestate you load should be from that frame.
+    /// GGRS never interprets your gamestate, so if restoring it can fail (e.g. because you store serialized bytes that turn out to be corrupt),
+    /// handle the failure in your own request handling code and propagate it as an error instead of panicking.
     LoadGameState {
         /// Use `cell.load()` to load your state.
         cell: GameStateCell<T::State>,
@@ -189,11 +313,61 @@ where
     /// You should advance the gamestate with the `inputs` provided to you.
     /// Disconnected players are indicated by having [`NULL_FRAME`] instead of the correct current frame in their input.
     AdvanceFrame {
-        /// Contains inputs and input status for each player.
+        /// Contains inputs and input status for each player, indexed by player handle. The order never depends on the order in which
+        /// inputs were added or received over the network, so you can apply them in this order on every machine.
         inputs: Vec<(T::Input, InputStatus)>,
     },
 }
 
+// #############
+// #  STRUCTS  #
+// #############
+
+/// A set of operations a session supports, as returned by `capabilities()` of every session type.
+/// Generic code, e.g. a debug UI, can use this to tell which operations are valid instead of trying them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    /// Local input can be added with `add_local_input()`.
+    pub const LOCAL_INPUT: Self = Self(1 << 0);
+    /// Players can be disconnected with `disconnect_player()`.
+    pub const DISCONNECT_PLAYER: Self = Self(1 << 1);
+    /// Statistics about the network connection are available through `network_stats()`.
+    pub const NETWORK_STATS: Self = Self(1 << 2);
+    /// Local input can be delayed. `frames_until_input_active()` reports the remaining delay.
+    pub const INPUT_DELAY: Self = Self(1 << 3);
+    /// Disconnect timeouts can be suspended with `suspend_timeouts()`.
+    pub const SUSPEND_TIMEOUTS: Self = Self(1 << 4);
+    /// Desyncs are detected by comparing checksums, see [`DesyncDetection`] and [`SyncTestSession`].
+    pub const DESYNC_DETECTION: Self = Self(1 << 5);
+    /// The session can be saved and restored with `save_checkpoint()` and `restore_checkpoint()`, given a serializable state.
+    pub const CHECKPOINTS: Self = Self(1 << 6);
+
+    /// Returns a set without any capabilities.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns true if all capabilities of `other` are contained in this set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the set of capabilities contained in either set.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
 // #############
 // #  TRAITS   #
 // #############
@@ -215,6 +389,12 @@ pub trait Config: 'static + Send + Sync {
 
     /// The address type which identifies the remote clients
     type Address: Clone + PartialEq + Eq + Hash + Send + Sync + Debug;
+
+    /// If set, inputs are transmitted with only this many bits per player, packed contiguously without byte padding.
+    /// For example, 4 players with 6 buttons each need 3 bytes per frame instead of 4. The serialized input, read as a
+    /// little-endian integer, must fit into the given number of bits, which must be between 1 and 64. All clients of a
+    /// session must use the same value. Per default, inputs are transmitted byte-wise.
+    const INPUT_BITS: Option<usize> = None;
 }
 
 /// This [`NonBlockingSocket`] trait is used when you want to use GGRS with your own socket.
@@ -232,6 +412,15 @@ where
     /// This method should return all messages received since the last time this method was called.
     /// The pairs `(A, Message)` indicate from which address each packet was received.
     fn receive_all_messages(&mut self) -> Vec<(A, Message)>;
+
+    /// Returns the local address this socket is bound to, e.g. to learn which port the OS assigned when binding to port 0.
+    /// The default implementation returns an error of kind [`std::io::ErrorKind::Unsupported`].
+    fn local_addr(&self) -> std::io::Result<A> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "the socket does not provide its local address",
+        ))
+    }
 }
 
 /// Compile time parameterization for sessions.
@@ -249,6 +438,12 @@ pub trait Config: 'static {
 
     /// The address type which identifies the remote clients
     type Address: Clone + PartialEq + Eq + Hash + Debug;
+
+    /// If set, inputs are transmitted with only this many bits per player, packed contiguously without byte padding.
+    /// For example, 4 players with 6 buttons each need 3 bytes per frame instead of 4. The serialized input, read as a
+    /// little-endian integer, must fit into the given number of bits, which must be between 1 and 64. All clients of a
+    /// session must use the same value. Per default, inputs are transmitted byte-wise.
+    const INPUT_BITS: Option<usize> = None;
 }
 
 /// This [`NonBlockingSocket`] trait is used when you want to use GGRS with your own socket.
@@ -266,4 +461,13 @@ where
     /// This method should return all messages received since the last time this method was called.
     /// The pairs `(A, Message)` indicate from which address each packet was received.
     fn receive_all_messages(&mut self) -> Vec<(A, Message)>;
+
+    /// Returns the local address this socket is bound to, e.g. to learn which port the OS assigned when binding to port 0.
+    /// The default implementation returns an error of kind [`std::io::ErrorKind::Unsupported`].
+    fn local_addr(&self) -> std::io::Result<A> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "the socket does not provide its local address",
+        ))
+    }
 }
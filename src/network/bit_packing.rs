@@ -0,0 +1,165 @@
+//! Bit-level accessors used to transmit inputs without byte padding, see [`Config::INPUT_BITS`].
+//!
+//! [`Config::INPUT_BITS`]: crate::Config::INPUT_BITS
+
+use crate::{Config, GgrsError};
+
+/// Writes the lowest `bits` bits of `value` into `buf`, starting at bit `offset`. Bits are numbered from the least significant
+/// bit of the first byte on, so bit 8 is the least significant bit of the second byte.
+///
+/// # Panics
+/// Panics if `bits` is larger than 64 or the bits don't fit into `buf`.
+pub fn write_bits(buf: &mut [u8], offset: usize, bits: usize, value: u64) {
+    assert!(bits <= 64, "cannot write more than 64 bits at once");
+    assert!(offset + bits <= buf.len() * 8, "bits exceed the buffer");
+    for i in 0..bits {
+        let pos = offset + i;
+        let mask = 1 << (pos % 8);
+        if (value >> i) & 1 == 1 {
+            buf[pos / 8] |= mask;
+        } else {
+            buf[pos / 8] &= !mask;
+        }
+    }
+}
+
+/// Reads `bits` bits from `buf`, starting at bit `offset`, and returns them as the lowest bits of a `u64`.
+/// Uses the same bit order as [`write_bits()`].
+///
+/// # Panics
+/// Panics if `bits` is larger than 64 or the bits exceed `buf`.
+pub fn read_bits(buf: &[u8], offset: usize, bits: usize) -> u64 {
+    assert!(bits <= 64, "cannot read more than 64 bits at once");
+    assert!(offset + bits <= buf.len() * 8, "bits exceed the buffer");
+    let mut value = 0;
+    for i in 0..bits {
+        let pos = offset + i;
+        if (buf[pos / 8] >> (pos % 8)) & 1 == 1 {
+            value |= 1 << i;
+        }
+    }
+    value
+}
+
+/// Checks that [`Config::INPUT_BITS`] is between 1 and 64 and that the default input can be transmitted with it.
+///
+/// [`Config::INPUT_BITS`]: crate::Config::INPUT_BITS
+pub(crate) fn check_config<T: Config>() -> Result<(), GgrsError> {
+    if let Some(bits) = T::INPUT_BITS {
+        if !(1..=64).contains(&bits) {
+            return Err(GgrsError::InvalidRequest {
+                info: "Config::INPUT_BITS must be between 1 and 64.".to_owned(),
+            });
+        }
+    }
+    check_input::<T>(&T::Input::default())
+}
+
+/// Checks that `input` serializes into 8 bytes or less and fits into [`Config::INPUT_BITS`], if set.
+///
+/// [`Config::INPUT_BITS`]: crate::Config::INPUT_BITS
+pub(crate) fn check_input<T: Config>(input: &T::Input) -> Result<(), GgrsError> {
+    let Some(bits) = T::INPUT_BITS else {
+        return Ok(());
+    };
+    let serialized = bincode::serialize(input).map_err(|e| GgrsError::InvalidRequest {
+        info: format!("Input serialization failed: {}", e),
+    })?;
+    if serialized.len() > 8 {
+        return Err(GgrsError::InvalidRequest {
+            info: format!(
+                "Inputs packed into bits must serialize into 8 bytes or less, but the input takes {} bytes.",
+                serialized.len()
+            ),
+        });
+    }
+    if to_bits(&serialized, bits).is_none() {
+        return Err(GgrsError::InvalidRequest {
+            info: format!(
+                "The serialized input does not fit into Config::INPUT_BITS ({} bits).",
+                bits
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Reads a serialized input as a little-endian integer. Returns `None` if it doesn't fit into `bits` bits.
+fn to_bits(input: &[u8], bits: usize) -> Option<u64> {
+    if input.len() > 8 {
+        return None;
+    }
+    let mut le_bytes = [0; 8];
+    le_bytes[..input.len()].copy_from_slice(input);
+    let value = u64::from_le_bytes(le_bytes);
+    (bits == 64 || value >> bits == 0).then_some(value)
+}
+
+/// Packs the serialized inputs of several players contiguously into `bits` bits each.
+/// The inputs must have been checked with [`check_input()`] before.
+pub(crate) fn pack(inputs: &[Vec<u8>], bits: usize) -> Vec<u8> {
+    let mut buf = vec![0; (inputs.len() * bits).div_ceil(8)];
+    for (i, input) in inputs.iter().enumerate() {
+        let value = to_bits(input, bits).expect("packed inputs are checked when they are added");
+        write_bits(&mut buf, i * bits, bits, value);
+    }
+    buf
+}
+
+/// Unpacks `count` inputs of `bits` bits each into serialized inputs of `input_size` bytes.
+pub(crate) fn unpack(buf: &[u8], bits: usize, count: usize, input_size: usize) -> Vec<Vec<u8>> {
+    (0..count)
+        .map(|i| read_bits(buf, i * bits, bits).to_le_bytes()[..input_size].to_vec())
+        .collect()
+}
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod bit_packing_tests {
+    use super::*;
+
+    #[test]
+    fn test_read_write_bits() {
+        let mut buf = [0u8; 3];
+        write_bits(&mut buf, 3, 6, 0b101101);
+        assert_eq!(buf, [0b0110_1000, 0b0000_0001, 0]);
+        assert_eq!(read_bits(&buf, 3, 6), 0b101101);
+
+        // overwriting clears bits that are not set anymore
+        write_bits(&mut buf, 3, 6, 0);
+        assert_eq!(buf, [0; 3]);
+
+        write_bits(&mut buf, 0, 24, 0xabcdef);
+        assert_eq!(read_bits(&buf, 0, 24), 0xabcdef);
+        assert_eq!(read_bits(&buf, 4, 8), 0xde);
+    }
+
+    #[test]
+    fn test_pack_roundtrip() {
+        // four players with six buttons each fit into three bytes instead of four
+        let inputs: Vec<Vec<u8>> = vec![vec![0b111111], vec![0], vec![0b101010], vec![0b000111]];
+        let packed = pack(&inputs, 6);
+        assert_eq!(packed.len(), 3);
+        assert_eq!(unpack(&packed, 6, 4, 1), inputs);
+
+        // inputs serialized into several bytes
+        let inputs: Vec<Vec<u8>> = (0..5u32)
+            .map(|i| (i * 1000).to_le_bytes().to_vec())
+            .collect();
+        let packed = pack(&inputs, 13);
+        assert_eq!(packed.len(), 9);
+        assert_eq!(unpack(&packed, 13, 5, 4), inputs);
+    }
+
+    #[test]
+    fn test_to_bits() {
+        assert_eq!(to_bits(&[0b111111], 6), Some(0b111111));
+        assert_eq!(to_bits(&[0b1000000], 6), None);
+        assert_eq!(to_bits(&[0, 0b1], 9), Some(0b1_0000_0000));
+        assert_eq!(to_bits(&[0xff; 8], 64), Some(u64::MAX));
+        assert_eq!(to_bits(&[0; 9], 64), None);
+    }
+}
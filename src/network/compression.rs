@@ -1,5 +1,8 @@
 // special thanks to james7132
 
+/// Varints of more bytes could overflow while being decoded.
+const MAX_VARINT_BYTES: usize = 9;
+
 pub(crate) fn encode<'a>(
     reference: &[u8],
     pending_input: impl Iterator<Item = &'a Vec<u8>>,
@@ -29,19 +32,74 @@ pub(crate) fn delta_encode<'a>(
     bytes
 }
 
+/// Decodes inputs encoded with [`encode()`]. Fails if `data` is malformed or decodes into more than `max_inputs` inputs.
 pub(crate) fn decode(
     reference: &[u8],
     data: &[u8],
+    max_inputs: usize,
 ) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
-    // decode the RLE encoding first
+    // decode the RLE encoding first. The RLE decoder panics on malformed data, so it is checked beforehand
+    rle_decoded_len(data, reference.len().saturating_mul(max_inputs))?;
     let buf = bitfield_rle::decode(data)?;
 
     // decode the delta-encoding
-    Ok(delta_decode(reference, &buf))
+    delta_decode(reference, &buf)
 }
 
-pub(crate) fn delta_decode(ref_bytes: &[u8], data: &[u8]) -> Vec<Vec<u8>> {
-    assert!(data.len() % ref_bytes.len() == 0);
+/// Returns the length of the RLE-encoded `data` once decoded. Fails if the encoding is truncated or decodes into more than `max_len` bytes.
+fn rle_decoded_len(
+    data: &[u8],
+    max_len: usize,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut offset = 0;
+    let mut len: usize = 0;
+    while offset < data.len() {
+        // every run starts with a varint holding its length and kind, which must not overflow a u64
+        let mut next = 0u64;
+        for i in 0..=MAX_VARINT_BYTES {
+            if i == MAX_VARINT_BYTES {
+                return Err("RLE run length is too large".into());
+            }
+            let Some(&byte) = data.get(offset) else {
+                return Err("RLE run length is truncated".into());
+            };
+            offset += 1;
+            next |= ((byte & 127) as u64) << (7 * i);
+            if byte & 128 == 0 {
+                break;
+            }
+        }
+
+        let repeat = next & 1 == 1;
+        let run_len = if repeat { next >> 2 } else { next >> 1 };
+        len = usize::try_from(run_len)
+            .ok()
+            .and_then(|run_len| len.checked_add(run_len))
+            .filter(|&len| len <= max_len)
+            .ok_or_else(|| format!("RLE data decodes into more than {} bytes", max_len))?;
+        // literal runs are followed by their bytes
+        if !repeat {
+            if run_len as usize > data.len() - offset {
+                return Err("RLE literal run is truncated".into());
+            }
+            offset += run_len as usize;
+        }
+    }
+    Ok(len)
+}
+
+pub(crate) fn delta_decode(
+    ref_bytes: &[u8],
+    data: &[u8],
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    if ref_bytes.is_empty() || !data.len().is_multiple_of(ref_bytes.len()) {
+        return Err(format!(
+            "decoded {} bytes, which is not a multiple of the reference length {}",
+            data.len(),
+            ref_bytes.len()
+        )
+        .into());
+    }
     let out_size = data.len() / ref_bytes.len();
     let mut output = Vec::with_capacity(out_size);
 
@@ -53,7 +111,7 @@ pub(crate) fn delta_decode(ref_bytes: &[u8], data: &[u8]) -> Vec<Vec<u8>> {
         output.push(buffer);
     }
 
-    output
+    Ok(output)
 }
 
 // #########
@@ -76,8 +134,29 @@ mod compression_tests {
         let pend_inp = vec![inp0, inp1, inp2, inp3, inp4];
 
         let encoded = encode(&ref_input, pend_inp.iter());
-        let decoded = decode(&ref_input, &encoded).unwrap();
+        let decoded = decode(&ref_input, &encoded, pend_inp.len()).unwrap();
 
         assert!(pend_inp == decoded);
     }
+
+    #[test]
+    fn test_decode_length_mismatch() {
+        let ref_input = vec![0, 0, 0, 1];
+        assert!(delta_decode(&ref_input, &[0, 1, 0, 1, 0]).is_err());
+        assert!(delta_decode(&[], &[]).is_err());
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        let ref_input = vec![0, 0, 0, 1];
+        let encoded = encode(&ref_input, [vec![0, 0, 1, 0], vec![0, 0, 1, 1]].iter());
+        assert!(decode(&ref_input, &encoded, 1).is_err());
+        assert!(decode(&ref_input, &encoded, 2).is_ok());
+
+        // truncated and overlong run lengths, a truncated literal run and a huge repeated run
+        assert!(decode(&ref_input, &[0xff], 8).is_err());
+        assert!(decode(&ref_input, &[0xff; 10], 8).is_err());
+        assert!(decode(&ref_input, &[0b1000], 8).is_err());
+        assert!(decode(&ref_input, &[0xff, 0xff, 0xff, 0x7f], 8).is_err());
+    }
 }
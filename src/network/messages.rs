@@ -8,6 +8,13 @@ pub(crate) struct ConnectionStatus {
     pub last_frame: Frame,
 }
 
+impl ConnectionStatus {
+    /// Returns true if the player is disconnected at the given frame, i.e. there is no input for that frame.
+    pub(crate) fn disconnected_at(&self, frame: Frame) -> bool {
+        self.disconnected && self.last_frame < frame
+    }
+}
+
 impl Default for ConnectionStatus {
     fn default() -> Self {
         Self {
@@ -20,11 +27,23 @@ impl Default for ConnectionStatus {
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct SyncRequest {
     pub random_request: u32, // please reply back with this random data
+    pub protocol_version: u16,
+    pub protocol_id: u64,
+    /// The size of a single input on the wire, in bits.
+    pub input_bits: u32,
+    /// The number of players in the session, not counting spectators.
+    pub num_players: u16,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct SyncReply {
     pub random_reply: u32, // here's your random data back
+    pub protocol_version: u16,
+    pub protocol_id: u64,
+    /// The size of a single input on the wire, in bits.
+    pub input_bits: u32,
+    /// The number of players in the session, not counting spectators.
+    pub num_players: u16,
 }
 
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -111,11 +130,15 @@ pub(crate) struct QualityReply {
 pub(crate) struct ChecksumReport {
     pub checksum: u128,
     pub frame: Frame,
+    /// Checksum of the confirmed inputs of all players that led to the state of `frame`
+    pub input_checksum: u128,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub(crate) struct MessageHeader {
     pub magic: u16,
+    /// Increases with every message sent to a peer, wrapping around.
+    pub sequence: u16,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -133,9 +156,46 @@ pub(crate) enum MessageBody {
 /// A messages that [`NonBlockingSocket`] sends and receives. When implementing [`NonBlockingSocket`],
 /// you should deserialize received messages into this `Message` type and pass them.
 ///
+/// On the wire, all fields of a message are encoded in little-endian byte order, independent of the platform.
+/// [`UdpNonBlockingSocket`] uses the default configuration of `bincode` 1.x, which guarantees this.
+/// If you serialize messages yourself, use an encoding with a fixed byte order as well, so peers on platforms
+/// with different endianness can talk to each other.
+///
+/// [`UdpNonBlockingSocket`]: crate::UdpNonBlockingSocket
 /// [`NonBlockingSocket`]: crate::NonBlockingSocket
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Message {
     pub(crate) header: MessageHeader,
     pub(crate) body: MessageBody,
 }
+
+#[cfg(test)]
+mod messages_tests {
+    use super::*;
+
+    #[test]
+    fn test_header_is_little_endian() {
+        let msg = Message {
+            header: MessageHeader {
+                magic: 0x1234,
+                sequence: 0x5678,
+            },
+            body: MessageBody::ChecksumReport(ChecksumReport {
+                checksum: 1,
+                frame: 0x0102_0304,
+                input_checksum: 2,
+            }),
+        };
+        let bytes = bincode::serialize(&msg).unwrap();
+
+        // header, then the variant index of the body as a u32
+        assert_eq!(bytes[0..8], [0x34, 0x12, 0x78, 0x56, 6, 0, 0, 0]);
+        let mut checksum = [0; 16];
+        checksum[0] = 1;
+        assert_eq!(bytes[8..24], checksum);
+        assert_eq!(bytes[24..28], [0x04, 0x03, 0x02, 0x01]);
+
+        let decoded: Message = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, msg);
+    }
+}
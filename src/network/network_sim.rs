@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use instant::{Duration, Instant};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::network::messages::Message;
+use crate::{Clock, Config, GgrsError, NonBlockingSocket};
+
+/// Parameters of the bad network a [`P2PSession`] simulates for its outgoing packets, set with
+/// [`P2PSession::set_network_simulation()`]. This is meant for testing how your game feels under bad network conditions
+/// and must not be used in production. The default simulates a perfect network.
+///
+/// [`P2PSession`]: crate::P2PSession
+/// [`P2PSession::set_network_simulation()`]: crate::P2PSession#method.set_network_simulation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NetworkSim {
+    /// The time every outgoing packet is held back, in milliseconds.
+    pub latency_ms: u64,
+    /// The maximum additional time a packet is held back, in milliseconds. Each packet is delayed by a random amount up to
+    /// this value, so packets can arrive in a different order than they were sent.
+    pub jitter_ms: u64,
+    /// The share of outgoing packets that is dropped, in percent between 0 and 100.
+    pub loss_pct: u8,
+    /// The seed for the random loss and jitter, so a simulation can be reproduced.
+    pub seed: u64,
+}
+
+/// A socket that sends packets through a [`NetworkSim`] before handing them to the actual socket.
+/// Without a simulation, it passes all packets through directly.
+pub(crate) struct SimulatedSocket<T: Config> {
+    inner: Box<dyn NonBlockingSocket<T::Address>>,
+    clock: Arc<dyn Clock>,
+    sim: NetworkSim,
+    rng: StdRng,
+    /// Packets held back until the given point in time, in the order they were sent
+    delayed: Vec<(Instant, T::Address, Message)>,
+    /// The number of packets dropped by the simulation, per receiver
+    dropped: HashMap<T::Address, usize>,
+}
+
+impl<T: Config> SimulatedSocket<T> {
+    pub(crate) fn new(
+        inner: Box<dyn NonBlockingSocket<T::Address>>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let sim = NetworkSim::default();
+        Self {
+            inner,
+            clock,
+            sim,
+            rng: StdRng::seed_from_u64(sim.seed),
+            delayed: Vec::new(),
+            dropped: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn set_simulation(&mut self, sim: NetworkSim) -> Result<(), GgrsError> {
+        if sim.loss_pct > 100 {
+            return Err(GgrsError::InvalidRequest {
+                info: "The simulated packet loss must be between 0 and 100 percent.".to_owned(),
+            });
+        }
+        self.sim = sim;
+        self.rng = StdRng::seed_from_u64(sim.seed);
+        Ok(())
+    }
+
+    /// Returns the number of packets to the given address dropped by the simulation.
+    pub(crate) fn dropped_packets(&self, addr: &T::Address) -> usize {
+        self.dropped.get(addr).copied().unwrap_or(0)
+    }
+
+    /// Sends all held back packets that are due, the earliest first.
+    fn send_due_packets(&mut self) {
+        if self.delayed.is_empty() {
+            return;
+        }
+        let now = self.clock.now();
+        // a stable sort keeps packets that are due at the same time in the order they were sent
+        self.delayed.sort_by_key(|(due, _, _)| *due);
+        let num_due = self.delayed.partition_point(|(due, _, _)| *due <= now);
+        for (_, addr, msg) in self.delayed.drain(..num_due) {
+            self.inner.send_to(&msg, &addr);
+        }
+    }
+}
+
+impl<T: Config> NonBlockingSocket<T::Address> for SimulatedSocket<T> {
+    fn send_to(&mut self, msg: &Message, addr: &T::Address) {
+        if self.sim.loss_pct > 0 && self.rng.gen_range(0..100) < self.sim.loss_pct {
+            *self.dropped.entry(addr.clone()).or_insert(0) += 1;
+            return;
+        }
+        let mut delay = self.sim.latency_ms;
+        if self.sim.jitter_ms > 0 {
+            delay += self.rng.gen_range(0..=self.sim.jitter_ms);
+        }
+        if delay == 0 {
+            self.inner.send_to(msg, addr);
+        } else {
+            let due = self.clock.now() + Duration::from_millis(delay);
+            self.delayed.push((due, addr.clone(), msg.clone()));
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(T::Address, Message)> {
+        self.send_due_packets();
+        self.inner.receive_all_messages()
+    }
+
+    fn local_addr(&self) -> std::io::Result<T::Address> {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod network_sim_tests {
+    use std::sync::Mutex;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::network::messages::{MessageBody, MessageHeader};
+    use crate::MockClock;
+
+    #[derive(Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+    struct TestInput {
+        inp: u8,
+    }
+
+    struct TestConfig;
+
+    impl Config for TestConfig {
+        type Input = TestInput;
+        type State = u8;
+        type Address = u32;
+    }
+
+    /// Records the sequence numbers of all messages handed to it
+    struct RecordingSocket {
+        sent: Arc<Mutex<Vec<u16>>>,
+    }
+
+    impl NonBlockingSocket<u32> for RecordingSocket {
+        fn send_to(&mut self, msg: &Message, _addr: &u32) {
+            self.sent.lock().unwrap().push(msg.header.sequence);
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(u32, Message)> {
+            Vec::new()
+        }
+    }
+
+    fn message(sequence: u16) -> Message {
+        Message {
+            header: MessageHeader { magic: 0, sequence },
+            body: MessageBody::KeepAlive,
+        }
+    }
+
+    fn simulated_socket(clock: &MockClock) -> (SimulatedSocket<TestConfig>, Arc<Mutex<Vec<u16>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let inner = RecordingSocket { sent: sent.clone() };
+        (
+            SimulatedSocket::new(Box::new(inner), Arc::new(clock.clone())),
+            sent,
+        )
+    }
+
+    #[test]
+    fn test_passes_through_without_simulation() {
+        let clock = MockClock::new();
+        let (mut socket, sent) = simulated_socket(&clock);
+        for i in 0..10 {
+            socket.send_to(&message(i), &1);
+        }
+        assert_eq!(*sent.lock().unwrap(), (0..10).collect::<Vec<_>>());
+        assert_eq!(socket.dropped_packets(&1), 0);
+    }
+
+    #[test]
+    fn test_loss_is_reproducible() {
+        let sim = NetworkSim {
+            loss_pct: 50,
+            seed: 42,
+            ..Default::default()
+        };
+        let clock = MockClock::new();
+        let (mut socket1, sent1) = simulated_socket(&clock);
+        let (mut socket2, sent2) = simulated_socket(&clock);
+        socket1.set_simulation(sim).unwrap();
+        socket2.set_simulation(sim).unwrap();
+        for i in 0..100 {
+            socket1.send_to(&message(i), &1);
+            socket2.send_to(&message(i), &1);
+        }
+
+        let num_sent = sent1.lock().unwrap().len();
+        assert!(num_sent > 20 && num_sent < 80);
+        assert_eq!(socket1.dropped_packets(&1), 100 - num_sent);
+        assert_eq!(socket1.dropped_packets(&2), 0);
+        assert_eq!(*sent1.lock().unwrap(), *sent2.lock().unwrap());
+    }
+
+    #[test]
+    fn test_latency_holds_packets_back() {
+        let clock = MockClock::new();
+        let (mut socket, sent) = simulated_socket(&clock);
+        socket
+            .set_simulation(NetworkSim {
+                latency_ms: 50,
+                jitter_ms: 20,
+                ..Default::default()
+            })
+            .unwrap();
+        for i in 0..10 {
+            socket.send_to(&message(i), &1);
+        }
+
+        clock.advance(Duration::from_millis(49));
+        socket.receive_all_messages();
+        assert!(sent.lock().unwrap().is_empty());
+
+        clock.advance(Duration::from_millis(21));
+        socket.receive_all_messages();
+        let mut sent = sent.lock().unwrap().clone();
+        sent.sort_unstable();
+        assert_eq!(sent, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_invalid_loss() {
+        let clock = MockClock::new();
+        let (mut socket, _) = simulated_socket(&clock);
+        assert!(socket
+            .set_simulation(NetworkSim {
+                loss_pct: 101,
+                ..Default::default()
+            })
+            .is_err());
+    }
+}
@@ -9,6 +9,45 @@ pub struct NetworkStats {
     pub ping: u128,
     /// The estimated bandwidth used between the two clients, in kilobits per second.
     pub kbps_sent: usize,
+    /// The estimated bandwidth used by the packets received from the remote client, in kilobits per second.
+    pub kbps_received: usize,
+    /// The total amount of input bytes sent to the remote client before compression. Inputs are resent until they are acknowledged,
+    /// so every transmission counts.
+    pub bytes_sent_raw: usize,
+    /// The total amount of input bytes sent to the remote client after compression. Compare this with [`bytes_sent_raw`]
+    /// to get the compression ratio of your inputs.
+    ///
+    /// [`bytes_sent_raw`]: #structfield.bytes_sent_raw
+    pub bytes_sent_compressed: usize,
+    /// The number of duplicated packets received from the remote client that have been discarded.
+    pub duplicates_discarded: usize,
+    /// The number of packets received from the remote client after a packet that was sent later.
+    pub packets_reordered: usize,
+    /// The number of inputs received from the remote client for frames that had already been received before. Inputs are
+    /// retransmitted until they are acknowledged, so these are expected and ignored.
+    pub redundant_inputs: usize,
+    /// The share of recent input predictions for the remote player that turned out to be wrong, between 0 and 1.
+    /// Inputs that arrived before they had to be predicted count as well, checked against the prediction they would have gotten.
+    /// Only filled in by [`P2PSession::network_stats()`] for remote players.
+    ///
+    /// [`P2PSession::network_stats()`]: crate::P2PSession#method.network_stats
+    pub misprediction_rate: f32,
+    /// The number of packets to the remote client dropped by the network simulation.
+    /// Only filled in by [`P2PSession::network_stats()`], see [`P2PSession::set_network_simulation()`].
+    ///
+    /// [`P2PSession::network_stats()`]: crate::P2PSession#method.network_stats
+    /// [`P2PSession::set_network_simulation()`]: crate::P2PSession#method.set_network_simulation
+    pub simulated_packets_dropped: usize,
+    /// The end-to-end input lag in frames: the number of local frames between submitting a local input and receiving the
+    /// acknowledgement of the remote client for it, i.e. the round trip of an input in frames. The input delay is not included,
+    /// add it to get the lag the local player perceives. Measured for the most recently acknowledged input, and only for remote
+    /// players.
+    pub input_lag_frames: i32,
+    /// The number of inputs received from the remote client that have been rejected, because they were too far in the future
+    /// or could not be decoded. See [`GgrsEvent::SuspiciousPeer`].
+    ///
+    /// [`GgrsEvent::SuspiciousPeer`]: crate::GgrsEvent::SuspiciousPeer
+    pub inputs_rejected: usize,
 
     /// The number of frames GGRS calculates that the local client is behind the remote client at this instant in time.
     /// For example, if at this instant the current game client is running frame 1002 and the remote game client is running frame 1009,
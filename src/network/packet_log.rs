@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+
+use crate::network::messages::{Message, MessageBody};
+
+/// The number of [`PacketEvent`]s kept until they are drained. Older events are dropped.
+const MAX_PACKET_EVENTS: usize = 1024;
+
+/// Whether a packet has been sent to or received from a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    /// The packet has been handed to the socket to be sent.
+    Sent,
+    /// The packet has been received from the socket.
+    Received,
+}
+
+/// The type of a packet exchanged between peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    /// Asks the peer to take part in the handshake.
+    SyncRequest,
+    /// Answers a [`PacketKind::SyncRequest`].
+    SyncReply,
+    /// Carries inputs that have not been acknowledged yet.
+    Input,
+    /// Acknowledges received inputs.
+    InputAck,
+    /// Reports the frame advantage and measures the round trip time.
+    QualityReport,
+    /// Answers a [`PacketKind::QualityReport`].
+    QualityReply,
+    /// Carries the checksum of a frame for desync detection.
+    ChecksumReport,
+    /// Keeps the connection alive while there is nothing else to send.
+    KeepAlive,
+}
+
+/// Metadata of a single packet sent or received by a [`P2PSession`], as returned by [`P2PSession::drain_packet_events()`].
+///
+/// [`P2PSession`]: crate::P2PSession
+/// [`P2PSession::drain_packet_events()`]: crate::P2PSession#method.drain_packet_events
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketEvent<A> {
+    /// Whether the packet has been sent or received.
+    pub direction: PacketDirection,
+    /// The type of the packet.
+    pub kind: PacketKind,
+    /// The sequence number of the packet, which increases with every packet sent to a peer and wraps around.
+    pub sequence: u16,
+    /// The size of the serialized packet in bytes, without the headers of the underlying transport.
+    pub size: usize,
+    /// The peer the packet has been sent to or received from.
+    pub addr: A,
+}
+
+/// Records metadata of sent and received packets, keeping the most recent ones until they are drained.
+#[derive(Debug)]
+pub(crate) struct PacketLog<A> {
+    events: VecDeque<PacketEvent<A>>,
+}
+
+impl<A: Clone> PacketLog<A> {
+    pub(crate) fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, direction: PacketDirection, addr: &A, msg: &Message) {
+        let kind = match msg.body {
+            MessageBody::SyncRequest(_) => PacketKind::SyncRequest,
+            MessageBody::SyncReply(_) => PacketKind::SyncReply,
+            MessageBody::Input(_) => PacketKind::Input,
+            MessageBody::InputAck(_) => PacketKind::InputAck,
+            MessageBody::QualityReport(_) => PacketKind::QualityReport,
+            MessageBody::QualityReply(_) => PacketKind::QualityReply,
+            MessageBody::ChecksumReport(_) => PacketKind::ChecksumReport,
+            MessageBody::KeepAlive => PacketKind::KeepAlive,
+        };
+        let size = bincode::serialized_size(msg).expect("message should be serializable") as usize;
+
+        if self.events.len() == MAX_PACKET_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(PacketEvent {
+            direction,
+            kind,
+            sequence: msg.header.sequence,
+            size,
+            addr: addr.clone(),
+        });
+    }
+
+    pub(crate) fn drain(&mut self) -> Vec<PacketEvent<A>> {
+        self.events.drain(..).collect()
+    }
+}
@@ -1,45 +1,57 @@
 use crate::frame_info::PlayerInput;
+use crate::network::bit_packing;
 use crate::network::compression::{decode, encode};
 use crate::network::messages::{
     ChecksumReport, ConnectionStatus, Input, InputAck, Message, MessageBody, MessageHeader,
     QualityReply, QualityReport, SyncReply, SyncRequest,
 };
+use crate::network::packet_log::{PacketDirection, PacketLog};
 use crate::time_sync::TimeSync;
 use crate::{
-    Config, DesyncDetection, Frame, GgrsError, NonBlockingSocket, PlayerHandle, NULL_FRAME,
+    Clock, Config, DesyncDetection, DisconnectReason, Frame, GgrsError, IncompatibilityReason,
+    NonBlockingSocket, PlayerHandle, NULL_FRAME,
 };
-use tracing::trace;
+use tracing::{trace, warn};
 
 use instant::{Duration, Instant};
 use std::collections::vec_deque::Drain;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::ops::Add;
+use std::sync::Arc;
 
 use super::network_stats::NetworkStats;
 
+/// Version of the GGRS network protocol. Peers with a different version cannot synchronize.
+const PROTOCOL_VERSION: u16 = 4;
 const UDP_HEADER_SIZE: usize = 28; // Size of IP + UDP headers
+/// The amount of recently received sequence numbers remembered to detect duplicates.
+const SEQUENCE_WINDOW_SIZE: u16 = 64;
 const NUM_SYNC_PACKETS: u32 = 5;
 const UDP_SHUTDOWN_TIMER: u64 = 5000;
 const PENDING_OUTPUT_SIZE: usize = 128;
 const SYNC_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+// The interval between sync retries doubles with every retry up to this maximum
+const MAX_SYNC_RETRY_INTERVAL: Duration = Duration::from_millis(1600);
 const RUNNING_RETRY_INTERVAL: Duration = Duration::from_millis(200);
 const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(200);
 const QUALITY_REPORT_INTERVAL: Duration = Duration::from_millis(200);
+/// After this many input messages with rejected inputs, the remote client is reported as suspicious
+const SUSPICIOUS_INPUT_MESSAGES: usize = 8;
+/// Input messages decoding into more inputs are rejected, which bounds the memory a malformed message can allocate
+const MAX_INPUTS_PER_MESSAGE: usize = u16::MAX as usize;
 /// Number of old checksums to keep in memory
 pub const MAX_CHECKSUM_HISTORY_SIZE: usize = 32;
 
-fn millis_since_epoch() -> u128 {
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis()
-    }
-    #[cfg(target_arch = "wasm32")]
-    {
-        js_sys::Date::new_0().get_time() as u128
+/// Returns the size of a single input on the wire in bits.
+fn wire_input_bits<T: Config>() -> usize {
+    match T::INPUT_BITS {
+        Some(bits) => bits,
+        None => {
+            let bytes =
+                bincode::serialized_size(&T::Input::default()).expect("input serialization failed");
+            bytes as usize * 8
+        }
     }
 }
 
@@ -54,7 +66,10 @@ struct InputBytes {
 
 impl InputBytes {
     fn zeroed<T: Config>(num_players: usize) -> Self {
-        let size = core::mem::size_of::<T::Input>() * num_players;
+        let size = match T::INPUT_BITS {
+            Some(bits) => (num_players * bits).div_ceil(8),
+            None => core::mem::size_of::<T::Input>() * num_players,
+        };
         Self {
             frame: NULL_FRAME,
             bytes: vec![0; size],
@@ -65,9 +80,9 @@ impl InputBytes {
         num_players: usize,
         inputs: &HashMap<PlayerHandle, PlayerInput<T::Input>>,
     ) -> Self {
-        let mut bytes = Vec::new();
+        let mut serialized = Vec::new();
         let mut frame = NULL_FRAME;
-        // in ascending order
+        // in ascending order of handles, so the bytes don't depend on the order the local inputs have been added in
         for handle in 0..num_players {
             if let Some(input) = inputs.get(&handle) {
                 assert!(frame == NULL_FRAME || input.frame == NULL_FRAME || frame == input.frame);
@@ -75,26 +90,60 @@ impl InputBytes {
                     frame = input.frame;
                 }
 
-                bincode::serialize_into(&mut bytes, &input.input)
-                    .expect("input serialization failed");
+                serialized
+                    .push(bincode::serialize(&input.input).expect("input serialization failed"));
             }
         }
+        let bytes = match T::INPUT_BITS {
+            Some(bits) => bit_packing::pack(&serialized, bits),
+            None => serialized.concat(),
+        };
         Self { frame, bytes }
     }
 
-    fn to_player_inputs<T: Config>(&self, num_players: usize) -> Vec<PlayerInput<T::Input>> {
+    /// Deserializes the inputs of all players. Fails if the bytes don't hold valid inputs for `num_players` players.
+    fn to_player_inputs<T: Config>(
+        &self,
+        num_players: usize,
+    ) -> Result<Vec<PlayerInput<T::Input>>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(bits) = T::INPUT_BITS {
+            if self.bytes.len() * 8 < num_players * bits {
+                return Err(format!(
+                    "{} bytes are too short for {} inputs of {} bits",
+                    self.bytes.len(),
+                    num_players,
+                    bits
+                )
+                .into());
+            }
+            let input_size = bincode::serialized_size(&T::Input::default())? as usize;
+            return bit_packing::unpack(&self.bytes, bits, num_players, input_size)
+                .iter()
+                .map(|bytes| {
+                    let input: T::Input = bincode::deserialize(bytes)?;
+                    Ok(PlayerInput::new(self.frame, input))
+                })
+                .collect();
+        }
+
+        if num_players == 0 || !self.bytes.len().is_multiple_of(num_players) {
+            return Err(format!(
+                "{} bytes cannot be split into inputs of {} players",
+                self.bytes.len(),
+                num_players
+            )
+            .into());
+        }
         let mut player_inputs = Vec::new();
-        assert!(self.bytes.len() % num_players == 0);
         let size = self.bytes.len() / num_players;
         for p in 0..num_players {
             let start = p * size;
             let end = start + size;
             let player_byte_slice = &self.bytes[start..end];
-            let input: T::Input =
-                bincode::deserialize(player_byte_slice).expect("input deserialization failed");
+            let input: T::Input = bincode::deserialize(player_byte_slice)?;
             player_inputs.push(PlayerInput::new(self.frame, input));
         }
-        player_inputs
+        Ok(player_inputs)
     }
 }
 
@@ -113,11 +162,17 @@ where
         player: PlayerHandle,
     },
     /// The remote client has disconnected.
-    Disconnected,
+    Disconnected { reason: DisconnectReason },
     /// The session has not received packets from the remote client since `disconnect_timeout` ms.
     NetworkInterrupted { disconnect_timeout: u128 },
     /// Sent only after a `NetworkInterrupted` event, if communication has resumed.
     NetworkResumed,
+    /// The remote client uses a different protocol version, protocol id, input size or number of players.
+    IncompatiblePeer { reason: IncompatibilityReason },
+    /// The handshake with the remote client ran out of retries or time.
+    SynchronizationFailed,
+    /// The remote client repeatedly sent inputs that have been rejected.
+    SuspiciousPeer { rejected_inputs: usize },
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -142,17 +197,26 @@ where
     state: ProtocolState,
     sync_remaining_roundtrips: u32,
     sync_random_requests: HashSet<u32>,
+    sync_start_time: Instant,
+    sync_last_request_time: Instant,
+    sync_retry_interval: Duration,
+    sync_retries: usize,
     running_last_quality_report: Instant,
     running_last_input_recv: Instant,
     disconnect_notify_sent: bool,
     disconnect_event_sent: bool,
+    incompatible_event_sent: bool,
+    suspicious_event_sent: bool,
 
     // constants
     disconnect_timeout: Duration,
     disconnect_notify_start: Duration,
+    sync_timeout: Option<Duration>,
+    max_sync_retries: Option<usize>,
     shutdown_timeout: Instant,
     fps: usize,
     magic: u16,
+    protocol_id: u64,
 
     // the other client
     peer_addr: T::Address,
@@ -161,9 +225,22 @@ where
 
     // input compression
     pending_output: VecDeque<InputBytes>,
+    /// Inputs are sent in batches of this many frames
+    send_interval_frames: usize,
+    /// The number of inputs added to `pending_output` since the last batch was sent
+    unsent_inputs: usize,
     last_acked_input: InputBytes,
+    /// The local frame at which each input in `pending_output` was submitted
+    pending_submit_frames: VecDeque<Frame>,
+    /// The local frame at which the most recently acknowledged input was submitted, until the input lag is updated
+    acked_submit_frame: Frame,
+    input_lag_frames: i32,
     max_prediction: usize,
     recv_inputs: HashMap<Frame, InputBytes>,
+    /// Inputs for later frames are rejected, so the remote client has to resend them later
+    max_input_frame: Frame,
+    inputs_rejected: usize,
+    rejected_input_messages: usize,
 
     // time sync
     time_sync_layer: TimeSync,
@@ -171,15 +248,31 @@ where
     remote_frame_advantage: i32,
 
     // network
-    stats_start_time: u128,
+    created_at: Instant,
+    stats_start_time: Instant,
     packets_sent: usize,
     bytes_sent: usize,
+    packets_received: usize,
+    bytes_received: usize,
+    input_bytes_raw: usize,
+    input_bytes_compressed: usize,
     round_trip_time: u128,
     last_send_time: Instant,
     last_recv_time: Instant,
+    timeouts_suspended_at: Option<Instant>,
+    clock: Arc<dyn Clock>,
+
+    // sequence numbers
+    next_send_sequence: u16,
+    last_recv_sequence: Option<u16>,
+    /// bit `i` is set if the message with sequence number `last_recv_sequence - i` has been received
+    recv_sequence_window: u64,
+    duplicates_discarded: usize,
+    redundant_inputs: usize,
+    packets_reordered: usize,
 
     // debug desync
-    pub(crate) pending_checksums: HashMap<Frame, u128>,
+    pub(crate) pending_checksums: HashMap<Frame, ChecksumReport>,
     desync_detection: DesyncDetection,
 }
 
@@ -189,7 +282,51 @@ impl<T: Config> PartialEq for UdpProtocol<T> {
     }
 }
 
+/// The parameters all endpoints of a session are created with.
+#[derive(Debug, Clone)]
+pub(crate) struct EndpointConfig {
+    pub num_players: usize,
+    pub max_prediction: usize,
+    pub disconnect_timeout: Duration,
+    pub disconnect_notify_start: Duration,
+    pub fps: usize,
+    pub desync_detection: DesyncDetection,
+    pub protocol_id: u64,
+    pub sync_timeout: Option<Duration>,
+    pub max_sync_retries: Option<usize>,
+    pub send_interval_frames: usize,
+    pub clock: Arc<dyn Clock>,
+}
+
 impl<T: Config> UdpProtocol<T> {
+    /// Creates an endpoint with the parameters of its session and starts the synchronization.
+    pub(crate) fn from_config(
+        handles: Vec<PlayerHandle>,
+        peer_addr: T::Address,
+        local_players: usize,
+        config: &EndpointConfig,
+    ) -> Self {
+        let mut endpoint = Self::new(
+            handles,
+            peer_addr,
+            config.num_players,
+            local_players,
+            config.max_prediction,
+            config.disconnect_timeout,
+            config.disconnect_notify_start,
+            config.fps,
+            config.desync_detection,
+            config.protocol_id,
+            config.sync_timeout,
+            config.max_sync_retries,
+            config.clock.clone(),
+        );
+        endpoint.send_interval_frames = config.send_interval_frames;
+        endpoint.synchronize();
+        endpoint
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         mut handles: Vec<PlayerHandle>,
         peer_addr: T::Address,
@@ -200,6 +337,10 @@ impl<T: Config> UdpProtocol<T> {
         disconnect_notify_start: Duration,
         fps: usize,
         desync_detection: DesyncDetection,
+        protocol_id: u64,
+        sync_timeout: Option<Duration>,
+        max_sync_retries: Option<usize>,
+        clock: Arc<dyn Clock>,
     ) -> Self {
         let mut magic = rand::random::<u16>();
         while magic == 0 {
@@ -219,6 +360,8 @@ impl<T: Config> UdpProtocol<T> {
         let mut recv_inputs = HashMap::new();
         recv_inputs.insert(NULL_FRAME, InputBytes::zeroed::<T>(recv_player_num));
 
+        let now = clock.now();
+
         Self {
             num_players,
             handles,
@@ -229,17 +372,26 @@ impl<T: Config> UdpProtocol<T> {
             state: ProtocolState::Initializing,
             sync_remaining_roundtrips: NUM_SYNC_PACKETS,
             sync_random_requests: HashSet::new(),
-            running_last_quality_report: Instant::now(),
-            running_last_input_recv: Instant::now(),
+            sync_start_time: now,
+            sync_last_request_time: now,
+            sync_retry_interval: SYNC_RETRY_INTERVAL,
+            sync_retries: 0,
+            running_last_quality_report: now,
+            running_last_input_recv: now,
             disconnect_notify_sent: false,
             disconnect_event_sent: false,
+            incompatible_event_sent: false,
+            suspicious_event_sent: false,
 
             // constants
             disconnect_timeout,
             disconnect_notify_start,
-            shutdown_timeout: Instant::now(),
+            sync_timeout,
+            max_sync_retries,
+            shutdown_timeout: now,
             fps,
             magic,
+            protocol_id,
 
             // the other client
             peer_addr,
@@ -248,9 +400,17 @@ impl<T: Config> UdpProtocol<T> {
 
             // input compression
             pending_output: VecDeque::with_capacity(PENDING_OUTPUT_SIZE),
+            pending_submit_frames: VecDeque::with_capacity(PENDING_OUTPUT_SIZE),
+            acked_submit_frame: NULL_FRAME,
+            input_lag_frames: 0,
+            send_interval_frames: 1,
+            unsent_inputs: 0,
             last_acked_input: InputBytes::zeroed::<T>(local_players),
             max_prediction,
             recv_inputs,
+            max_input_frame: Frame::MAX,
+            inputs_rejected: 0,
+            rejected_input_messages: 0,
 
             // time sync
             time_sync_layer: TimeSync::new(),
@@ -258,12 +418,27 @@ impl<T: Config> UdpProtocol<T> {
             remote_frame_advantage: 0,
 
             // network
-            stats_start_time: 0,
+            created_at: now,
+            stats_start_time: now,
             packets_sent: 0,
             bytes_sent: 0,
+            packets_received: 0,
+            bytes_received: 0,
+            input_bytes_raw: 0,
+            input_bytes_compressed: 0,
             round_trip_time: 0,
-            last_send_time: Instant::now(),
-            last_recv_time: Instant::now(),
+            last_send_time: now,
+            last_recv_time: now,
+            timeouts_suspended_at: None,
+            clock,
+
+            // sequence numbers
+            next_send_sequence: 0,
+            last_recv_sequence: None,
+            recv_sequence_window: 0,
+            duplicates_discarded: 0,
+            redundant_inputs: 0,
+            packets_reordered: 0,
 
             // debug desync
             pending_checksums: HashMap::new(),
@@ -271,6 +446,30 @@ impl<T: Config> UdpProtocol<T> {
         }
     }
 
+    /// Releases memory of buffers that grew beyond their usual size. Buffers within their usual size are left alone, so they
+    /// aren't reallocated over and over while in use.
+    pub(crate) fn compact(&mut self) {
+        // received inputs are kept for twice the prediction window, see `on_input`
+        self.recv_inputs.shrink_to(2 * self.max_prediction + 1);
+        self.pending_output.shrink_to(PENDING_OUTPUT_SIZE);
+        self.pending_submit_frames.shrink_to(PENDING_OUTPUT_SIZE);
+        self.pending_checksums.shrink_to(MAX_CHECKSUM_HISTORY_SIZE);
+    }
+
+    /// Sets the last frame for which inputs of the remote client are accepted. Inputs for later frames are rejected.
+    pub(crate) fn set_max_input_frame(&mut self, frame: Frame) {
+        self.max_input_frame = frame;
+    }
+
+    /// Measures the input lag of the most recently acknowledged input: the number of frames between the local frame it was
+    /// submitted at and `local_frame`, the local frame at which the acknowledgement has been received.
+    pub(crate) fn update_input_lag(&mut self, local_frame: Frame) {
+        if self.acked_submit_frame != NULL_FRAME {
+            self.input_lag_frames = local_frame - self.acked_submit_frame;
+            self.acked_submit_frame = NULL_FRAME;
+        }
+    }
+
     pub(crate) fn update_local_frame_advantage(&mut self, local_frame: Frame) {
         if local_frame == NULL_FRAME || self.last_recv_frame() == NULL_FRAME {
             return;
@@ -282,13 +481,26 @@ impl<T: Config> UdpProtocol<T> {
         self.local_frame_advantage = remote_frame - local_frame;
     }
 
+    /// Returns the time left until the endpoint times out, once no packet has been received for longer than
+    /// `disconnect_notify_start`. Returns `None` before that, while timeouts are suspended or if the endpoint is not running.
+    pub(crate) fn time_until_disconnect(&self) -> Option<Duration> {
+        let now = self.clock.now();
+        if self.state != ProtocolState::Running
+            || self.timeouts_suspended_at.is_some()
+            || self.last_recv_time + self.disconnect_notify_start >= now
+        {
+            return None;
+        }
+        let silence = now - self.last_recv_time;
+        Some(self.disconnect_timeout.saturating_sub(silence))
+    }
+
     pub(crate) fn network_stats(&self) -> Result<NetworkStats, GgrsError> {
         if self.state != ProtocolState::Synchronizing && self.state != ProtocolState::Running {
             return Err(GgrsError::NotSynchronized);
         }
 
-        let now = millis_since_epoch();
-        let seconds = (now - self.stats_start_time) / 1000;
+        let seconds = (self.clock.now() - self.stats_start_time).as_secs();
         if seconds == 0 {
             return Err(GgrsError::NotSynchronized);
         }
@@ -296,11 +508,23 @@ impl<T: Config> UdpProtocol<T> {
         let total_bytes_sent = self.bytes_sent + (self.packets_sent * UDP_HEADER_SIZE);
         let bps = total_bytes_sent / seconds as usize;
         //let upd_overhead = (self.packets_sent * UDP_HEADER_SIZE) / self.bytes_sent;
+        let total_bytes_received = self.bytes_received + (self.packets_received * UDP_HEADER_SIZE);
+        let bps_received = total_bytes_received / seconds as usize;
 
         Ok(NetworkStats {
             ping: self.round_trip_time,
             send_queue_len: self.pending_output.len(),
             kbps_sent: bps / 1024,
+            kbps_received: bps_received / 1024,
+            bytes_sent_raw: self.input_bytes_raw,
+            bytes_sent_compressed: self.input_bytes_compressed,
+            duplicates_discarded: self.duplicates_discarded,
+            redundant_inputs: self.redundant_inputs,
+            packets_reordered: self.packets_reordered,
+            misprediction_rate: 0.0,
+            simulated_packets_dropped: 0,
+            input_lag_frames: self.input_lag_frames,
+            inputs_rejected: self.inputs_rejected,
             local_frames_behind: self.local_frame_advantage,
             remote_frames_behind: self.remote_frame_advantage,
         })
@@ -333,19 +557,64 @@ impl<T: Config> UdpProtocol<T> {
             return;
         }
 
+        // let the remote know we are leaving. If this message gets lost, the remote will time out instead.
+        if self.state == ProtocolState::Running {
+            let body = Input {
+                ack_frame: self.last_recv_frame(),
+                disconnect_requested: true,
+                ..Default::default()
+            };
+            self.queue_message(MessageBody::Input(body));
+        }
+
         self.state = ProtocolState::Disconnected;
         // schedule the timeout which will lead to shutdown
-        self.shutdown_timeout = Instant::now().add(Duration::from_millis(UDP_SHUTDOWN_TIMER))
+        self.shutdown_timeout = self
+            .clock
+            .now()
+            .add(Duration::from_millis(UDP_SHUTDOWN_TIMER))
+    }
+
+    /// Stops checking for network interruptions and disconnect timeouts until `resume_timeouts()` is called.
+    pub(crate) fn suspend_timeouts(&mut self) {
+        if self.timeouts_suspended_at.is_none() {
+            self.timeouts_suspended_at = Some(self.clock.now());
+        }
+    }
+
+    /// Resumes checking for timeouts. The time spent suspended does not count towards the timeouts.
+    /// The frame advantage measured before the pause no longer applies afterwards, so it starts over.
+    pub(crate) fn resume_timeouts(&mut self) {
+        if let Some(suspended_at) = self.timeouts_suspended_at.take() {
+            // only the silence before the pause is shifted, packets received during the pause are as recent as they are
+            if self.last_recv_time < suspended_at {
+                self.last_recv_time += self.clock.now() - suspended_at;
+            }
+            self.time_sync_layer = TimeSync::new();
+            self.local_frame_advantage = 0;
+            self.remote_frame_advantage = 0;
+        }
     }
 
     pub(crate) fn synchronize(&mut self) {
         assert_eq!(self.state, ProtocolState::Initializing);
         self.state = ProtocolState::Synchronizing;
         self.sync_remaining_roundtrips = NUM_SYNC_PACKETS;
-        self.stats_start_time = millis_since_epoch();
+        self.sync_start_time = self.clock.now();
+        self.stats_start_time = self.clock.now();
         self.send_sync_request();
     }
 
+    /// The number of frames we are estimated to be behind the remote client.
+    pub(crate) fn local_frame_advantage(&self) -> i32 {
+        self.local_frame_advantage
+    }
+
+    /// The number of frames the remote client estimates to be behind us, as last reported by it.
+    pub(crate) fn remote_frame_advantage(&self) -> i32 {
+        self.remote_frame_advantage
+    }
+
     pub(crate) fn average_frame_advantage(&self) -> i32 {
         self.time_sync_layer.average_frame_advantage()
     }
@@ -354,20 +623,36 @@ impl<T: Config> UdpProtocol<T> {
         self.peer_addr.clone()
     }
 
-    pub(crate) fn poll(&mut self, connect_status: &[ConnectionStatus]) -> Drain<Event<T>> {
-        let now = Instant::now();
+    pub(crate) fn poll(&mut self, connect_status: &[ConnectionStatus]) -> Drain<'_, Event<T>> {
+        let now = self.clock.now();
         match self.state {
             ProtocolState::Synchronizing => {
-                // some time has passed, let us send another sync request
-                if self.last_send_time + SYNC_RETRY_INTERVAL < now {
-                    self.send_sync_request();
+                let timed_out = self
+                    .sync_timeout
+                    .is_some_and(|timeout| self.sync_start_time + timeout < now);
+                // some time has passed, let us send another sync request, waiting longer with every retry
+                if !timed_out && self.sync_last_request_time + self.sync_retry_interval < now {
+                    if self
+                        .max_sync_retries
+                        .is_some_and(|max_retries| self.sync_retries >= max_retries)
+                    {
+                        self.fail_synchronization();
+                    } else {
+                        self.sync_retries += 1;
+                        self.sync_retry_interval =
+                            std::cmp::min(self.sync_retry_interval * 2, MAX_SYNC_RETRY_INTERVAL);
+                        self.send_sync_request();
+                    }
+                }
+                if timed_out {
+                    self.fail_synchronization();
                 }
             }
             ProtocolState::Running => {
                 // resend pending inputs, if some time has passed without sending or receiving inputs
                 if self.running_last_input_recv + RUNNING_RETRY_INTERVAL < now {
                     self.send_pending_output(connect_status);
-                    self.running_last_input_recv = Instant::now();
+                    self.running_last_input_recv = self.clock.now();
                 }
 
                 // periodically send a quality report
@@ -381,7 +666,8 @@ impl<T: Config> UdpProtocol<T> {
                 }
 
                 // trigger a NetworkInterrupted event if we didn't receive a packet for some time
-                if !self.disconnect_notify_sent
+                if self.timeouts_suspended_at.is_none()
+                    && !self.disconnect_notify_sent
                     && self.last_recv_time + self.disconnect_notify_start < now
                 {
                     let duration: Duration = self.disconnect_timeout - self.disconnect_notify_start;
@@ -392,15 +678,18 @@ impl<T: Config> UdpProtocol<T> {
                 }
 
                 // if we pass the disconnect_timeout threshold, send an event to disconnect
-                if !self.disconnect_event_sent
+                if self.timeouts_suspended_at.is_none()
+                    && !self.disconnect_event_sent
                     && self.last_recv_time + self.disconnect_timeout < now
                 {
-                    self.event_queue.push_back(Event::Disconnected);
+                    self.event_queue.push_back(Event::Disconnected {
+                        reason: DisconnectReason::Timeout,
+                    });
                     self.disconnect_event_sent = true;
                 }
             }
             ProtocolState::Disconnected => {
-                if self.shutdown_timeout < Instant::now() {
+                if self.shutdown_timeout < self.clock.now() {
                     self.state = ProtocolState::Shutdown;
                 }
             }
@@ -417,6 +706,10 @@ impl<T: Config> UdpProtocol<T> {
                         .pending_output
                         .pop_front()
                         .expect("Expected input to exist");
+                    self.acked_submit_frame = self
+                        .pending_submit_frames
+                        .pop_front()
+                        .expect("Expected submit frame to exist");
                 } else {
                     break;
                 }
@@ -430,7 +723,8 @@ impl<T: Config> UdpProtocol<T> {
 
     pub(crate) fn send_all_messages(
         &mut self,
-        socket: &mut Box<dyn NonBlockingSocket<T::Address>>,
+        socket: &mut dyn NonBlockingSocket<T::Address>,
+        mut packet_log: Option<&mut PacketLog<T::Address>>,
     ) {
         if self.state == ProtocolState::Shutdown {
             trace!(
@@ -448,14 +742,19 @@ impl<T: Config> UdpProtocol<T> {
 
         trace!("Sending {} messages over socket", self.send_queue.len());
         for msg in self.send_queue.drain(..) {
+            if let Some(packet_log) = packet_log.as_deref_mut() {
+                packet_log.record(PacketDirection::Sent, &self.peer_addr, &msg);
+            }
             socket.send_to(&msg, &self.peer_addr);
         }
     }
 
+    /// Sends the given inputs, which have been submitted while the local session was at `local_frame`.
     pub(crate) fn send_input(
         &mut self,
         inputs: &HashMap<PlayerHandle, PlayerInput<T::Input>>,
         connect_status: &[ConnectionStatus],
+        local_frame: Frame,
     ) {
         if self.state != ProtocolState::Running {
             return;
@@ -471,14 +770,23 @@ impl<T: Config> UdpProtocol<T> {
         );
 
         self.pending_output.push_back(endpoint_data);
+        self.pending_submit_frames.push_back(local_frame);
 
         // we should never have so much pending input for a remote player (if they didn't ack, we should stop at MAX_PREDICTION_THRESHOLD)
         // this is a spectator that didn't ack our input, we just disconnect them
-        if self.pending_output.len() > PENDING_OUTPUT_SIZE {
-            self.event_queue.push_back(Event::Disconnected);
+        if self.pending_output.len() > PENDING_OUTPUT_SIZE && !self.disconnect_event_sent {
+            self.event_queue.push_back(Event::Disconnected {
+                reason: DisconnectReason::ProtocolError,
+            });
+            self.disconnect_event_sent = true;
         }
 
-        self.send_pending_output(connect_status);
+        // send the inputs once a batch is complete
+        self.unsent_inputs += 1;
+        if self.unsent_inputs >= self.send_interval_frames {
+            self.unsent_inputs = 0;
+            self.send_pending_output(connect_status);
+        }
     }
 
     fn send_pending_output(&mut self, connect_status: &[ConnectionStatus]) {
@@ -496,18 +804,15 @@ impl<T: Config> UdpProtocol<T> {
                 &self.last_acked_input.bytes,
                 self.pending_output.iter().map(|gi| &gi.bytes),
             );
+            let raw_len: usize = self.pending_output.iter().map(|gi| gi.bytes.len()).sum();
             trace!(
                 "Encoded {} bytes from {} pending output(s) into {} bytes",
-                {
-                    let mut sum = 0;
-                    for gi in self.pending_output.iter() {
-                        sum += gi.bytes.len();
-                    }
-                    sum
-                },
+                raw_len,
                 self.pending_output.len(),
                 body.bytes.len()
             );
+            self.input_bytes_raw += raw_len;
+            self.input_bytes_compressed += body.bytes.len();
 
             body.ack_frame = self.last_recv_frame();
             body.disconnect_requested = self.state == ProtocolState::Disconnected;
@@ -529,38 +834,61 @@ impl<T: Config> UdpProtocol<T> {
         self.queue_message(MessageBody::KeepAlive);
     }
 
+    fn fail_synchronization(&mut self) {
+        warn!(
+            "Synchronization with {:?} failed after {} retries",
+            self.peer_addr, self.sync_retries
+        );
+        self.state = ProtocolState::Shutdown;
+        self.event_queue.push_back(Event::SynchronizationFailed);
+    }
+
     fn send_sync_request(&mut self) {
+        self.sync_last_request_time = self.clock.now();
         let random_number = rand::random::<u32>();
         self.sync_random_requests.insert(random_number);
         let body = SyncRequest {
             random_request: random_number,
+            protocol_version: PROTOCOL_VERSION,
+            protocol_id: self.protocol_id,
+            input_bits: wire_input_bits::<T>() as u32,
+            num_players: self.num_players as u16,
         };
         self.queue_message(MessageBody::SyncRequest(body));
     }
 
     fn send_quality_report(&mut self) {
-        self.running_last_quality_report = Instant::now();
+        self.running_last_quality_report = self.clock.now();
         let body = QualityReport {
             frame_advantage: i16::try_from(
                 self.local_frame_advantage
                     .clamp(i16::MIN as i32, i16::MAX as i32),
             )
             .expect("local_frame_advantage should have been clamped into the range of an i16"),
-            ping: millis_since_epoch(),
+            ping: self.millis_since_start(),
         };
 
         self.queue_message(MessageBody::QualityReport(body));
     }
 
+    /// The timestamp of quality reports. Only this endpoint interprets the timestamps it sent, so they are relative to its creation.
+    fn millis_since_start(&self) -> u128 {
+        (self.clock.now() - self.created_at).as_millis()
+    }
+
     fn queue_message(&mut self, body: MessageBody) {
         trace!("Queuing message to {:?}: {:?}", self.peer_addr, body);
 
         // set the header
-        let header = MessageHeader { magic: self.magic };
+        let header = MessageHeader {
+            magic: self.magic,
+            sequence: self.next_send_sequence,
+        };
+        self.next_send_sequence = self.next_send_sequence.wrapping_add(1);
         let msg = Message { header, body };
 
         self.packets_sent += 1;
-        self.last_send_time = Instant::now();
+        self.last_send_time = self.clock.now();
         self.bytes_sent += std::mem::size_of_val(&msg);
 
         // add the packet to the back of the send queue
@@ -586,8 +914,18 @@ impl<T: Config> UdpProtocol<T> {
             return;
         }
 
+        // duplicates use up bandwidth as well, so they are counted
+        self.packets_received += 1;
+        self.bytes_received += std::mem::size_of_val(msg);
+
+        // filter packets we already received
+        if !self.register_sequence(msg.header.sequence) {
+            trace!("Received duplicate message; ignoring");
+            return;
+        }
+
         // update time when we last received packages
-        self.last_recv_time = Instant::now();
+        self.last_recv_time = self.clock.now();
 
         // if the connection has been marked as interrupted, send an event to signal we are receiving again
         if self.disconnect_notify_sent && self.state == ProtocolState::Running {
@@ -609,10 +947,61 @@ impl<T: Config> UdpProtocol<T> {
         }
     }
 
+    /// Records the sequence number of a received message. Returns false if the message is a duplicate of one we already received.
+    /// Messages arriving after a message with a higher sequence number are counted as reordered.
+    fn register_sequence(&mut self, sequence: u16) -> bool {
+        let last_sequence = match self.last_recv_sequence {
+            Some(last_sequence) => last_sequence,
+            None => {
+                self.last_recv_sequence = Some(sequence);
+                self.recv_sequence_window = 1;
+                return true;
+            }
+        };
+
+        let ahead = sequence.wrapping_sub(last_sequence);
+        if ahead != 0 && ahead < u16::MAX / 2 {
+            // the newest message so far
+            self.recv_sequence_window = if ahead < SEQUENCE_WINDOW_SIZE {
+                (self.recv_sequence_window << ahead) | 1
+            } else {
+                1
+            };
+            self.last_recv_sequence = Some(sequence);
+            return true;
+        }
+
+        let behind = last_sequence.wrapping_sub(sequence);
+        if behind < SEQUENCE_WINDOW_SIZE {
+            let bit = 1 << behind;
+            if self.recv_sequence_window & bit != 0 {
+                self.duplicates_discarded += 1;
+                return false;
+            }
+            self.recv_sequence_window |= bit;
+        }
+        // messages too old to be checked for duplicates are handled anyway, since handling inputs is idempotent
+        self.packets_reordered += 1;
+        true
+    }
+
     /// Upon receiving a `SyncRequest`, answer with a `SyncReply` with the proper data
     fn on_sync_request(&mut self, body: SyncRequest) {
+        // don't help incompatible peers to synchronize
+        if !self.is_compatible(
+            body.protocol_version,
+            body.protocol_id,
+            body.input_bits,
+            body.num_players,
+        ) {
+            return;
+        }
         let reply_body = SyncReply {
             random_reply: body.random_request,
+            protocol_version: PROTOCOL_VERSION,
+            protocol_id: self.protocol_id,
+            input_bits: wire_input_bits::<T>() as u32,
+            num_players: self.num_players as u16,
         };
         self.queue_message(MessageBody::SyncReply(reply_body));
     }
@@ -623,12 +1012,23 @@ impl<T: Config> UdpProtocol<T> {
         if self.state != ProtocolState::Synchronizing {
             return;
         }
+        // never synchronize with an incompatible peer
+        if !self.is_compatible(
+            body.protocol_version,
+            body.protocol_id,
+            body.input_bits,
+            body.num_players,
+        ) {
+            return;
+        }
         // this is not the correct reply
         if !self.sync_random_requests.remove(&body.random_reply) {
             return;
         }
         // the sync reply is good, so we send a sync request again until we have finished the required roundtrips. Then, we can conclude the syncing process.
         self.sync_remaining_roundtrips -= 1;
+        self.sync_retries = 0;
+        self.sync_retry_interval = SYNC_RETRY_INTERVAL;
         if self.sync_remaining_roundtrips > 0 {
             // register an event
             let evt = Event::Synchronizing {
@@ -648,6 +1048,40 @@ impl<T: Config> UdpProtocol<T> {
         }
     }
 
+    /// Checks if the remote speaks the same protocol and exchanges inputs of the same shape. Reports an incompatible peer once.
+    fn is_compatible(
+        &mut self,
+        protocol_version: u16,
+        protocol_id: u64,
+        input_bits: u32,
+        num_players: u16,
+    ) -> bool {
+        let reason = if protocol_version != PROTOCOL_VERSION {
+            IncompatibilityReason::ProtocolVersion
+        } else if protocol_id != self.protocol_id {
+            IncompatibilityReason::ProtocolId
+        } else if input_bits as usize != wire_input_bits::<T>() {
+            IncompatibilityReason::InputSize {
+                local: wire_input_bits::<T>(),
+                remote: input_bits as usize,
+            }
+        } else if num_players as usize != self.num_players {
+            IncompatibilityReason::NumPlayers {
+                local: self.num_players,
+                remote: num_players as usize,
+            }
+        } else {
+            return true;
+        };
+        if !self.incompatible_event_sent {
+            warn!("Peer {:?} is incompatible: {:?}", self.peer_addr, reason);
+            self.event_queue
+                .push_back(Event::IncompatiblePeer { reason });
+            self.incompatible_event_sent = true;
+        }
+        false
+    }
+
     fn on_input(&mut self, body: &Input) {
         // drop pending outputs until the ack frame
         self.pop_pending_output(body.ack_frame);
@@ -656,10 +1090,19 @@ impl<T: Config> UdpProtocol<T> {
         if body.disconnect_requested {
             // if a disconnect is requested, disconnect now
             if self.state != ProtocolState::Disconnected && !self.disconnect_event_sent {
-                self.event_queue.push_back(Event::Disconnected);
+                self.event_queue.push_back(Event::Disconnected {
+                    reason: DisconnectReason::Shutdown,
+                });
                 self.disconnect_event_sent = true;
             }
+            // the remote is leaving, so there is nothing else to handle
+            return;
         } else {
+            // a message with the wrong number of players is malformed
+            if body.peer_connect_status.len() != self.peer_connect_status.len() {
+                self.reject_inputs(0);
+                return;
+            }
             // update the peer connection status
             for i in 0..self.peer_connect_status.len() {
                 self.peer_connect_status[i].disconnected = body.peer_connect_status[i].disconnected
@@ -672,9 +1115,10 @@ impl<T: Config> UdpProtocol<T> {
         }
 
         // if the encoded packet is decoded with an input we did not receive yet, we cannot recover
-        assert!(
-            self.last_recv_frame() == NULL_FRAME || self.last_recv_frame() + 1 >= body.start_frame
-        );
+        if self.last_recv_frame() != NULL_FRAME && self.last_recv_frame() + 1 < body.start_frame {
+            self.reject_inputs(0);
+            return;
+        }
 
         // if we did not receive any input yet, we decode with the blank input,
         // otherwise we use the input previous to the start of the encoded inputs
@@ -686,23 +1130,38 @@ impl<T: Config> UdpProtocol<T> {
 
         // if we have the necessary input saved, we decode
         if let Some(decode_inp) = self.recv_inputs.get(&decode_frame) {
-            self.running_last_input_recv = Instant::now();
+            self.running_last_input_recv = self.clock.now();
 
-            let recv_inputs = decode(&decode_inp.bytes, &body.bytes).expect("decoding failed");
+            let Ok(recv_inputs) = decode(&decode_inp.bytes, &body.bytes, MAX_INPUTS_PER_MESSAGE)
+            else {
+                self.reject_inputs(0);
+                return;
+            };
 
+            let num_inputs = recv_inputs.len();
             for (i, inp) in recv_inputs.into_iter().enumerate() {
                 let inp_frame = body.start_frame + i as i32;
-                // skip inputs that we don't need
+                // skip inputs we already received, they have been retransmitted because our ack did not arrive in time
                 if inp_frame <= self.last_recv_frame() {
+                    self.redundant_inputs += 1;
                     continue;
                 }
+                // reject inputs too far in the future, they will be resent since we don't acknowledge them
+                if inp_frame > self.max_input_frame {
+                    self.reject_inputs(num_inputs - i);
+                    break;
+                }
 
                 let input_data = InputBytes {
                     frame: inp_frame,
                     bytes: inp,
                 };
+                // reject inputs that cannot be deserialized, together with the rest of the message since inputs are received in order
+                let Ok(player_inputs) = input_data.to_player_inputs::<T>(self.handles.len()) else {
+                    self.reject_inputs(num_inputs - i);
+                    break;
+                };
                 // send the input to the session
-                let player_inputs = input_data.to_player_inputs::<T>(self.handles.len());
                 self.recv_inputs.insert(input_data.frame, input_data);
 
                 for (i, player_input) in player_inputs.into_iter().enumerate() {
@@ -723,6 +1182,23 @@ impl<T: Config> UdpProtocol<T> {
         }
     }
 
+    /// Counts inputs of a message that have been rejected and reports the remote client once this happens repeatedly.
+    fn reject_inputs(&mut self, num_inputs: usize) {
+        self.inputs_rejected += num_inputs;
+        self.rejected_input_messages += 1;
+        if self.rejected_input_messages >= SUSPICIOUS_INPUT_MESSAGES && !self.suspicious_event_sent
+        {
+            warn!(
+                "Peer {:?} repeatedly sent inputs that have been rejected",
+                self.peer_addr
+            );
+            self.event_queue.push_back(Event::SuspiciousPeer {
+                rejected_inputs: self.inputs_rejected,
+            });
+            self.suspicious_event_sent = true;
+        }
+    }
+
     /// Upon receiving a `InputAck`, discard the oldest buffered input including the acked input.
     fn on_input_ack(&mut self, body: InputAck) {
         self.pop_pending_output(body.ack_frame);
@@ -737,7 +1213,7 @@ impl<T: Config> UdpProtocol<T> {
 
     /// Upon receiving a `QualityReply`, update network stats.
     fn on_quality_reply(&mut self, body: &QualityReply) {
-        let millis = millis_since_epoch();
+        let millis = self.millis_since_start();
         assert!(millis >= body.pong);
         self.round_trip_time = millis - body.pong;
     }
@@ -761,7 +1237,7 @@ impl<T: Config> UdpProtocol<T> {
             self.pending_checksums
                 .retain(|&frame, _| frame >= oldest_frame_to_keep);
         }
-        self.pending_checksums.insert(body.frame, body.checksum);
+        self.pending_checksums.insert(body.frame, *body);
     }
 
     /// Returns the frame of the last received input
@@ -772,11 +1248,125 @@ impl<T: Config> UdpProtocol<T> {
         }
     }
 
-    pub(crate) fn send_checksum_report(&mut self, frame_to_send: Frame, checksum: u128) {
+    pub(crate) fn send_checksum_report(
+        &mut self,
+        frame_to_send: Frame,
+        checksum: u128,
+        input_checksum: u128,
+    ) {
         let body = ChecksumReport {
             frame: frame_to_send,
             checksum,
+            input_checksum,
         };
         self.queue_message(MessageBody::ChecksumReport(body));
     }
 }
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod protocol_tests {
+    use super::*;
+    use crate::MockClock;
+    use serde::{Deserialize, Serialize};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    /// An input that not every byte can be deserialized into.
+    #[derive(Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+    struct TestInput {
+        pressed: bool,
+    }
+
+    struct TestConfig;
+
+    impl Config for TestConfig {
+        type Input = TestInput;
+        type State = u8;
+        type Address = SocketAddr;
+    }
+
+    fn remote_endpoint() -> UdpProtocol<TestConfig> {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+        UdpProtocol::new(
+            vec![0, 1],
+            addr,
+            2,
+            0,
+            8,
+            Duration::from_millis(2000),
+            Duration::from_millis(500),
+            60,
+            DesyncDetection::Off,
+            0,
+            None,
+            None,
+            Arc::new(MockClock::new()),
+        )
+    }
+
+    fn input_message(
+        sequence: u16,
+        peer_connect_status: Vec<ConnectionStatus>,
+        bytes: Vec<u8>,
+    ) -> Message {
+        Message {
+            header: MessageHeader { magic: 1, sequence },
+            body: MessageBody::Input(Input {
+                peer_connect_status,
+                disconnect_requested: false,
+                start_frame: 0,
+                ack_frame: NULL_FRAME,
+                bytes,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_malformed_inputs_are_rejected() {
+        let mut endpoint = remote_endpoint();
+        let statuses = vec![ConnectionStatus::default(); 2];
+        let malformed = [
+            // decodes into a byte that is not a valid bool
+            input_message(0, statuses.clone(), encode(&[0, 0], [vec![2, 0]].iter())),
+            // decodes into a number of bytes that doesn't split into inputs
+            input_message(
+                1,
+                statuses.clone(),
+                encode(&[0, 0, 0], [vec![1, 1, 1]].iter()),
+            ),
+            // cannot be decoded at all
+            input_message(2, statuses.clone(), vec![0xff; 3]),
+            // holds the connection status of the wrong number of players
+            input_message(
+                3,
+                vec![ConnectionStatus::default(); 1],
+                encode(&[0, 0], [vec![1, 0]].iter()),
+            ),
+        ];
+        for msg in &malformed {
+            endpoint.handle_message(msg);
+        }
+        assert_eq!(endpoint.rejected_input_messages, malformed.len());
+        assert_eq!(endpoint.inputs_rejected, 1);
+        assert_eq!(endpoint.last_recv_frame(), NULL_FRAME);
+        assert!(!endpoint
+            .event_queue
+            .iter()
+            .any(|event| matches!(event, Event::Input { .. })));
+
+        // a valid input is accepted afterwards
+        endpoint.handle_message(&input_message(
+            4,
+            statuses,
+            encode(&[0, 0], [vec![1, 0]].iter()),
+        ));
+        assert_eq!(endpoint.last_recv_frame(), 0);
+        assert!(endpoint.event_queue.iter().any(|event| matches!(
+            event,
+            Event::Input { input, player: 0 } if input.input.pressed
+        )));
+    }
+}
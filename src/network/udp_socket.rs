@@ -80,4 +80,9 @@ impl NonBlockingSocket<SocketAddr> for UdpNonBlockingSocket {
             }
         }
     }
+
+    /// Returns the address the socket is bound to. The IP address is unspecified (0.0.0.0), since the socket listens on all interfaces.
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
 }
@@ -1,11 +1,18 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 use instant::Duration;
 
 use crate::{
-    network::protocol::UdpProtocol, sessions::p2p_session::PlayerRegistry, Config, DesyncDetection,
-    GgrsError, NonBlockingSocket, P2PSession, PlayerHandle, PlayerType, SpectatorSession,
-    SyncTestSession,
+    input_queue::INPUT_QUEUE_LENGTH,
+    network::{
+        bit_packing,
+        protocol::{EndpointConfig, UdpProtocol},
+    },
+    sessions::p2p_session::PlayerRegistry,
+    Clock, Config, DesyncDetection, GgrsError, MissingLocalInput, NonBlockingSocket, P2PSession,
+    PlayerHandle, PlayerType, SpectatorSession, SyncTestSession, SystemClock, UdpNonBlockingSocket,
 };
 
 use super::p2p_spectator_session::SPECTATOR_BUFFER_SIZE;
@@ -23,11 +30,63 @@ const DEFAULT_CHECK_DISTANCE: usize = 2;
 const DEFAULT_MAX_FRAMES_BEHIND: usize = 10;
 // The amount of frames the spectator advances in a single step if too far behind
 const DEFAULT_CATCHUP_SPEED: usize = 1;
+// Confirmed inputs are discarded right away unless requested otherwise
+const DEFAULT_SPECTATOR_BUFFER_FRAMES: usize = 0;
+const DEFAULT_PROTOCOL_ID: u64 = 0;
+// A misprediction rate can never exceed 1, so the session never stalls because of mispredictions
+const DEFAULT_MISPREDICTION_STALL_THRESHOLD: f32 = 1.0;
+// Inputs are sent every frame
+const DEFAULT_SEND_INTERVAL_FRAMES: usize = 1;
 // The amount of events a spectator can buffer; should never be an issue if the user polls the events at every step
 pub(crate) const MAX_EVENT_QUEUE_SIZE: usize = 100;
 
+// Per default, a later local input for the same frame replaces the earlier one
+fn replace_input<I>(_previous: I, new: I) -> I {
+    new
+}
+
 /// The [`SessionBuilder`] builds all GGRS Sessions. After setting all appropriate values, use `SessionBuilder::start_yxz_session(...)`
 /// to consume the builder and create a Session of desired type.
+///
+/// # Session lifecycle
+///
+/// A session is not started before it is built, runs from the moment it is returned by one of the `start_xyz_session(...)`
+/// functions, and ends when it is dropped. A [`P2PSession`] additionally ends for good once it reaches [`SessionState::Failed`].
+/// Since starting a session consumes the builder, the same configuration can never be started twice, and a session that has
+/// ended can never be restarted:
+///
+/// ```compile_fail
+/// # use ggrs::{Config, SessionBuilder};
+/// # struct MyConfig;
+/// # impl Config for MyConfig {
+/// #     type Input = u8;
+/// #     type State = u8;
+/// #     type Address = std::net::SocketAddr;
+/// # }
+/// let builder = SessionBuilder::<MyConfig>::new();
+/// let session = builder.start_synctest_session();
+/// // compile error: the builder has been moved into the first session
+/// let second_session = builder.start_synctest_session();
+/// ```
+///
+/// To start over after a session has ended, build a new session with a new builder:
+///
+/// ```
+/// # use ggrs::{Config, SessionBuilder};
+/// # struct MyConfig;
+/// # impl Config for MyConfig {
+/// #     type Input = u8;
+/// #     type State = u8;
+/// #     type Address = std::net::SocketAddr;
+/// # }
+/// let session = SessionBuilder::<MyConfig>::new().start_synctest_session().unwrap();
+/// drop(session);
+/// let session = SessionBuilder::<MyConfig>::new().start_synctest_session().unwrap();
+/// assert_eq!(session.current_frame(), 0);
+/// ```
+///
+/// [`P2PSession`]: crate::P2PSession
+/// [`SessionState::Failed`]: crate::SessionState::Failed
 #[derive(Debug)]
 pub struct SessionBuilder<T>
 where
@@ -49,6 +108,21 @@ where
     check_dist: usize,
     max_frames_behind: usize,
     catchup_speed: usize,
+    spectator_buffer_frames: usize,
+    protocol_id: u64,
+    input_merge: fn(T::Input, T::Input) -> T::Input,
+    max_saved_frames: Option<usize>,
+    clock: Arc<dyn Clock>,
+    misprediction_stall_threshold: f32,
+    sync_timeout: Option<Duration>,
+    max_sync_retries: Option<usize>,
+    checksums: bool,
+    rollback_budget: Option<usize>,
+    input_queue_lengths: HashMap<PlayerHandle, usize>,
+    missing_local_input: MissingLocalInput,
+    send_interval_frames: usize,
+    packet_log: bool,
+    rollback_events: bool,
 }
 
 impl<T: Config> Default for SessionBuilder<T> {
@@ -74,6 +148,21 @@ impl<T: Config> SessionBuilder<T> {
             check_dist: DEFAULT_CHECK_DISTANCE,
             max_frames_behind: DEFAULT_MAX_FRAMES_BEHIND,
             catchup_speed: DEFAULT_CATCHUP_SPEED,
+            spectator_buffer_frames: DEFAULT_SPECTATOR_BUFFER_FRAMES,
+            protocol_id: DEFAULT_PROTOCOL_ID,
+            input_merge: replace_input,
+            max_saved_frames: None,
+            clock: Arc::new(SystemClock),
+            misprediction_stall_threshold: DEFAULT_MISPREDICTION_STALL_THRESHOLD,
+            sync_timeout: None,
+            max_sync_retries: None,
+            checksums: true,
+            rollback_budget: None,
+            input_queue_lengths: HashMap::new(),
+            missing_local_input: MissingLocalInput::Error,
+            send_interval_frames: DEFAULT_SEND_INTERVAL_FRAMES,
+            packet_log: false,
+            rollback_events: false,
         }
     }
 
@@ -146,7 +235,14 @@ impl<T: Config> SessionBuilder<T> {
         self
     }
 
-    /// Change the amount of frames GGRS will delay the inputs for local players.
+    /// Change the amount of frames GGRS will delay the inputs for local players. Default is 0.
+    ///
+    /// Delayed inputs occupy the input queues, which hold 128 frames per player unless set otherwise with
+    /// [`with_input_queue_length()`]. The input delay and the prediction window (plus the spectator buffer frames of a [`P2PSession`])
+    /// combined must be smaller than that, so with the default queue length and prediction window of 8 frames, the maximum input
+    /// delay is 119 frames. Starting a session with a larger input delay fails.
+    ///
+    /// [`with_input_queue_length()`]: Self#method.with_input_queue_length
     pub fn with_input_delay(mut self, delay: usize) -> Self {
         self.input_delay = delay;
         self
@@ -163,11 +259,89 @@ impl<T: Config> SessionBuilder<T> {
     /// much less save requests at the cost of potentially longer rollbacks and thus more advance
     /// frame requests. Recommended, if saving your gamestate takes much more time than advancing
     /// the game state.
+    ///
+    /// Predicted states are never saved in this mode; a rollback always loads the confirmed state and
+    /// resimulates every frame up to the current one. Dense saving (the default) saves every frame, so it
+    /// needs a save per frame and keeps up to a full prediction window of states with actual content,
+    /// while sparse saving keeps a single up-to-date state and trades that for CPU time spent resimulating.
+    /// Both modes produce the same gamestates.
     pub fn with_sparse_saving_mode(mut self, sparse_saving: bool) -> Self {
         self.sparse_saving = sparse_saving;
         self
     }
 
+    /// Lets GGRS choose the saving mode from the relative costs of saving and loading your gamestate, in arbitrary units
+    /// (e.g. microseconds you measured). If saving is more expensive than loading, sparse saving is used, since it saves
+    /// only confirmed frames and makes up for it by loading and resimulating more frames on rollback. Otherwise, every
+    /// frame is saved densely. This overrides [`with_sparse_saving_mode()`] and vice versa, whichever is called last wins.
+    /// Use [`P2PSession::in_sparse_saving_mode()`] to check the chosen mode.
+    ///
+    /// [`with_sparse_saving_mode()`]: Self#method.with_sparse_saving_mode
+    /// [`P2PSession::in_sparse_saving_mode()`]: crate::P2PSession#method.in_sparse_saving_mode
+    pub fn with_state_cost_hints(mut self, save_cost: u32, load_cost: u32) -> Self {
+        self.sparse_saving = save_cost > load_cost;
+        self
+    }
+
+    /// Enables or disables checksums for the [`P2PSession`]. With checksums disabled, GGRS discards all checksums given to
+    /// [`GameStateCell::save()`] and [`GameStateCell::checksums_enabled()`] returns false, so you can skip hashing your
+    /// gamestate every frame. The tradeoff is that you lose desync detection, so this can't be combined with
+    /// [`DesyncDetection::On`]. Checksums are enabled by default. They are always enabled in a [`SyncTestSession`],
+    /// since comparing checksums is its whole purpose.
+    ///
+    /// [`GameStateCell::save()`]: crate::GameStateCell#method.save
+    /// [`GameStateCell::checksums_enabled()`]: crate::GameStateCell#method.checksums_enabled
+    pub fn with_checksums(mut self, enabled: bool) -> Self {
+        self.checksums = enabled;
+        self
+    }
+
+    /// Sets what a [`P2PSession`] does when `advance_frame()` is called without input for one of the local players,
+    /// e.g. when the input thread of your game hiccups. Per default, `advance_frame()` returns an error.
+    pub fn with_missing_local_input(mut self, policy: MissingLocalInput) -> Self {
+        self.missing_local_input = policy;
+        self
+    }
+
+    /// Sets how many frames of local input a [`P2PSession`] collects before sending them to the remote clients in a single packet.
+    /// The session still simulates every frame. Sending inputs every few frames reduces the packet overhead, but remote clients
+    /// receive inputs up to `frames - 1` frames later, so they have to predict further ahead and roll back more often, just as
+    /// with a worse connection. Must not exceed the prediction window, or both sides would wait for each other's batches.
+    /// The default is 1, i.e. inputs are sent every frame.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the interval is 0.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_send_interval_frames(mut self, frames: usize) -> Result<Self, GgrsError> {
+        if frames == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "The send interval must be at least one frame.".to_owned(),
+            });
+        }
+        self.send_interval_frames = frames;
+        Ok(self)
+    }
+
+    /// Enables or disables recording the packets a [`P2PSession`] sends and receives, to debug connection issues like packet loss
+    /// or NAT traversal. The metadata of each packet is kept until it is drained with [`P2PSession::drain_packet_events()`].
+    /// Recording is disabled by default, since it costs some time for every packet.
+    ///
+    /// [`P2PSession::drain_packet_events()`]: crate::P2PSession#method.drain_packet_events
+    pub fn with_packet_log(mut self, enabled: bool) -> Self {
+        self.packet_log = enabled;
+        self
+    }
+
+    /// Enables or disables [`GgrsEvent::Rollback`] events, which tell a [`P2PSession`] which frames have been resimulated.
+    /// With frequent rollbacks, these events would crowd out more important events from the bounded event queue, so they are
+    /// disabled by default. Consecutive rollbacks that have not been polled yet are merged into a single event.
+    ///
+    /// [`GgrsEvent::Rollback`]: crate::GgrsEvent#variant.Rollback
+    pub fn with_rollback_events(mut self, enabled: bool) -> Self {
+        self.rollback_events = enabled;
+        self
+    }
+
     /// Sets the desync detection mode. With desync detection, the session will compare checksums for all peers to detect discrepancies / desyncs between peers
     /// If a desync is found the session will send a DesyncDetected event.
     pub fn with_desync_detection_mode(mut self, desync_detection: DesyncDetection) -> Self {
@@ -202,7 +376,8 @@ impl<T: Config> SessionBuilder<T> {
         Ok(self)
     }
 
-    /// Change the check distance. Default is 2.
+    /// Change the check distance. Default is 2. The check distance must be smaller than the prediction window,
+    /// since only that many gamestates are saved at a time.
     pub fn with_check_distance(mut self, check_distance: usize) -> Self {
         self.check_dist = check_distance;
         self
@@ -247,15 +422,209 @@ impl<T: Config> SessionBuilder<T> {
         Ok(self)
     }
 
+    /// Sets the amount of frames a [`P2PSession`] keeps confirmed inputs around after they have been confirmed. Per default, this is 0
+    /// and confirmed inputs are discarded as soon as possible. Retaining inputs for longer gives spectators that are relayed through
+    /// this session more room to lag behind before their inputs are lost.
+    ///
+    /// Input queues are allocated up front with a length of 128 frames per player, or the length set with [`with_input_queue_length()`],
+    /// so this does not cost additional memory.
+    /// Instead, every retained frame takes up a slot that would otherwise be available for predicted or delayed inputs.
+    /// Starting the session fails if the buffer, the prediction window and the input delay don't fit into the input queue together.
+    ///
+    /// [`with_input_queue_length()`]: Self#method.with_input_queue_length
+    pub fn with_spectator_buffer_frames(mut self, frames: usize) -> Self {
+        self.spectator_buffer_frames = frames;
+        self
+    }
+
+    /// Sets an application-defined protocol id. During synchronization, peers exchange their protocol ids and refuse to synchronize
+    /// with peers that use a different id (or a different version of GGRS' network protocol). Instead, a [`GgrsEvent::IncompatiblePeer`]
+    /// event is emitted. Default is 0.
+    ///
+    /// You should change the id whenever your input format or game logic changes in a way that makes older clients incompatible,
+    /// e.g. by deriving it from your game's version.
+    ///
+    /// [`GgrsEvent::IncompatiblePeer`]: crate::GgrsEvent::IncompatiblePeer
+    pub fn with_protocol_id(mut self, protocol_id: u64) -> Self {
+        self.protocol_id = protocol_id;
+        self
+    }
+
+    /// Limits the number of gamestates kept around for rollbacks. Per default, GGRS saves one gamestate per frame in the prediction
+    /// window plus the current frame, so it can always roll back as far as it may need to. On memory-constrained targets, you can
+    /// save fewer frames instead, accepting that deeper rollbacks become impossible: they make [`advance_frame()`] return
+    /// [`GgrsError::StateNotSaved`]. In a [`SyncTestSession`], the check distance must be smaller than the number of saved frames.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the number of frames is 0
+    ///
+    /// [`advance_frame()`]: P2PSession#method.advance_frame
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_max_saved_frames(mut self, frames: usize) -> Result<Self, GgrsError> {
+        if frames == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "At least one frame needs to be saved.".to_owned(),
+            });
+        }
+        self.max_saved_frames = Some(frames);
+        Ok(self)
+    }
+
+    /// Sets how multiple local inputs given for the same player and frame are combined. Per default, a later call to `add_local_input()`
+    /// replaces the input of an earlier call. With a merge function, the input registered so far and the new input are combined as
+    /// `merge(previous, new)` instead, e.g. to OR together button bitfields that are polled several times per frame.
+    ///
+    /// Whether merging makes sense depends on your input encoding: OR-ing bits is fine for buttons, but wrong for signed analog values.
+    pub fn with_input_merge(mut self, merge: fn(T::Input, T::Input) -> T::Input) -> Self {
+        self.input_merge = merge;
+        self
+    }
+
+    /// Sets the maximum time the synchronization with a remote client may take. If the handshake has not completed by then,
+    /// the session emits [`GgrsEvent::SynchronizationFailed`] and enters the terminal [`SessionState::Failed`].
+    /// Per default, the session keeps trying to synchronize forever.
+    ///
+    /// [`GgrsEvent::SynchronizationFailed`]: crate::GgrsEvent::SynchronizationFailed
+    /// [`SessionState::Failed`]: crate::SessionState::Failed
+    pub fn with_sync_timeout(mut self, timeout: Duration) -> Self {
+        self.sync_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how often an unanswered synchronization request is resent before giving up. The time between retries starts at 200ms
+    /// and doubles with every retry, up to 1.6s; every answered request resets both. After the last retry has gone unanswered,
+    /// the session emits [`GgrsEvent::SynchronizationFailed`] and enters the terminal [`SessionState::Failed`].
+    /// Per default, requests are retried forever.
+    ///
+    /// [`GgrsEvent::SynchronizationFailed`]: crate::GgrsEvent::SynchronizationFailed
+    /// [`SessionState::Failed`]: crate::SessionState::Failed
+    pub fn with_sync_retries(mut self, retries: usize) -> Self {
+        self.max_sync_retries = Some(retries);
+        self
+    }
+
+    /// Sets the [`Clock`] that drives all timers of the network protocol, like the disconnect timeout and the resending of packets.
+    /// Per default, the [`SystemClock`] is used. Pass a [`MockClock`] to let time pass deterministically in tests.
+    ///
+    /// [`MockClock`]: crate::MockClock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets the share of recent mispredictions (between 0 and 1) above which a [`P2PSession`] stops predicting the input of a remote player.
+    /// When the input of a player is mispredicted most of the time, e.g. because of a bad connection, predicting only leads to more rollbacks.
+    /// Above the threshold, the session waits for the actual input of that player instead and skips advancing the frame until it arrives,
+    /// just like when the prediction window is exhausted. The current rate of each player is reported in [`NetworkStats`].
+    /// Per default, the threshold is 1, so the session always predicts.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the threshold is not between 0 and 1
+    ///
+    /// [`NetworkStats`]: crate::NetworkStats
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_misprediction_stall_threshold(mut self, threshold: f32) -> Result<Self, GgrsError> {
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(GgrsError::InvalidRequest {
+                info: "The misprediction stall threshold should be between 0 and 1.".to_owned(),
+            });
+        }
+        self.misprediction_stall_threshold = threshold;
+        Ok(self)
+    }
+
+    /// Sets the number of frames a single rollback of a [`P2PSession`] may resimulate before it is reported as too deep. Deeper
+    /// rollbacks log a warning and are counted in [`P2PSession::rollbacks_over_budget()`], so you can find out whether rollbacks
+    /// stall your game loop. Set this to the number of frames you can resimulate within a frame time.
+    ///
+    /// GGRS always resimulates a rollback at once instead of splitting it across several calls to `advance_frame()`. Until the
+    /// resimulation has finished, the game would show outdated states, which adds the same latency a larger input delay does.
+    /// To keep rollbacks short, reduce the prediction window with [`with_max_prediction_window()`] instead.
+    /// Per default, there is no budget.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the budget is 0.
+    ///
+    /// [`P2PSession::rollbacks_over_budget()`]: crate::P2PSession#method.rollbacks_over_budget
+    /// [`with_max_prediction_window()`]: Self#method.with_max_prediction_window
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn with_rollback_budget(mut self, frames: usize) -> Result<Self, GgrsError> {
+        if frames == 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "The rollback budget must be at least one frame.".to_owned(),
+            });
+        }
+        self.rollback_budget = Some(frames);
+        Ok(self)
+    }
+
+    /// Sets the number of inputs GGRS keeps for the given player in a [`P2PSession`], instead of the default of 128.
+    /// Inputs of a player are kept until they are confirmed and, with [`with_spectator_buffer_frames()`], a while longer,
+    /// so players whose inputs are relayed to spectators may need a longer queue, while other players can do with less memory.
+    /// The spectator buffer, prediction window and input delay combined must be smaller than the length,
+    /// which is checked when starting the session. Inputs of a remote player for frames further ahead of the current frame
+    /// than the length are rejected, see [`GgrsEvent::SuspiciousPeer`].
+    ///
+    /// [`with_spectator_buffer_frames()`]: Self#method.with_spectator_buffer_frames
+    /// [`GgrsEvent::SuspiciousPeer`]: crate::GgrsEvent::SuspiciousPeer
+    pub fn with_input_queue_length(mut self, player_handle: PlayerHandle, length: usize) -> Self {
+        self.input_queue_lengths.insert(player_handle, length);
+        self
+    }
+
     /// Consumes the builder to construct a [`P2PSession`] and starts synchronization of endpoints.
+    /// If all players are local, there is nothing to synchronize and the session is running right away without any network traffic,
+    /// so the same code path can be used for single player and multiplayer games.
     /// # Errors
     /// - Returns [`InvalidRequest`] if insufficient players have been registered.
+    /// - Returns [`InvalidRequest`] if the spectator buffer, prediction window and input delay exceed the input queue length of a player.
+    /// - Returns [`InvalidRequest`] if an input queue length has been set for a handle that is not a player.
+    /// - Returns [`InvalidRequest`] if the send interval exceeds the prediction window.
+    /// - Returns [`InvalidRequest`] if desync detection is turned on while checksums are disabled.
+    /// - Returns [`InvalidRequest`] if [`Config::INPUT_BITS`] is not between 1 and 64, or the default input does not fit into it.
     ///
     /// [`InvalidRequest`]: GgrsError::InvalidRequest
     pub fn start_p2p_session(
         mut self,
         socket: impl NonBlockingSocket<T::Address> + 'static,
     ) -> Result<P2PSession<T>, GgrsError> {
+        // check if the retained inputs fit into the input queues
+        if let Some(handle) = self
+            .input_queue_lengths
+            .keys()
+            .find(|handle| **handle >= self.num_players)
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: format!("Input queue length set for handle {handle}, which is not a player."),
+            });
+        }
+        for player_handle in 0..self.num_players {
+            let length = self.input_queue_length(player_handle);
+            if self.spectator_buffer_frames + self.max_prediction + self.input_delay >= length {
+                return Err(GgrsError::InvalidRequest {
+                    info: format!(
+                        "Spectator buffer frames, max prediction and input delay combined must be smaller than the input queue length of player {player_handle} ({length}).",
+                    ),
+                });
+            }
+        }
+
+        // in lockstep mode, inputs have to be sent every frame
+        if self.send_interval_frames > self.max_prediction.max(1) {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "The send interval ({}) must not exceed the prediction window ({}).",
+                    self.send_interval_frames, self.max_prediction
+                ),
+            });
+        }
+
+        bit_packing::check_config::<T>()?;
+
+        // desync detection compares checksums
+        if !self.checksums && matches!(self.desync_detection, DesyncDetection::On { .. }) {
+            return Err(GgrsError::InvalidRequest {
+                info: "Desync detection requires checksums to be enabled.".to_owned(),
+            });
+        }
+
         // check if all players are added
         for player_handle in 0..self.num_players {
             if !self.player_reg.handles.contains_key(&player_handle) {
@@ -296,6 +665,7 @@ impl<T: Config> SessionBuilder<T> {
             }
         }
 
+        let endpoint_config = self.endpoint_config();
         Ok(P2PSession::<T>::new(
             self.num_players,
             self.max_prediction,
@@ -304,6 +674,17 @@ impl<T: Config> SessionBuilder<T> {
             self.sparse_saving,
             self.desync_detection,
             self.input_delay,
+            self.spectator_buffer_frames,
+            self.input_merge,
+            self.max_saved_frames,
+            self.misprediction_stall_threshold,
+            self.checksums,
+            endpoint_config,
+            self.rollback_budget,
+            self.input_queue_lengths,
+            self.missing_local_input,
+            self.packet_log,
+            self.rollback_events,
         ))
     }
 
@@ -311,11 +692,17 @@ impl<T: Config> SessionBuilder<T> {
     /// A [`SpectatorSession`] provides all functionality to connect to a remote host in a peer-to-peer fashion.
     /// The host will broadcast all confirmed inputs to this session.
     /// This session can be used to spectate a session without contributing to the game input.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if [`Config::INPUT_BITS`] is not between 1 and 64, or the default input does not fit into it.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
     pub fn start_spectator_session(
         self,
         host_addr: T::Address,
         socket: impl NonBlockingSocket<T::Address> + 'static,
-    ) -> SpectatorSession<T> {
+    ) -> Result<SpectatorSession<T>, GgrsError> {
+        bit_packing::check_config::<T>()?;
+
         // create host endpoint
         let mut host = UdpProtocol::new(
             (0..self.num_players).collect(),
@@ -327,15 +714,19 @@ impl<T: Config> SessionBuilder<T> {
             self.disconnect_notify_start,
             self.fps,
             DesyncDetection::Off,
+            self.protocol_id,
+            self.sync_timeout,
+            self.max_sync_retries,
+            self.clock.clone(),
         );
         host.synchronize();
-        SpectatorSession::new(
+        Ok(SpectatorSession::new(
             self.num_players,
             Box::new(socket),
             host,
             self.max_frames_behind,
             self.catchup_speed,
-        )
+        ))
     }
 
     /// Consumes the builder to construct a new [`SyncTestSession`]. During a [`SyncTestSession`], GGRS will simulate a rollback every frame
@@ -344,40 +735,101 @@ impl<T: Config> SessionBuilder<T> {
     /// Due to the decentralized nature of saving and loading gamestates, checksum comparisons can only be made if `check_distance` is 2 or higher.
     /// This is a great way to test if your system runs deterministically.
     /// After creating the session, add a local player, set input delay for them and then start the session.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the prediction window and input delay exceed the input queue length.
+    /// - Returns [`InvalidRequest`] if the check distance is not smaller than the prediction window or the number of saved frames.
+    /// - Returns [`InvalidRequest`] if [`Config::INPUT_BITS`] is not between 1 and 64, or the default input does not fit into it.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
     pub fn start_synctest_session(self) -> Result<SyncTestSession<T>, GgrsError> {
+        bit_packing::check_config::<T>()?;
+
+        // check if the delayed inputs fit into the input queues
+        if self.max_prediction + self.input_delay >= INPUT_QUEUE_LENGTH {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "Max prediction and input delay combined must be smaller than the input queue length ({}).",
+                    INPUT_QUEUE_LENGTH
+                ),
+            });
+        }
+        // rollbacks can't reach further back than the saved gamestates
         if self.check_dist >= self.max_prediction {
             return Err(GgrsError::InvalidRequest {
-                info: "Check distance too big.".to_owned(),
+                info: format!(
+                    "Check distance too big. It must be smaller than the prediction window ({}), but is {}.",
+                    self.max_prediction, self.check_dist
+                ),
             });
         }
+        if let Some(max_saved_frames) = self.max_saved_frames {
+            if self.check_dist >= max_saved_frames {
+                return Err(GgrsError::InvalidRequest {
+                    info: format!(
+                        "Check distance too big. It must be smaller than the number of saved frames ({}), but is {}.",
+                        max_saved_frames, self.check_dist
+                    ),
+                });
+            }
+        }
         Ok(SyncTestSession::new(
             self.num_players,
             self.max_prediction,
             self.check_dist,
             self.input_delay,
+            self.input_merge,
+            self.max_saved_frames,
         ))
     }
 
+    fn input_queue_length(&self, player_handle: PlayerHandle) -> usize {
+        self.input_queue_lengths
+            .get(&player_handle)
+            .copied()
+            .unwrap_or(INPUT_QUEUE_LENGTH)
+    }
+
     fn create_endpoint(
         &self,
         handles: Vec<PlayerHandle>,
         peer_addr: T::Address,
         local_players: usize,
     ) -> UdpProtocol<T> {
-        // create the endpoint, set parameters
-        let mut endpoint = UdpProtocol::new(
-            handles,
-            peer_addr,
-            self.num_players,
-            local_players,
-            self.max_prediction,
-            self.disconnect_timeout,
-            self.disconnect_notify_start,
-            self.fps,
-            self.desync_detection,
-        );
-        // start the synchronization
-        endpoint.synchronize();
-        endpoint
+        UdpProtocol::from_config(handles, peer_addr, local_players, &self.endpoint_config())
+    }
+
+    fn endpoint_config(&self) -> EndpointConfig {
+        EndpointConfig {
+            num_players: self.num_players,
+            max_prediction: self.max_prediction,
+            disconnect_timeout: self.disconnect_timeout,
+            disconnect_notify_start: self.disconnect_notify_start,
+            fps: self.fps,
+            desync_detection: self.desync_detection,
+            protocol_id: self.protocol_id,
+            sync_timeout: self.sync_timeout,
+            max_sync_retries: self.max_sync_retries,
+            send_interval_frames: self.send_interval_frames,
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl<T: Config<Address = SocketAddr>> SessionBuilder<T> {
+    /// Binds a [`UdpNonBlockingSocket`] to the given port and consumes the builder to construct a [`P2PSession`] on it.
+    /// The port is bound right away, so an unavailable port is reported here instead of surfacing later as missing packets.
+    /// If you need control over the socket, bind it yourself and use [`start_p2p_session()`] instead.
+    /// # Errors
+    /// - Returns [`SocketError`] if the port cannot be bound, e.g. because it is already in use.
+    /// - Returns all errors of [`start_p2p_session()`].
+    ///
+    /// [`start_p2p_session()`]: Self#method.start_p2p_session
+    /// [`SocketError`]: GgrsError::SocketError
+    pub fn start_p2p_session_on_port(self, port: u16) -> Result<P2PSession<T>, GgrsError> {
+        let socket =
+            UdpNonBlockingSocket::bind_to_port(port).map_err(|err| GgrsError::SocketError {
+                info: format!("Could not bind UDP port {port}: {err}"),
+            })?;
+        self.start_p2p_session(socket)
     }
 }
@@ -1,21 +1,83 @@
 use crate::error::GgrsError;
 use crate::frame_info::PlayerInput;
-use crate::network::messages::ConnectionStatus;
+use crate::network::bit_packing;
+use crate::network::messages::{ChecksumReport, ConnectionStatus};
+use crate::network::network_sim::{NetworkSim, SimulatedSocket};
 use crate::network::network_stats::NetworkStats;
-use crate::network::protocol::{UdpProtocol, MAX_CHECKSUM_HISTORY_SIZE};
-use crate::sync_layer::SyncLayer;
+use crate::network::packet_log::{PacketDirection, PacketEvent, PacketLog};
+use crate::network::protocol::{EndpointConfig, UdpProtocol, MAX_CHECKSUM_HISTORY_SIZE};
+use crate::sync_layer::{GameStateCell, SyncLayer};
 use crate::DesyncDetection;
 use crate::{
-    network::protocol::Event, Config, Frame, GgrsEvent, GgrsRequest, NonBlockingSocket,
-    PlayerHandle, PlayerType, SessionState, NULL_FRAME,
+    network::protocol::Event, Capabilities, Config, DisconnectReason, Frame, GgrsEvent,
+    GgrsRequest, InputStatus, MissingLocalInput, NonBlockingSocket, PlayerHandle, PlayerType,
+    SessionState, NULL_FRAME,
 };
 use tracing::{debug, trace, warn};
 
+use std::collections::hash_map::Entry;
 use std::collections::vec_deque::Drain;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::convert::TryInto;
 
+use instant::{Duration, Instant};
+
+/// The number of recently confirmed frames for which a [`ConfirmationInfo`] is kept.
+const MAX_CONFIRMATION_HISTORY: usize = 128;
+/// The number of [`FrameEvent`]s kept until they are drained. Older events are dropped.
+const MAX_FRAME_EVENTS: usize = 256;
+/// How often buffers that grew beyond their usual size are shrunk again.
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A transition of a single frame between predicted and confirmed inputs, as returned by [`P2PSession::drain_frame_events()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameEvent {
+    /// The frame has been simulated with predicted inputs of at least one player.
+    Predicted {
+        /// The simulated frame.
+        frame: Frame,
+    },
+    /// The inputs of the frame turned out to be mispredicted, so the session rolled back. The frame and all frames after it
+    /// have been simulated again.
+    Corrected {
+        /// The first mispredicted frame.
+        frame: Frame,
+    },
+    /// The inputs of all players for the frame have been confirmed, so it will never be simulated again.
+    Confirmed {
+        /// The confirmed frame.
+        frame: Frame,
+    },
+}
+
+/// Describes when the inputs of all players for a frame became confirmed, as returned by [`P2PSession::confirmation_info()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfirmationInfo {
+    /// The point in time at which the inputs were confirmed, taken from the clock of the session.
+    pub confirmed_at: Instant,
+    /// The local frame the session was at when the inputs were confirmed.
+    pub confirmed_in_frame: Frame,
+    /// The number of frames the session had simulated past this frame with predicted inputs before they were confirmed.
+    pub prediction_frames: usize,
+}
+
+/// What the game loop should do in the current tick, as returned by [`P2PSession::should_advance()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvanceDecision {
+    /// Add local input and call [`P2PSession::advance_frame()`].
+    Advance,
+    /// Don't advance for the given number of frames. Either the session is too far ahead of the confirmed inputs and has to
+    /// wait for remote inputs, in which case `frames` is 1 and you should ask again next tick, or it runs ahead of the remote
+    /// clients and should give them time to catch up, like with [`GgrsEvent::WaitRecommendation`].
+    Stall {
+        /// The number of frames to skip.
+        frames: u32,
+    },
+    /// The session is not synchronized with the remote clients yet. Keep calling [`P2PSession::poll_remote_clients()`].
+    WaitForSync,
+}
+
 const RECOMMENDATION_INTERVAL: Frame = 60;
 const MIN_RECOMMENDATION: u32 = 3;
 const MAX_EVENT_QUEUE_SIZE: usize = 100;
@@ -114,6 +176,11 @@ impl<T: Config> PlayerRegistry<T> {
 }
 
 /// A [`P2PSession`] provides all functionality to connect to remote clients in a peer-to-peer fashion, exchange inputs and handle the gamestate by saving, loading and advancing.
+///
+/// Predicted frames are never confirmed on their own. Inputs are resent until the remote peer acknowledges them, so a lost
+/// packet only delays the confirmation of a frame. If the inputs of a remote player stop arriving altogether, the session keeps
+/// predicting until the prediction window is used up and then stops advancing, until either the inputs arrive or the player is
+/// disconnected by the disconnect timeout.
 pub struct P2PSession<T>
 where
     T: Config,
@@ -134,7 +201,8 @@ where
     state: SessionState,
 
     /// The [`P2PSession`] uses this socket to send and receive all messages for remote players.
-    socket: Box<dyn NonBlockingSocket<T::Address>>,
+    /// Outgoing messages pass through the network simulation, if one has been set.
+    socket: SimulatedSocket<T>,
     /// Handles players and their endpoints
     player_reg: PlayerRegistry<T>,
     /// This struct contains information about remote players, like connection status and the frame of last received input.
@@ -151,18 +219,60 @@ where
     event_queue: VecDeque<GgrsEvent<T>>,
     /// Contains all local inputs not yet sent into the system. This should have inputs for every local player before calling advance_frame
     local_inputs: HashMap<PlayerHandle, PlayerInput<T::Input>>,
+    /// Combines multiple local inputs given for the same player and frame
+    input_merge: fn(T::Input, T::Input) -> T::Input,
 
     /// With desync detection, the session will compare checksums for all peers to detect discrepancies / desyncs between peers
     desync_detection: DesyncDetection,
     /// Desync detection over the network
-    local_checksum_history: HashMap<Frame, u128>,
+    local_checksum_history: HashMap<Frame, ChecksumReport>,
+    /// Checksums of the confirmed inputs that led to the frames we will send a checksum for, by the frame of the checksum
+    local_input_checksums: HashMap<Frame, u128>,
+    /// The next confirmed frame whose inputs might have to be included in an input checksum
+    next_input_checksum_frame: Frame,
     /// The last frame we sent a checksum for
     last_sent_checksum_frame: Frame,
+
+    /// The number of rollbacks since the session started
+    num_rollbacks: usize,
+    /// The number of frames resimulated during the last call to `advance_frame()`
+    last_rollback_frames: usize,
+    /// The largest number of frames resimulated by a single rollback since the session started or the peak was reset
+    peak_rollback_frames: usize,
+    /// Above this share of recent mispredictions, the session waits for the input of a remote player instead of predicting it
+    misprediction_stall_threshold: f32,
+    /// The parameters to create endpoints for spectators added after the session started
+    endpoint_config: EndpointConfig,
+    /// The number of frames a single rollback may resimulate before it is reported
+    rollback_budget: Option<usize>,
+    /// The number of rollbacks that resimulated more frames than the budget allows
+    rollbacks_over_budget: usize,
+    /// When the inputs of recently confirmed frames became confirmed, oldest first
+    confirmation_history: VecDeque<(Frame, ConfirmationInfo)>,
+    /// The number of frames simulated with the confirmed inputs of all players since the session started
+    total_confirmed_frames: usize,
+    /// A gamestate saved on demand, independent of the saved states used for rollbacks
+    bookmark: Option<(Frame, GameStateCell<T::State>)>,
+    /// Transitions of frames between predicted and confirmed inputs since they were last drained
+    frame_events: VecDeque<FrameEvent>,
+    /// What to do when `advance_frame()` is called without input for a local player
+    missing_local_input: MissingLocalInput,
+    /// Local players whose input for the current frame has been filled in according to `missing_local_input`
+    pending_defaulted_players: Vec<PlayerHandle>,
+    /// Local players whose input for the last advanced frame has been filled in according to `missing_local_input`
+    defaulted_local_players: Vec<PlayerHandle>,
+    /// Metadata of sent and received packets since they were last drained, if recording is enabled
+    packet_log: Option<PacketLog<T::Address>>,
+    /// Whether to emit an event for every rollback
+    rollback_events: bool,
+    /// When buffers have last been shrunk
+    last_compaction: Instant,
 }
 
 impl<T: Config> P2PSession<T> {
     /// Creates a new [`P2PSession`] for players who participate on the game input. After creating the session, add local and remote players,
     /// set input delay for local players and then start the session. The session will use the provided socket.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         num_players: usize,
         max_prediction: usize,
@@ -171,6 +281,17 @@ impl<T: Config> P2PSession<T> {
         sparse_saving: bool,
         desync_detection: DesyncDetection,
         input_delay: usize,
+        spectator_buffer_frames: usize,
+        input_merge: fn(T::Input, T::Input) -> T::Input,
+        max_saved_frames: Option<usize>,
+        misprediction_stall_threshold: f32,
+        checksums: bool,
+        endpoint_config: EndpointConfig,
+        rollback_budget: Option<usize>,
+        input_queue_lengths: HashMap<PlayerHandle, usize>,
+        missing_local_input: MissingLocalInput,
+        packet_log: bool,
+        rollback_events: bool,
     ) -> Self {
         // local connection status
         let mut local_connect_status = Vec::new();
@@ -180,6 +301,14 @@ impl<T: Config> P2PSession<T> {
 
         // sync layer & set input delay
         let mut sync_layer = SyncLayer::new(num_players, max_prediction);
+        sync_layer.set_spectator_buffer_frames(spectator_buffer_frames);
+        if let Some(frames) = max_saved_frames {
+            sync_layer.set_max_saved_frames(frames);
+        }
+        sync_layer.set_checksums_enabled(checksums);
+        for (player_handle, length) in input_queue_lengths {
+            sync_layer.set_input_queue_length(player_handle, length);
+        }
         for (player_handle, player_type) in players.handles.iter() {
             if let PlayerType::Local = player_type {
                 sync_layer.set_frame_delay(*player_handle, input_delay);
@@ -206,6 +335,9 @@ impl<T: Config> P2PSession<T> {
             sparse_saving
         };
 
+        let socket = SimulatedSocket::new(socket, endpoint_config.clock.clone());
+        let last_compaction = endpoint_config.clock.now();
+
         Self {
             state,
             num_players,
@@ -221,19 +353,46 @@ impl<T: Config> P2PSession<T> {
             player_reg: players,
             event_queue: VecDeque::new(),
             local_inputs: HashMap::new(),
+            input_merge,
             desync_detection,
             local_checksum_history: HashMap::new(),
+            local_input_checksums: HashMap::new(),
+            next_input_checksum_frame: 0,
             last_sent_checksum_frame: NULL_FRAME,
+            num_rollbacks: 0,
+            last_rollback_frames: 0,
+            peak_rollback_frames: 0,
+            misprediction_stall_threshold,
+            endpoint_config,
+            rollback_budget,
+            rollbacks_over_budget: 0,
+            confirmation_history: VecDeque::with_capacity(MAX_CONFIRMATION_HISTORY),
+            total_confirmed_frames: 0,
+            bookmark: None,
+            frame_events: VecDeque::with_capacity(MAX_FRAME_EVENTS),
+            missing_local_input,
+            pending_defaulted_players: Vec::new(),
+            defaulted_local_players: Vec::new(),
+            packet_log: packet_log.then(PacketLog::new),
+            rollback_events,
+            last_compaction,
         }
     }
 
     /// Registers local input for a player for the current frame. This should be successfully called for every local player before calling [`advance_frame()`].
-    /// If this is called multiple times for the same player before advancing the frame, older given inputs will be overwritten.
+    /// If this is called multiple times for the same player before advancing the frame, older given inputs will be overwritten,
+    /// unless a merge function has been set with [`SessionBuilder::with_input_merge()`].
+    ///
+    /// With several local players, the order of the calls for different players does not matter: their inputs are kept apart and
+    /// combined in ascending order of their player handles, both for the session and for the inputs sent to remote clients.
+    /// Merging only ever applies to inputs of the same player.
     ///
     /// # Errors
     /// - Returns [`InvalidRequest`] when the given handle does not refer to a local player.
+    /// - Returns [`InvalidRequest`] when the input does not fit into [`Config::INPUT_BITS`].
     ///
     /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`SessionBuilder::with_input_merge()`]: crate::SessionBuilder::with_input_merge
     /// [`InvalidRequest`]: GgrsError::InvalidRequest
     pub fn add_local_input(
         &mut self,
@@ -251,8 +410,15 @@ impl<T: Config> P2PSession<T> {
                     .to_owned(),
             });
         }
+        let input = match self.local_inputs.get(&player_handle) {
+            Some(previous) => (self.input_merge)(previous.input, input),
+            None => input,
+        };
+        bit_packing::check_input::<T>(&input)?;
         let player_input = PlayerInput::<T::Input>::new(self.sync_layer.current_frame(), input);
         self.local_inputs.insert(player_handle, player_input);
+        self.pending_defaulted_players
+            .retain(|&handle| handle != player_handle);
         Ok(())
     }
 
@@ -262,12 +428,21 @@ impl<T: Config> P2PSession<T> {
     ///
     /// # Errors
     /// - Returns [`InvalidRequest`] if the provided player handle refers to a remote player.
+    /// - Returns [`InvalidRequest`] if the session reached the last frame it can advance to, see [`Frame`].
+    /// - Returns [`InvalidRequest`] if the state of a frame was saved with [`GameStateCell::save_unchanged()`] in sparse saving mode.
     /// - Returns [`NotSynchronized`] if the session is not yet ready to accept input. In this case, you either need to start the session or wait for synchronization between clients.
+    /// - Returns [`StateNotSaved`] if a rollback reaches further back than the saved frames set with [`SessionBuilder::with_max_saved_frames()`].
+    ///   This is detected before the session requests or simulates anything, so the session stays consistent. It cannot advance anymore though,
+    ///   since every further call needs the same rollback.
     ///
     /// [`Vec<GgrsRequest>`]: GgrsRequest
     /// [`InvalidRequest`]: GgrsError::InvalidRequest
     /// [`NotSynchronized`]: GgrsError::NotSynchronized
+    /// [`StateNotSaved`]: GgrsError::StateNotSaved
+    /// [`SessionBuilder::with_max_saved_frames()`]: crate::SessionBuilder::with_max_saved_frames
     pub fn advance_frame(&mut self) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        self.last_rollback_frames = 0;
+
         // receive info from remote players, trigger events and send messages
         self.poll_remote_clients();
 
@@ -277,15 +452,35 @@ impl<T: Config> P2PSession<T> {
             return Err(GgrsError::NotSynchronized);
         }
 
-        // check if input for all local players is queued
+        // the frame counter must not overflow, no matter how long the session runs
+        self.sync_layer.check_frame_limit()?;
+
+        // with sparse saving, the state of the previous frame is usually not saved, so there is nothing to refer back to
+        if self.sparse_saving && self.sync_layer.last_saved_state_unchanged() {
+            return Err(GgrsError::InvalidRequest {
+                info: "GameStateCell::save_unchanged() cannot be used in sparse saving mode."
+                    .to_owned(),
+            });
+        }
+
+        // check if input for all local players is queued, or fill it in according to the policy
+        let num_defaulted = self.pending_defaulted_players.len();
         for handle in self.player_reg.local_player_handles() {
-            if !self.local_inputs.contains_key(&handle) {
-                return Err(GgrsError::InvalidRequest {
-                    info: format!(
-                        "Missing local input for handle {handle} while calling advance_frame()."
-                    ),
-                });
+            if self.local_inputs.contains_key(&handle) {
+                continue;
             }
+            let input = self.missing_local_input(handle)?;
+            let player_input = PlayerInput::new(self.sync_layer.current_frame(), input);
+            self.local_inputs.insert(handle, player_input);
+            self.pending_defaulted_players.push(handle);
+        }
+        if self.pending_defaulted_players.len() > num_defaulted {
+            debug!(
+                "No input given for local players {:?} in frame {}, filled in with {:?}",
+                &self.pending_defaulted_players[num_defaulted..],
+                self.sync_layer.current_frame(),
+                self.missing_local_input
+            );
         }
 
         /*
@@ -314,15 +509,20 @@ impl<T: Config> P2PSession<T> {
         // no need to ever save the game state either.
         let lockstep = self.in_lockstep_mode();
 
+        // propagate disconnects to multiple players
+        self.update_player_disconnects();
+
+        // make sure a rollback can load its gamestate before requesting anything, so a failure leaves the session intact
+        if !lockstep {
+            self.check_rollback_state_saved()?;
+        }
+
         // if we are in the first frame, we have to save the state
         if self.sync_layer.current_frame() == 0 && !lockstep {
             trace!("Saving state of first frame");
             requests.push(self.sync_layer.save_current_state());
         }
 
-        // propagate disconnects to multiple players
-        self.update_player_disconnects();
-
         // find the confirmed frame for which we received all inputs
         let confirmed_frame = self.confirmed_frame();
 
@@ -335,14 +535,14 @@ impl<T: Config> P2PSession<T> {
                 .check_simulation_consistency(self.disconnect_frame);
             // if we have an incorrect frame, then we need to rollback
             if first_incorrect != NULL_FRAME {
-                self.adjust_gamestate(first_incorrect, confirmed_frame, &mut requests);
+                self.adjust_gamestate(first_incorrect, confirmed_frame, &mut requests)?;
                 self.disconnect_frame = NULL_FRAME;
             }
 
             // request gamestate save of current frame
             let last_saved = self.sync_layer.last_saved_frame();
             if self.sparse_saving {
-                self.check_last_saved_state(last_saved, confirmed_frame, &mut requests);
+                self.check_last_saved_state(last_saved, confirmed_frame, &mut requests)?;
             } else {
                 // without sparse saving, always save the current frame after correcting and rollbacking
                 requests.push(self.sync_layer.save_current_state());
@@ -356,6 +556,16 @@ impl<T: Config> P2PSession<T> {
         // send confirmed inputs to spectators before throwing them away
         self.send_confirmed_inputs_to_spectators(confirmed_frame);
 
+        // checksum the inputs of newly confirmed frames for desync detection before throwing them away
+        self.record_input_checksums(confirmed_frame);
+
+        // note when the inputs of newly confirmed frames have been confirmed
+        self.record_confirmations(confirmed_frame);
+        let simulated_confirmed = confirmed_frame.min(self.sync_layer.current_frame()) + 1;
+        self.total_confirmed_frames = self
+            .total_confirmed_frames
+            .max(simulated_confirmed.max(0) as usize);
+
         // set the last confirmed frame and discard all saved inputs before that frame
         self.sync_layer
             .set_last_confirmed_frame(confirmed_frame, self.sparse_saving);
@@ -372,7 +582,11 @@ impl<T: Config> P2PSession<T> {
          */
 
         // register local inputs in the system and send them
-        for handle in self.player_reg.local_player_handles() {
+        let local_handles = self.player_reg.local_player_handles();
+        let last_sent_frame = local_handles.first().map_or(NULL_FRAME, |&handle| {
+            self.local_connect_status[handle].last_frame
+        });
+        for &handle in &local_handles {
             // we have checked that these all exist
             let player_input = self
                 .local_inputs
@@ -389,9 +603,36 @@ impl<T: Config> P2PSession<T> {
 
         // if the local inputs have not been dropped by the sync layer, send to all remote clients
         if !self.local_inputs.values().any(|&i| i.frame == NULL_FRAME) {
+            // after the input delay has been increased, the frames in between repeat the previous input
+            let added_frame = self.local_inputs.values().next().map(|input| input.frame);
+            if let Some(added_frame) = added_frame.filter(|_| last_sent_frame != NULL_FRAME) {
+                for frame in last_sent_frame + 1..added_frame {
+                    let repeated_inputs = local_handles
+                        .iter()
+                        .map(|&handle| {
+                            let input = self
+                                .sync_layer
+                                .confirmed_input(handle, frame)
+                                .expect("Repeated local input should be in the input queue");
+                            (handle, PlayerInput::new(frame, input))
+                        })
+                        .collect();
+                    for endpoint in self.player_reg.remotes.values_mut() {
+                        endpoint.send_input(
+                            &repeated_inputs,
+                            &self.local_connect_status,
+                            self.sync_layer.current_frame(),
+                        );
+                    }
+                }
+            }
             for endpoint in self.player_reg.remotes.values_mut() {
-                endpoint.send_input(&self.local_inputs, &self.local_connect_status);
-                endpoint.send_all_messages(&mut self.socket);
+                endpoint.send_input(
+                    &self.local_inputs,
+                    &self.local_connect_status,
+                    self.sync_layer.current_frame(),
+                );
+                endpoint.send_all_messages(&mut self.socket, self.packet_log.as_mut());
             }
         }
 
@@ -399,30 +640,25 @@ impl<T: Config> P2PSession<T> {
          * ADVANCE THE STATE
          */
 
-        let can_advance = if lockstep {
-            // lockstep mode: only advance if the current frame has inputs confirmed from all other
-            // players.
-            self.sync_layer.last_confirmed_frame() == self.sync_layer.current_frame()
-        } else {
-            // rollback mode: advance as long as we aren't past our prediction window
-            let frames_ahead = if self.sync_layer.last_confirmed_frame() == NULL_FRAME {
-                // we haven't had any frames confirmed, so all frames we've advanced are "ahead"
-                self.sync_layer.current_frame()
-            } else {
-                // we're not at the first frame, so we have to subtract the last confirmed frame
-                self.sync_layer.current_frame() - self.sync_layer.last_confirmed_frame()
-            };
-            frames_ahead < self.max_prediction as i32
-        };
-        if can_advance {
+        if self.can_advance_now(self.sync_layer.last_confirmed_frame()) {
             // get correct inputs for the current frame
             let inputs = self
                 .sync_layer
                 .synchronized_inputs(&self.local_connect_status);
+            if inputs
+                .iter()
+                .any(|(_, status)| *status == InputStatus::Predicted)
+            {
+                self.push_frame_event(FrameEvent::Predicted {
+                    frame: self.sync_layer.current_frame(),
+                });
+            }
             // advance the frame count
             self.sync_layer.advance_frame();
             // clear the local inputs after advancing the frame to allow new inputs to be ingested
             self.local_inputs.clear();
+            self.defaulted_local_players = std::mem::take(&mut self.pending_defaulted_players);
+            self.defaulted_local_players.sort_unstable();
             requests.push(GgrsRequest::AdvanceFrame { inputs });
         } else {
             debug!(
@@ -434,12 +670,73 @@ impl<T: Config> P2PSession<T> {
         Ok(requests)
     }
 
+    /// Whether the current frame can be simulated if the inputs up to `confirmed_frame` are confirmed. Both
+    /// [`advance_frame()`] and [`should_advance()`] decide with this, so they apply the same rules.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`should_advance()`]: Self#method.should_advance
+    fn can_advance_now(&self, confirmed_frame: Frame) -> bool {
+        if self.max_prediction == 0 {
+            // lockstep mode: only advance if the current frame has inputs confirmed from all other
+            // players.
+            confirmed_frame == self.sync_layer.current_frame()
+        } else {
+            // rollback mode: advance as long as we aren't past our prediction window and we don't
+            // have to predict players whose inputs we mostly predicted wrong
+            self.prediction_frames_left(confirmed_frame) > 0 && !self.unreliable_prediction_needed()
+        }
+    }
+
+    fn prediction_frames_left(&self, confirmed_frame: Frame) -> u32 {
+        let frames_ahead = if confirmed_frame == NULL_FRAME {
+            // we haven't had any frames confirmed, so all frames we've advanced are "ahead"
+            self.sync_layer.current_frame()
+        } else {
+            // we're not at the first frame, so we have to subtract the last confirmed frame
+            self.sync_layer.current_frame() - confirmed_frame
+        };
+        self.max_prediction
+            .saturating_sub(frames_ahead.max(0) as usize) as u32
+    }
+
+    /// Returns true if the current frame needs a prediction for a remote player whose recent inputs were mispredicted too often.
+    fn unreliable_prediction_needed(&self) -> bool {
+        let current_frame = self.sync_layer.current_frame();
+        self.player_reg
+            .remote_player_handles()
+            .iter()
+            .any(|&handle| {
+                let con_stat = &self.local_connect_status[handle];
+                !con_stat.disconnected
+                    && con_stat.last_frame < current_frame
+                    && self.sync_layer.misprediction_rate(handle)
+                        > self.misprediction_stall_threshold
+            })
+    }
+
     /// Should be called periodically by your application to give GGRS a chance to do internal work.
     /// GGRS will receive packets, distribute them to corresponding endpoints, handle all occurring events and send all outgoing packets.
+    /// About once a second, it also releases memory of internal buffers that grew beyond their usual size, e.g. inputs held back
+    /// or received while a remote client was far ahead, once these inputs are no longer needed.
     pub fn poll_remote_clients(&mut self) {
+        // reject inputs too far in the future, so a misbehaving remote client can't make us hold back inputs without bound
+        let current_frame = self.sync_layer.current_frame();
+        for endpoint in self.player_reg.remotes.values_mut() {
+            let horizon = endpoint
+                .handles()
+                .iter()
+                .map(|&handle| self.sync_layer.input_queue_length(handle))
+                .min()
+                .unwrap_or(0);
+            endpoint.set_max_input_frame(current_frame + horizon as Frame);
+        }
+
         // Get all packets and distribute them to associated endpoints.
         // The endpoints will handle their packets, which will trigger both events and UPD replies.
         for (from_addr, msg) in &self.socket.receive_all_messages() {
+            if let Some(packet_log) = self.packet_log.as_mut() {
+                packet_log.record(PacketDirection::Received, from_addr, msg);
+            }
             if let Some(endpoint) = self.player_reg.remotes.get_mut(from_addr) {
                 endpoint.handle_message(msg);
             }
@@ -452,6 +749,7 @@ impl<T: Config> P2PSession<T> {
         for remote_endpoint in self.player_reg.remotes.values_mut() {
             if remote_endpoint.is_running() {
                 remote_endpoint.update_local_frame_advantage(self.sync_layer.current_frame());
+                remote_endpoint.update_input_lag(self.sync_layer.current_frame());
             }
         }
 
@@ -479,14 +777,34 @@ impl<T: Config> P2PSession<T> {
 
         // send all queued packets
         for endpoint in self.player_reg.remotes.values_mut() {
-            endpoint.send_all_messages(&mut self.socket);
+            endpoint.send_all_messages(&mut self.socket, self.packet_log.as_mut());
         }
         for endpoint in self.player_reg.spectators.values_mut() {
-            endpoint.send_all_messages(&mut self.socket);
+            endpoint.send_all_messages(&mut self.socket, self.packet_log.as_mut());
+        }
+
+        // release memory of buffers that grew during a burst, e.g. while a peer was catching up after a pause
+        let now = self.endpoint_config.clock.now();
+        if now.duration_since(self.last_compaction) >= COMPACTION_INTERVAL {
+            self.sync_layer.compact_inputs();
+            for endpoint in self
+                .player_reg
+                .remotes
+                .values_mut()
+                .chain(self.player_reg.spectators.values_mut())
+            {
+                endpoint.compact();
+            }
+            self.local_checksum_history
+                .shrink_to(MAX_CHECKSUM_HISTORY_SIZE);
+            self.local_input_checksums
+                .shrink_to(MAX_CHECKSUM_HISTORY_SIZE);
+            self.last_compaction = now;
         }
     }
 
     /// Disconnects a remote player and all other remote players with the same address from the session.
+    /// This queues a [`GgrsEvent::Disconnected`] event with [`DisconnectReason::Requested`] and notifies the remote client.
     /// # Errors
     /// - Returns [`InvalidRequest`] if you try to disconnect a local player or the provided handle is invalid.
     ///
@@ -501,10 +819,15 @@ impl<T: Config> P2PSession<T> {
                 info: "Local Player cannot be disconnected.".to_owned(),
             }),
             // a remote player can only be disconnected if not already disconnected, since there is some additional logic attached
-            Some(PlayerType::Remote(_)) => {
+            Some(PlayerType::Remote(addr)) => {
                 if !self.local_connect_status[player_handle].disconnected {
+                    let addr = addr.clone();
                     let last_frame = self.local_connect_status[player_handle].last_frame;
                     self.disconnect_player_at_frame(player_handle, last_frame);
+                    self.event_queue.push_back(GgrsEvent::Disconnected {
+                        addr,
+                        reason: DisconnectReason::Requested,
+                    });
                     return Ok(());
                 }
                 Err(GgrsError::InvalidRequest {
@@ -512,13 +835,56 @@ impl<T: Config> P2PSession<T> {
                 })
             }
             // disconnecting spectators is simpler
-            Some(PlayerType::Spectator(_)) => {
+            Some(PlayerType::Spectator(addr)) => {
+                let addr = addr.clone();
                 self.disconnect_player_at_frame(player_handle, NULL_FRAME);
+                self.event_queue.push_back(GgrsEvent::Disconnected {
+                    addr,
+                    reason: DisconnectReason::Requested,
+                });
                 Ok(())
             }
         }
     }
 
+    /// Suspends all disconnect timeouts of this session, so remote clients are neither reported as interrupted nor disconnected
+    /// while no packets arrive from them, e.g. because you are stepping through your game with a debugger. Call [`resume_timeouts()`]
+    /// to continue; the time spent suspended does not count towards the timeouts.
+    ///
+    /// This is a debugging aid only and must not be used in production: remote clients are not informed and will still time out
+    /// on their side.
+    ///
+    /// [`resume_timeouts()`]: Self#method.resume_timeouts
+    pub fn suspend_timeouts(&mut self) {
+        for endpoint in self
+            .player_reg
+            .remotes
+            .values_mut()
+            .chain(self.player_reg.spectators.values_mut())
+        {
+            endpoint.suspend_timeouts();
+        }
+    }
+
+    /// Resumes disconnect timeouts after [`suspend_timeouts()`].
+    ///
+    /// Since frames did not advance while time passed, the frame advantage measured before the pause is stale. It is reset here,
+    /// so [`frames_ahead()`] starts over from 0 and no [`GgrsEvent::WaitRecommendation`] is based on the time before the pause.
+    ///
+    /// [`suspend_timeouts()`]: Self#method.suspend_timeouts
+    /// [`frames_ahead()`]: Self#method.frames_ahead
+    pub fn resume_timeouts(&mut self) {
+        self.frames_ahead = 0;
+        for endpoint in self
+            .player_reg
+            .remotes
+            .values_mut()
+            .chain(self.player_reg.spectators.values_mut())
+        {
+            endpoint.resume_timeouts();
+        }
+    }
+
     /// Returns a [`NetworkStats`] struct that gives information about the quality of the network connection.
     /// # Errors
     /// - Returns [`InvalidRequest`] if the handle not referring to a remote player or spectator.
@@ -533,13 +899,22 @@ impl<T: Config> P2PSession<T> {
                 .remotes
                 .get(addr)
                 .expect("Endpoint should exist for any registered player")
-                .network_stats(),
+                .network_stats()
+                .map(|stats| NetworkStats {
+                    misprediction_rate: self.sync_layer.misprediction_rate(player_handle),
+                    simulated_packets_dropped: self.socket.dropped_packets(addr),
+                    ..stats
+                }),
             Some(PlayerType::Spectator(addr)) => self
                 .player_reg
-                .remotes
+                .spectators
                 .get(addr)
                 .expect("Endpoint should exist for any registered player")
-                .network_stats(),
+                .network_stats()
+                .map(|stats| NetworkStats {
+                    simulated_packets_dropped: self.socket.dropped_packets(addr),
+                    ..stats
+                }),
             _ => Err(GgrsError::InvalidRequest {
                 info: "Given player handle not referring to a remote player or spectator"
                     .to_owned(),
@@ -547,7 +922,110 @@ impl<T: Config> P2PSession<T> {
         }
     }
 
+    /// Returns the time left until the given remote player or spectator is disconnected for not sending any packets, e.g. for a
+    /// countdown while reconnecting. The countdown starts once nothing has been received for the time set with
+    /// [`SessionBuilder::with_disconnect_notify_delay()`], at the same time as [`GgrsEvent::NetworkInterrupted`] is sent,
+    /// and ends at the timeout set with [`SessionBuilder::with_disconnect_timeout()`].
+    ///
+    /// Returns `None` for local players, invalid handles, peers that are not running or disconnected, peers that have sent packets
+    /// recently and while timeouts are suspended.
+    ///
+    /// [`SessionBuilder::with_disconnect_notify_delay()`]: crate::SessionBuilder#method.with_disconnect_notify_delay
+    /// [`SessionBuilder::with_disconnect_timeout()`]: crate::SessionBuilder#method.with_disconnect_timeout
+    pub fn time_until_disconnect(&self, player_handle: PlayerHandle) -> Option<Duration> {
+        match self.player_reg.handles.get(&player_handle)? {
+            PlayerType::Local => None,
+            PlayerType::Remote(addr) => self.player_reg.remotes.get(addr)?.time_until_disconnect(),
+            PlayerType::Spectator(addr) => self
+                .player_reg
+                .spectators
+                .get(addr)?
+                .time_until_disconnect(),
+        }
+    }
+
+    /// Returns the metrics of this session in the Prometheus text exposition format, so a dedicated server process can expose them
+    /// for scraping. This includes the current and confirmed frame, the number of rollbacks and the ping and send rate of every
+    /// remote player. Network metrics of players who are not synchronized yet are omitted.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_prometheus(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(out, "# HELP ggrs_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE ggrs_{} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "ggrs_{}{} {}", name, labels, value);
+            }
+        };
+
+        let no_labels = |value: String| vec![(String::new(), value)];
+        metric(
+            "current_frame",
+            "gauge",
+            "The current frame of the session.",
+            &no_labels(self.current_frame().to_string()),
+        );
+        metric(
+            "confirmed_frame",
+            "gauge",
+            "The highest frame for which all inputs have been received.",
+            &no_labels(self.sync_layer.last_confirmed_frame().to_string()),
+        );
+        metric(
+            "rollbacks_total",
+            "counter",
+            "The number of rollbacks since the session started.",
+            &no_labels(self.num_rollbacks.to_string()),
+        );
+
+        let mut handles = self.remote_player_handles();
+        handles.sort_unstable();
+        let stats: Vec<_> = handles
+            .into_iter()
+            .filter_map(|handle| {
+                Some((
+                    format!("{{player=\"{}\"}}", handle),
+                    self.network_stats(handle).ok()?,
+                ))
+            })
+            .collect();
+        metric(
+            "ping_milliseconds",
+            "gauge",
+            "The round trip time to a remote player.",
+            &stats
+                .iter()
+                .map(|(labels, stats)| (labels.clone(), stats.ping.to_string()))
+                .collect::<Vec<_>>(),
+        );
+        metric(
+            "send_kbps",
+            "gauge",
+            "The estimated bandwidth sent to a remote player, in kilobits per second.",
+            &stats
+                .iter()
+                .map(|(labels, stats)| (labels.clone(), stats.kbps_sent.to_string()))
+                .collect::<Vec<_>>(),
+        );
+        metric(
+            "recv_kbps",
+            "gauge",
+            "The estimated bandwidth received from a remote player, in kilobits per second.",
+            &stats
+                .iter()
+                .map(|(labels, stats)| (labels.clone(), stats.kbps_received.to_string()))
+                .collect::<Vec<_>>(),
+        );
+
+        out
+    }
+
     /// Returns the highest confirmed frame. We have received all input for this frame and it is thus correct.
+    /// This is the minimum of [`player_confirmed_frame()`] over all players who are still connected.
+    ///
+    /// [`player_confirmed_frame()`]: Self#method.player_confirmed_frame
     pub fn confirmed_frame(&self) -> Frame {
         let mut confirmed_frame = i32::MAX;
 
@@ -561,16 +1039,447 @@ impl<T: Config> P2PSession<T> {
         confirmed_frame
     }
 
+    /// Returns the highest frame for which the input of the given player has been received (or added, for local players),
+    /// or [`NULL_FRAME`] if there is none yet. For disconnected players, this is the last frame their input is used for, as agreed on by all peers.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] when the given handle does not refer to a player.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn player_confirmed_frame(&self, player_handle: PlayerHandle) -> Result<Frame, GgrsError> {
+        self.local_connect_status
+            .get(player_handle)
+            .map(|con_stat| con_stat.last_frame)
+            .ok_or_else(|| GgrsError::InvalidRequest {
+                info: "The player handle you provided is not referring to a player.".to_owned(),
+            })
+    }
+
+    /// Returns the latest confirmed input of the given player, or `None` if no input of that player has been received yet.
+    /// In contrast to the inputs handed out with [`GgrsRequest::AdvanceFrame`], this is never a prediction, so you can use it
+    /// to display the last known action of a remote player. It only changes when a new input of that player arrives.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the handle does not refer to a player.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn last_confirmed_input(
+        &self,
+        player_handle: PlayerHandle,
+    ) -> Result<Option<T::Input>, GgrsError> {
+        if player_handle >= self.num_players {
+            return Err(GgrsError::InvalidRequest {
+                info: "The player handle you provided is not referring to a player.".to_owned(),
+            });
+        }
+        Ok(self.sync_layer.last_confirmed_input(player_handle))
+    }
+
+    /// Returns the input the given player submitted for the given frame, or `None` if that frame is not confirmed yet or its inputs
+    /// have already been discarded. Inputs of confirmed frames are only kept as long as GGRS needs them, so for audit trails or
+    /// replays, query each frame once it has been confirmed, or keep them around longer with [`with_spectator_buffer_frames()`].
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the handle does not refer to a player.
+    ///
+    /// [`with_spectator_buffer_frames()`]: crate::SessionBuilder#method.with_spectator_buffer_frames
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn player_input_for_frame(
+        &self,
+        player_handle: PlayerHandle,
+        frame: Frame,
+    ) -> Result<Option<T::Input>, GgrsError> {
+        if player_handle >= self.num_players {
+            return Err(GgrsError::InvalidRequest {
+                info: "The player handle you provided is not referring to a player.".to_owned(),
+            });
+        }
+        if frame > self.confirmed_frame() {
+            return Ok(None);
+        }
+        Ok(self.sync_layer.confirmed_input(player_handle, frame))
+    }
+
+    /// Returns a bitmask of the players that are disconnected at the given frame. Bit `i` corresponds to player handle `i`, so
+    /// if players 0 and 2 are disconnected, the mask is `0b101`. These are the players that get [`InputStatus::Disconnected`]
+    /// for that frame. Handles of 32 and above are not represented in the mask.
+    ///
+    /// [`InputStatus::Disconnected`]: crate::InputStatus::Disconnected
+    pub fn disconnect_flags(&self, frame: Frame) -> u32 {
+        self.local_connect_status
+            .iter()
+            .take(u32::BITS as usize)
+            .enumerate()
+            .filter(|(_, con_stat)| con_stat.disconnected_at(frame))
+            .fold(0, |flags, (handle, _)| flags | (1 << handle))
+    }
+
     /// Returns the current frame of a session.
     pub fn current_frame(&self) -> Frame {
         self.sync_layer.current_frame()
     }
 
+    /// Returns the frame of the present gamestate, which has been simulated with predicted inputs if some are still missing.
+    /// This is the same as [`current_frame()`].
+    ///
+    /// [`current_frame()`]: Self#method.current_frame
+    pub fn simulation_frame(&self) -> Frame {
+        self.sync_layer.current_frame()
+    }
+
+    /// Returns the latest frame whose gamestate will never be rolled back, since the inputs of all frames before it are confirmed.
+    /// It lags behind [`simulation_frame()`] by the number of frames simulated with predicted inputs.
+    ///
+    /// Rendering this frame instead of the present one trades latency for smoothness: corrections by rollbacks never become visible,
+    /// since only states that can't change anymore are displayed. To do so, keep the render-relevant parts of the gamestates between
+    /// the two frames around, e.g. from the [`SaveGameState`] requests, and display the state of this frame, or interpolate from it
+    /// towards the present state to hide some of the added latency. If predictions are rarely wrong, rendering the present frame
+    /// usually feels more responsive.
+    ///
+    /// [`simulation_frame()`]: Self#method.simulation_frame
+    /// [`SaveGameState`]: GgrsRequest::SaveGameState
+    pub fn render_frame(&self) -> Frame {
+        std::cmp::min(self.confirmed_frame() + 1, self.sync_layer.current_frame())
+    }
+
     /// Returns the maximum prediction window of a session.
     pub fn max_prediction(&self) -> usize {
         self.max_prediction
     }
 
+    /// Returns true if local input for the given player has been registered for the current frame and has not been used by [`advance_frame()`] yet.
+    /// Inputs are kept if the session could not advance, e.g. because the prediction threshold was reached.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    pub fn has_local_input(&self, player_handle: PlayerHandle) -> bool {
+        self.local_inputs.contains_key(&player_handle)
+    }
+
+    /// Returns the inputs of all players that the next call to [`advance_frame()`] will advance the current frame with, including
+    /// predictions for remote players whose input has not been received yet, in the same form as [`GgrsRequest::AdvanceFrame`].
+    /// This allows to preview or validate a frame before advancing it. Local inputs registered with [`add_local_input()`] are
+    /// taken into account, missing ones are filled in according to [`SessionBuilder::with_missing_local_input()`].
+    ///
+    /// The inputs will differ if new remote inputs are received during [`advance_frame()`] or [`poll_remote_clients()`]
+    /// in the meantime, or if [`advance_frame()`] cannot advance the frame.
+    ///
+    /// # Errors
+    /// - Returns [`NotSynchronized`] if the session is not yet ready to accept input.
+    /// - Returns [`InvalidRequest`] if a local input is missing and missing inputs are not filled in.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`add_local_input()`]: Self#method.add_local_input
+    /// [`poll_remote_clients()`]: Self#method.poll_remote_clients
+    /// [`SessionBuilder::with_missing_local_input()`]: crate::SessionBuilder#method.with_missing_local_input
+    /// [`NotSynchronized`]: GgrsError::NotSynchronized
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn next_frame_inputs(&self) -> Result<Vec<(T::Input, InputStatus)>, GgrsError> {
+        if self.state != SessionState::Running {
+            return Err(GgrsError::NotSynchronized);
+        }
+        let mut local_inputs = self.local_inputs.clone();
+        for handle in self.player_reg.local_player_handles() {
+            if let Entry::Vacant(entry) = local_inputs.entry(handle) {
+                let input = self.missing_local_input(handle)?;
+                entry.insert(PlayerInput::new(self.sync_layer.current_frame(), input));
+            }
+        }
+        Ok(self
+            .sync_layer
+            .preview_synchronized_inputs(&local_inputs, &self.local_connect_status))
+    }
+
+    /// Returns the handles of the local players whose input for the last frame advanced by [`advance_frame()`] has not been
+    /// registered with [`add_local_input()`], but filled in according to [`SessionBuilder::with_missing_local_input()`], in ascending order.
+    /// This tells a forgotten input apart from an input that has intentionally been given as the default input.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`add_local_input()`]: Self#method.add_local_input
+    /// [`SessionBuilder::with_missing_local_input()`]: crate::SessionBuilder#method.with_missing_local_input
+    pub fn defaulted_local_players(&self) -> &[PlayerHandle] {
+        &self.defaulted_local_players
+    }
+
+    /// Returns how many more frames will use default input for the given local player. With an input delay of `n` frames, inputs
+    /// added in frame `f` are used in frame `f + n`, so the player has no input for the first `n` frames of the session.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] when the given handle does not refer to a local player.
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn frames_until_input_active(
+        &self,
+        player_handle: PlayerHandle,
+    ) -> Result<usize, GgrsError> {
+        if !self
+            .player_reg
+            .local_player_handles()
+            .contains(&player_handle)
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: "The player handle you provided is not referring to a local player."
+                    .to_owned(),
+            });
+        }
+        Ok(self.sync_layer.frames_until_input_active(player_handle))
+    }
+
+    /// Returns the local address of the socket this session uses. If you bound the socket to port 0, this tells you which port
+    /// the OS assigned, so you can share it with the other peers, e.g. through a matchmaking server.
+    ///
+    /// # Errors
+    /// - Returns the error of [`NonBlockingSocket::local_addr()`], e.g. if your socket does not implement it.
+    pub fn local_addr(&self) -> std::io::Result<T::Address> {
+        self.socket.local_addr()
+    }
+
+    /// Simulates a bad network for all outgoing packets of this session: each packet is dropped with the given probability,
+    /// otherwise it is held back by the given latency plus a random jitter. The randomness is seeded, so a run with the same
+    /// inputs and timing can be reproduced. Packets held back are sent during [`poll_remote_clients()`]. The number of packets
+    /// dropped is reported per remote client in [`NetworkStats`]. Pass [`NetworkSim::default()`] to turn the simulation off.
+    ///
+    /// This is meant for testing only and must not be used in production.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the packet loss is greater than 100 percent.
+    ///
+    /// [`poll_remote_clients()`]: Self#method.poll_remote_clients
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn set_network_simulation(&mut self, sim: NetworkSim) -> Result<(), GgrsError> {
+        self.socket.set_simulation(sim)
+    }
+
+    /// Changes the input delay of all local players while the session is running, e.g. when the connection has improved.
+    /// All local players share a single stream of inputs to the remote clients, so they always have the same delay.
+    ///
+    /// The new delay applies from the next call to [`advance_frame()`]: the input added for frame `f` is then used in frame
+    /// `f + delay`. When the delay increases, the last input is repeated for the frames in between. When it decreases, the
+    /// inputs of the next frames are ignored until the delayed frames have caught up, since their frames already have input.
+    /// Remote clients receive the inputs together with the frames they apply to, so all peers simulate the transition the same way.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the delay, the prediction window and the spectator buffer don't fit into the input queue
+    ///   of a local player together, see [`SessionBuilder::with_input_delay()`].
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`SessionBuilder::with_input_delay()`]: crate::SessionBuilder#method.with_input_delay
+    pub fn set_input_delay(&mut self, delay: usize) -> Result<(), GgrsError> {
+        let local_handles = self.player_reg.local_player_handles();
+        for &handle in &local_handles {
+            if delay > self.sync_layer.max_frame_delay(handle) {
+                return Err(GgrsError::InvalidRequest {
+                    info: format!(
+                        "Spectator buffer frames, max prediction and input delay combined must be smaller than the input queue length of player {handle}."
+                    ),
+                });
+            }
+        }
+        for handle in local_handles {
+            self.sync_layer.set_frame_delay(handle, delay);
+        }
+        Ok(())
+    }
+
+    /// Decides whether the game loop should advance the session in the current tick, combining the synchronization state, the
+    /// prediction threshold and the time synchronization between clients into a single call. Call it after
+    /// [`poll_remote_clients()`], since it does not receive packets itself. It does not change the session, so asking
+    /// repeatedly within a tick gives the same answer.
+    ///
+    /// A recommendation to stall for time synchronization is the same one [`advance_frame()`] would queue as
+    /// [`GgrsEvent::WaitRecommendation`], so if you follow it, that event will not be queued. Skip the returned number of frames
+    /// before asking again.
+    ///
+    /// [`poll_remote_clients()`]: Self#method.poll_remote_clients
+    /// [`advance_frame()`]: Self#method.advance_frame
+    pub fn should_advance(&self) -> AdvanceDecision {
+        if self.state != SessionState::Running {
+            return AdvanceDecision::WaitForSync;
+        }
+
+        // the local inputs for the current frame are only added when advancing, so only remote players can hold back confirmation
+        let current_frame = self.sync_layer.current_frame();
+        let mut confirmed_frame = current_frame;
+        for handle in self.player_reg.remote_player_handles() {
+            let con_stat = &self.local_connect_status[handle];
+            if !con_stat.disconnected {
+                confirmed_frame = std::cmp::min(confirmed_frame, con_stat.last_frame);
+            }
+        }
+        if !self.can_advance_now(confirmed_frame) {
+            return AdvanceDecision::Stall { frames: 1 };
+        }
+
+        let frames_ahead = self.max_frame_advantage();
+        if self.wait_recommended(frames_ahead) {
+            return AdvanceDecision::Stall {
+                frames: frames_ahead as u32,
+            };
+        }
+        AdvanceDecision::Advance
+    }
+
+    /// Returns how many more frames the session can advance before reaching the prediction threshold, if no further inputs are confirmed.
+    /// The value is updated with every call to [`advance_frame()`]. In lockstep mode, this is always 0.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    pub fn remaining_prediction_frames(&self) -> u32 {
+        self.prediction_frames_left(self.sync_layer.last_confirmed_frame())
+    }
+
+    /// Returns the number of frames that have been resimulated due to a rollback during the last call to [`advance_frame()`],
+    /// or 0 if there was no rollback. In sparse saving mode, a single call can roll back twice, in which case the frames of
+    /// both rollbacks are added up. Deep rollbacks are expensive, so this helps to attribute CPU spikes to them.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    pub fn last_rollback_frames(&self) -> usize {
+        self.last_rollback_frames
+    }
+
+    /// Returns the largest number of frames resimulated during a single call to `advance_frame()` since the session started or
+    /// since the last call to [`reset_peak_rollback_depth()`]. This is the worst case of [`last_rollback_frames()`] and tells how
+    /// demanding a match was on resimulation.
+    ///
+    /// [`reset_peak_rollback_depth()`]: Self#method.reset_peak_rollback_depth
+    /// [`last_rollback_frames()`]: Self#method.last_rollback_frames
+    pub fn peak_rollback_depth(&self) -> usize {
+        self.peak_rollback_frames
+    }
+
+    /// Resets the value returned by [`peak_rollback_depth()`] to 0, e.g. to measure each round of a match on its own.
+    ///
+    /// [`peak_rollback_depth()`]: Self#method.peak_rollback_depth
+    pub fn reset_peak_rollback_depth(&mut self) {
+        self.peak_rollback_frames = 0;
+    }
+
+    /// Returns the number of rollbacks that resimulated more frames than the budget set with [`SessionBuilder::with_rollback_budget()`].
+    ///
+    /// [`SessionBuilder::with_rollback_budget()`]: crate::SessionBuilder#method.with_rollback_budget
+    pub fn rollbacks_over_budget(&self) -> usize {
+        self.rollbacks_over_budget
+    }
+
+    /// Returns the number of frames that have been simulated with the confirmed inputs of all players since the session started.
+    /// Unlike [`current_frame()`], this never includes predicted frames and never decreases, so it is suited for progress bars,
+    /// e.g. when catching up with a replay.
+    ///
+    /// [`current_frame()`]: Self#method.current_frame
+    pub fn total_confirmed_frames(&self) -> usize {
+        self.total_confirmed_frames
+    }
+
+    /// Returns all transitions of frames between predicted and confirmed inputs since the last call, oldest first.
+    /// This is a lightweight alternative to [`events()`] for analytics that only care about frames. Only the latest 256
+    /// transitions are kept, so drain them regularly, e.g. once per frame.
+    ///
+    /// [`events()`]: Self#method.events
+    pub fn drain_frame_events(&mut self) -> Vec<FrameEvent> {
+        self.frame_events.drain(..).collect()
+    }
+
+    /// Returns the metadata of all packets sent and received since the last call, oldest first, if recording has been enabled with
+    /// [`SessionBuilder::with_packet_log()`]. Only the most recent 1024 packets are kept, so drain them regularly.
+    /// Packets from addresses that do not belong to the session are included, which helps to debug NAT traversal.
+    ///
+    /// [`SessionBuilder::with_packet_log()`]: crate::SessionBuilder#method.with_packet_log
+    pub fn drain_packet_events(&mut self) -> Vec<PacketEvent<T::Address>> {
+        self.packet_log
+            .as_mut()
+            .map(PacketLog::drain)
+            .unwrap_or_default()
+    }
+
+    /// Returns when the inputs of all players for the given frame became confirmed, e.g. for audit logging.
+    /// Only the last 128 confirmed frames are kept. Returns `None` for older frames and frames not yet confirmed.
+    pub fn confirmation_info(&self, frame: Frame) -> Option<ConfirmationInfo> {
+        let (first_frame, _) = self.confirmation_history.front()?;
+        if frame < *first_frame {
+            return None;
+        }
+        self.confirmation_history
+            .get((frame - first_frame) as usize)
+            .map(|(_, info)| *info)
+    }
+
+    /// Returns the oldest and newest frame for which a gamestate is currently saved, or `None` if no gamestate has been saved yet.
+    /// GGRS can only roll back to frames within this range.
+    pub fn saved_frame_range(&self) -> Option<(Frame, Frame)> {
+        self.sync_layer.frame_range()
+    }
+
+    /// Returns a copy of the most recently saved gamestate together with its frame, e.g. to show it in a debugger UI.
+    /// The copy is independent of the saved states GGRS uses for rollbacks. Returns `None` if no gamestate has been saved yet,
+    /// if the latest [`GgrsRequest::SaveGameState`] has not been handled yet or if it was handled without saving any data.
+    pub fn latest_saved_state(&self) -> Option<(Frame, T::State)>
+    where
+        T::State: Clone,
+    {
+        let frame = self.sync_layer.last_saved_frame();
+        if frame == NULL_FRAME {
+            return None;
+        }
+        let state = self.sync_layer.saved_state_by_frame(frame)?.load()?;
+        Some((frame, state))
+    }
+
+    /// Requests to save the gamestate of the current frame as a bookmark, e.g. when the user presses a "bookmark" key.
+    /// Handle the returned [`GgrsRequest::SaveGameState`] like any other save request. The frame does not advance and the
+    /// bookmark is kept apart from the saved states used for rollbacks, so it neither affects nor is affected by rollbacks.
+    /// Only a single bookmark is kept, each call replaces the previous one.
+    pub fn save_bookmark(&mut self) -> GgrsRequest<T> {
+        let frame = self.sync_layer.current_frame();
+        let cell = self.sync_layer.detached_cell();
+        self.bookmark = Some((frame, cell.clone()));
+        GgrsRequest::SaveGameState { cell, frame }
+    }
+
+    /// Returns the gamestate saved with [`save_bookmark()`] together with its frame, or `None` if there is no bookmark
+    /// or the request to save it has not been handled yet.
+    ///
+    /// [`save_bookmark()`]: Self#method.save_bookmark
+    pub fn bookmark(&self) -> Option<(Frame, GameStateCell<T::State>)> {
+        let (frame, cell) = self.bookmark.as_ref()?;
+        if cell.frame() != *frame {
+            return None;
+        }
+        Some((*frame, cell.clone()))
+    }
+
+    /// Returns the checksum that has been stored with the saved gamestate of the given frame, or `None` if that frame is not
+    /// saved (anymore) or has been saved without a checksum. Together with [`saved_frame_range()`], this allows to compare the
+    /// checksums of any saved frames while debugging.
+    ///
+    /// [`saved_frame_range()`]: Self#method.saved_frame_range
+    pub fn checksum_at(&self, frame: Frame) -> Option<u128> {
+        self.sync_layer.checksum_at(frame)
+    }
+
+    /// Attaches an application-defined label (e.g. a round number or checkpoint name) to the current frame, which helps to find
+    /// your way around long sessions while debugging. Tagging the same frame again replaces its label. Labels are kept for the
+    /// whole session, so you should not tag every frame.
+    pub fn tag_frame(&mut self, label: String) {
+        self.sync_layer.tag_frame(label);
+    }
+
+    /// Returns the label attached to the given frame through [`tag_frame()`], if any.
+    ///
+    /// [`tag_frame()`]: Self#method.tag_frame
+    pub fn frame_label(&self, frame: Frame) -> Option<&str> {
+        self.sync_layer
+            .frame_labels()
+            .get(&frame)
+            .map(String::as_str)
+    }
+
+    /// Returns all frame labels attached through [`tag_frame()`], ordered by frame.
+    ///
+    /// [`tag_frame()`]: Self#method.tag_frame
+    pub fn frame_labels(&self) -> impl Iterator<Item = (Frame, &str)> {
+        self.sync_layer
+            .frame_labels()
+            .iter()
+            .map(|(&frame, label)| (frame, label.as_str()))
+    }
+
     /// Returns true if the session is running in lockstep mode.
     ///
     /// In lockstep mode, a session will only advance if the current frame has inputs confirmed from
@@ -579,16 +1488,36 @@ impl<T: Config> P2PSession<T> {
         self.max_prediction == 0
     }
 
+    /// Returns true if the session only saves confirmed frames, see [`SessionBuilder::with_sparse_saving_mode()`].
+    /// Sparse saving is always turned off in lockstep mode.
+    ///
+    /// [`SessionBuilder::with_sparse_saving_mode()`]: crate::SessionBuilder#method.with_sparse_saving_mode
+    pub fn in_sparse_saving_mode(&self) -> bool {
+        self.sparse_saving
+    }
+
     /// Returns the current [`SessionState`] of a session.
     pub fn current_state(&self) -> SessionState {
         self.state
     }
 
     /// Returns all events that happened since last queried for events. If the number of stored events exceeds `MAX_EVENT_QUEUE_SIZE`, the oldest events will be discarded.
-    pub fn events(&mut self) -> Drain<GgrsEvent<T>> {
+    /// GGRS never calls back into your code. Events are queued while the session advances or polls and are handed out here as a single batch,
+    /// so you can handle them whenever it suits you without reentrancy issues.
+    pub fn events(&mut self) -> Drain<'_, GgrsEvent<T>> {
         self.event_queue.drain(..)
     }
 
+    /// Returns the operations this session supports.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::LOCAL_INPUT
+            | Capabilities::DISCONNECT_PLAYER
+            | Capabilities::NETWORK_STATS
+            | Capabilities::INPUT_DELAY
+            | Capabilities::SUSPEND_TIMEOUTS
+            | Capabilities::DESYNC_DETECTION
+    }
+
     /// Returns the number of players added to this session
     pub fn num_players(&self) -> usize {
         self.player_reg.num_players()
@@ -599,6 +1528,61 @@ impl<T: Config> P2PSession<T> {
         self.player_reg.num_spectators()
     }
 
+    /// Adds a spectator at the given address after the session has been started and returns its handle. The host relays all confirmed
+    /// inputs to the spectator the same way as to spectators added with [`SessionBuilder::add_player()`], retransmitting them until they
+    /// are acknowledged. The session synchronizes with the new spectator before it continues, so you need to keep polling.
+    /// Use [`network_stats()`] with the returned handle to monitor the bandwidth used for relaying inputs to the spectator.
+    ///
+    /// Spectators need all inputs from the very first frame on, so they can only be added before the first frame has been advanced.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the session has already advanced a frame.
+    /// - Returns [`InvalidRequest`] if the address already belongs to a remote player or spectator.
+    ///
+    /// [`SessionBuilder::add_player()`]: crate::SessionBuilder#method.add_player
+    /// [`network_stats()`]: Self#method.network_stats
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn add_spectator(&mut self, addr: T::Address) -> Result<PlayerHandle, GgrsError> {
+        if self.sync_layer.current_frame() > 0 {
+            return Err(GgrsError::InvalidRequest {
+                info: "Spectators can only be added before the first frame has been advanced."
+                    .to_owned(),
+            });
+        }
+        if self.player_reg.remotes.contains_key(&addr)
+            || self.player_reg.spectators.contains_key(&addr)
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: "The address already belongs to a remote player or spectator.".to_owned(),
+            });
+        }
+
+        // spectator handles start after the player handles
+        let handle = self
+            .player_reg
+            .handles
+            .keys()
+            .copied()
+            .max()
+            .map_or(self.num_players, |max| (max + 1).max(self.num_players));
+        self.player_reg
+            .handles
+            .insert(handle, PlayerType::Spectator(addr.clone()));
+        // the host of the spectator sends inputs for all players
+        let endpoint = UdpProtocol::from_config(
+            vec![handle],
+            addr.clone(),
+            self.num_players,
+            &self.endpoint_config,
+        );
+        self.player_reg.spectators.insert(addr, endpoint);
+
+        // wait for the spectator before advancing
+        if self.state == SessionState::Running {
+            self.state = SessionState::Synchronizing;
+        }
+        Ok(handle)
+    }
+
     /// Returns the handles of local players that have been added
     pub fn local_player_handles(&self) -> Vec<PlayerHandle> {
         self.player_reg.local_player_handles()
@@ -624,6 +1608,50 @@ impl<T: Config> P2PSession<T> {
         self.frames_ahead
     }
 
+    /// Returns how many frames the local client is estimated to be ahead of the remote client that is furthest behind.
+    /// A positive value means we are ahead and the remotes have to predict our inputs more often, a negative value means
+    /// we are behind. The estimate is based on the latest received input of each remote client and the ping. Returns 0
+    /// if there are no running remote clients.
+    ///
+    /// In contrast to [`frames_ahead()`], this is the instantaneous value and not averaged over time.
+    ///
+    /// [`frames_ahead()`]: Self#method.frames_ahead
+    pub fn local_frame_advantage(&self) -> i32 {
+        self.player_reg
+            .remotes
+            .values()
+            .filter(|endpoint| endpoint.is_running())
+            // the endpoint measures how many frames we are behind
+            .map(|endpoint| -endpoint.local_frame_advantage())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns how many frames the remote client of the given player is ahead of the local client, as last reported by the remote
+    /// client itself. The sign convention is the same as for [`local_frame_advantage()`]: a positive value means the remote client
+    /// is ahead of us, a negative value means it is behind.
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the handle does not refer to a remote player.
+    ///
+    /// [`local_frame_advantage()`]: Self#method.local_frame_advantage
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn remote_frame_advantage(&self, player_handle: PlayerHandle) -> Result<i32, GgrsError> {
+        match self.player_reg.handles.get(&player_handle) {
+            Some(PlayerType::Remote(addr)) => {
+                let endpoint = self
+                    .player_reg
+                    .remotes
+                    .get(addr)
+                    .expect("Endpoint should exist for any registered player");
+                // the remote measures how many frames it is behind us
+                Ok(-endpoint.remote_frame_advantage())
+            }
+            _ => Err(GgrsError::InvalidRequest {
+                info: "Given player handle not referring to a remote player".to_owned(),
+            }),
+        }
+    }
+
     /// Returns the [`DesyncDetection`] mode set for this session at creation time.
     pub fn desync_detection(&self) -> DesyncDetection {
         self.desync_detection
@@ -644,16 +1672,23 @@ impl<T: Config> P2PSession<T> {
                     .get_mut(addr)
                     .expect("There should be no address without registered endpoint");
 
-                // mark the affected players as disconnected
+                // mark the affected players as disconnected after the agreed frame, which may lie before the last input we received
+                // from them. All peers then substitute the same neutral input from the same frame on.
                 for &handle in endpoint.handles() {
                     self.local_connect_status[handle].disconnected = true;
+                    self.local_connect_status[handle].last_frame = last_frame;
                 }
                 endpoint.disconnect();
 
                 if self.sync_layer.current_frame() > last_frame {
                     // remember to adjust simulation to account for the fact that the player disconnected a few frames ago,
                     // resimulating with correct disconnect flags (to account for user having some AI kick in).
-                    self.disconnect_frame = last_frame + 1;
+                    let disconnect_frame = last_frame + 1;
+                    if self.disconnect_frame == NULL_FRAME
+                        || disconnect_frame < self.disconnect_frame
+                    {
+                        self.disconnect_frame = disconnect_frame;
+                    }
                 }
             }
             PlayerType::Spectator(addr) => {
@@ -694,13 +1729,35 @@ impl<T: Config> P2PSession<T> {
         self.state = SessionState::Running;
     }
 
+    /// Returns [`GgrsError::StateNotSaved`] if correcting the simulation requires loading a gamestate that is not saved anymore.
+    fn check_rollback_state_saved(&self) -> Result<(), GgrsError> {
+        let first_incorrect = self
+            .sync_layer
+            .check_simulation_consistency(self.disconnect_frame);
+        if first_incorrect == NULL_FRAME {
+            return Ok(());
+        }
+        // same as in adjust_gamestate()
+        let frame_to_load = if self.sparse_saving {
+            self.sync_layer.last_saved_frame()
+        } else {
+            first_incorrect
+        };
+        match self.sync_layer.saved_state_by_frame(frame_to_load) {
+            Some(_) => Ok(()),
+            None => Err(GgrsError::StateNotSaved {
+                frame: frame_to_load,
+            }),
+        }
+    }
+
     /// Roll back to `min_confirmed` frame and resimulate the game with most up-to-date input data.
     fn adjust_gamestate(
         &mut self,
         first_incorrect: Frame,
         min_confirmed: Frame,
         requests: &mut Vec<GgrsRequest<T>>,
-    ) {
+    ) -> Result<(), GgrsError> {
         let current_frame = self.sync_layer.current_frame();
         // determine the frame to load
         let frame_to_load = if self.sparse_saving {
@@ -713,44 +1770,154 @@ impl<T: Config> P2PSession<T> {
 
         // we should always load a frame that is before or exactly the first incorrect frame
         assert!(frame_to_load <= first_incorrect);
-        let count = current_frame - frame_to_load;
 
         // request to load that frame
         debug!(
             "Pushing request to load frame {} (current frame {})",
             frame_to_load, current_frame
         );
-        requests.push(self.sync_layer.load_frame(frame_to_load));
 
-        // we are now at the desired frame
-        assert_eq!(self.sync_layer.current_frame(), frame_to_load);
-        self.sync_layer.reset_prediction();
+        // step forward to the previous current state, but with updated inputs. With sparse saving, we only save exactly the
+        // min_confirmed frame, otherwise we save every state except the very first (just loaded that)
+        let sparse_saving = self.sparse_saving;
+        let count = self.sync_layer.rollback_to(
+            frame_to_load,
+            &self.local_connect_status,
+            |frame| {
+                if sparse_saving {
+                    frame == min_confirmed
+                } else {
+                    frame != frame_to_load
+                }
+            },
+            requests,
+        )?;
+
+        // let the user know which frames have been resimulated
+        self.push_frame_event(FrameEvent::Corrected {
+            frame: first_incorrect,
+        });
+        self.num_rollbacks += 1;
+        // with sparse saving, a single call to advance_frame() can roll back twice
+        self.last_rollback_frames += count;
+        self.peak_rollback_frames = self.peak_rollback_frames.max(self.last_rollback_frames);
+        if let Some(budget) = self.rollback_budget {
+            if count > budget {
+                warn!(
+                    "Rollback from frame {} resimulated {} frames, exceeding the budget of {} frames",
+                    current_frame, count, budget
+                );
+                self.rollbacks_over_budget += 1;
+            }
+        }
+        if self.rollback_events {
+            self.push_rollback_event(frame_to_load, current_frame);
+        }
+        Ok(())
+    }
+
+    /// Lets the user know which frames have been resimulated. If the latest event not yet polled is also a rollback,
+    /// both are merged into one, so frequent rollbacks do not evict other events from the queue.
+    fn push_rollback_event(&mut self, from_frame: Frame, to_frame: Frame) {
+        if let Some(GgrsEvent::Rollback {
+            from_frame: pending_from,
+            to_frame: pending_to,
+        }) = self.event_queue.back_mut()
+        {
+            *pending_from = (*pending_from).min(from_frame);
+            *pending_to = (*pending_to).max(to_frame);
+            return;
+        }
+        self.event_queue.push_back(GgrsEvent::Rollback {
+            from_frame,
+            to_frame,
+        });
+        while self.event_queue.len() > MAX_EVENT_QUEUE_SIZE {
+            self.event_queue.pop_front();
+        }
+    }
 
-        // step forward to the previous current state, but with updated inputs
-        for i in 0..count {
-            let inputs = self
+    /// Remembers when the inputs of all frames up to the given confirmed frame became confirmed, and emits a frame event for each of them.
+    fn record_confirmations(&mut self, confirmed_frame: Frame) {
+        let mut next_frame = match self.confirmation_history.back() {
+            Some((frame, _)) => frame + 1,
+            None => 0,
+        };
+        if next_frame > confirmed_frame {
+            return;
+        }
+        // older frames would be dropped from the history right away
+        let oldest_kept = confirmed_frame - MAX_CONFIRMATION_HISTORY as Frame + 1;
+        if next_frame < oldest_kept {
+            self.confirmation_history.clear();
+            next_frame = oldest_kept;
+        }
+
+        let confirmed_at = self.endpoint_config.clock.now();
+        let current_frame = self.sync_layer.current_frame();
+        while next_frame <= confirmed_frame {
+            if self.confirmation_history.len() == MAX_CONFIRMATION_HISTORY {
+                self.confirmation_history.pop_front();
+            }
+            let info = ConfirmationInfo {
+                confirmed_at,
+                confirmed_in_frame: current_frame,
+                prediction_frames: (current_frame - next_frame).max(0) as usize,
+            };
+            self.confirmation_history.push_back((next_frame, info));
+            self.push_frame_event(FrameEvent::Confirmed { frame: next_frame });
+            next_frame += 1;
+        }
+    }
+
+    fn push_frame_event(&mut self, event: FrameEvent) {
+        if self.frame_events.len() == MAX_FRAME_EVENTS {
+            self.frame_events.pop_front();
+        }
+        self.frame_events.push_back(event);
+    }
+
+    /// Returns the input to use for a local player without registered input, according to the `missing_local_input` policy.
+    fn missing_local_input(&self, handle: PlayerHandle) -> Result<T::Input, GgrsError> {
+        match self.missing_local_input {
+            MissingLocalInput::Error => Err(GgrsError::InvalidRequest {
+                info: format!("Missing local input for handle {handle}."),
+            }),
+            MissingLocalInput::Zero => Ok(T::Input::default()),
+            MissingLocalInput::RepeatLast => Ok(self
                 .sync_layer
-                .synchronized_inputs(&self.local_connect_status);
+                .last_confirmed_input(handle)
+                .unwrap_or_default()),
+        }
+    }
 
-            // decide whether to request a state save
-            if self.sparse_saving {
-                // with sparse saving, we only save exactly the min_confirmed frame
-                if self.sync_layer.current_frame() == min_confirmed {
-                    requests.push(self.sync_layer.save_current_state());
-                }
-            } else {
-                // without sparse saving, we save every state except the very first (just loaded that))
-                if i > 0 {
-                    requests.push(self.sync_layer.save_current_state());
+    /// Computes the checksums of the confirmed inputs that lead to the frames we will send checksums for.
+    fn record_input_checksums(&mut self, confirmed_frame: Frame) {
+        let DesyncDetection::On { interval } = self.desync_detection else {
+            return;
+        };
+
+        while self.next_input_checksum_frame <= confirmed_frame {
+            // the inputs of a frame lead to the state of the next frame
+            let checksum_frame = self.next_input_checksum_frame + 1;
+            if checksum_frame % interval as Frame == 0 {
+                let input_checksum = self
+                    .sync_layer
+                    .input_checksum(self.next_input_checksum_frame, &self.local_connect_status);
+                self.local_input_checksums
+                    .insert(checksum_frame, input_checksum);
+
+                // sending a state checksum consumes the input checksum of its frame, but without saved states (e.g. in
+                // lockstep mode), no state checksums are sent, so only the most recent input checksums are kept
+                if self.local_input_checksums.len() > MAX_CHECKSUM_HISTORY_SIZE {
+                    let oldest_frame_to_keep =
+                        checksum_frame - (MAX_CHECKSUM_HISTORY_SIZE as i32 - 1) * interval as i32;
+                    self.local_input_checksums
+                        .retain(|&frame, _| frame >= oldest_frame_to_keep);
                 }
             }
-
-            // advance the frame
-            self.sync_layer.advance_frame();
-            requests.push(GgrsRequest::AdvanceFrame { inputs });
+            self.next_input_checksum_frame += 1;
         }
-        // after all this, we should have arrived at the same frame where we started
-        assert_eq!(self.sync_layer.current_frame(), current_frame);
     }
 
     /// For each spectator, send all confirmed input up until the minimum confirmed frame.
@@ -774,7 +1941,11 @@ impl<T: Config> P2PSession<T> {
             // send it to all spectators
             for endpoint in self.player_reg.spectators.values_mut() {
                 if endpoint.is_running() {
-                    endpoint.send_input(&input_map, &self.local_connect_status);
+                    endpoint.send_input(
+                        &input_map,
+                        &self.local_connect_status,
+                        self.sync_layer.current_frame(),
+                    );
                 }
             }
 
@@ -841,11 +2012,16 @@ impl<T: Config> P2PSession<T> {
         interval
     }
 
+    /// Returns true if the session is far enough ahead of the remote clients to recommend waiting, at most every
+    /// [`RECOMMENDATION_INTERVAL`] frames.
+    fn wait_recommended(&self, frames_ahead: i32) -> bool {
+        self.sync_layer.current_frame() > self.next_recommended_sleep
+            && frames_ahead >= MIN_RECOMMENDATION as i32
+    }
+
     fn check_wait_recommendation(&mut self) {
         self.frames_ahead = self.max_frame_advantage();
-        if self.sync_layer.current_frame() > self.next_recommended_sleep
-            && self.frames_ahead >= MIN_RECOMMENDATION as i32
-        {
+        if self.wait_recommended(self.frames_ahead) {
             self.next_recommended_sleep = self.sync_layer.current_frame() + RECOMMENDATION_INTERVAL;
             self.event_queue.push_back(GgrsEvent::WaitRecommendation {
                 skip_frames: self
@@ -861,7 +2037,7 @@ impl<T: Config> P2PSession<T> {
         last_saved: Frame,
         confirmed_frame: Frame,
         requests: &mut Vec<GgrsRequest<T>>,
-    ) {
+    ) -> Result<(), GgrsError> {
         // in sparse saving mode, we need to make sure not to lose the last saved frame
         if self.sync_layer.current_frame() - last_saved >= self.max_prediction as i32 {
             // check if the current frame is confirmed, otherwise we need to roll back
@@ -870,7 +2046,7 @@ impl<T: Config> P2PSession<T> {
                 requests.push(self.sync_layer.save_current_state());
             } else {
                 // roll back to the last saved state, resimulate and save on the way
-                self.adjust_gamestate(last_saved, confirmed_frame, requests);
+                self.adjust_gamestate(last_saved, confirmed_frame, requests)?;
             }
 
             // after all this, we should have saved the confirmed state
@@ -880,6 +2056,7 @@ impl<T: Config> P2PSession<T> {
                         == std::cmp::min(confirmed_frame, self.sync_layer.current_frame())
             );
         }
+        Ok(())
     }
 
     /// Handle events received from the UDP endpoints. Most events are being forwarded to the user for notification, but some require action.
@@ -902,6 +2079,24 @@ impl<T: Config> P2PSession<T> {
                     disconnect_timeout,
                 });
             }
+            // synchronization failed for good, then forward to user
+            Event::SynchronizationFailed => {
+                self.state = SessionState::Failed;
+                self.event_queue
+                    .push_back(GgrsEvent::SynchronizationFailed { addr });
+            }
+            // forward to user
+            Event::IncompatiblePeer { reason } => {
+                self.event_queue
+                    .push_back(GgrsEvent::IncompatiblePeer { addr, reason });
+            }
+            // forward to user
+            Event::SuspiciousPeer { rejected_inputs } => {
+                self.event_queue.push_back(GgrsEvent::SuspiciousPeer {
+                    addr,
+                    rejected_inputs,
+                });
+            }
             // forward to user
             Event::NetworkResumed => {
                 self.event_queue
@@ -913,7 +2108,7 @@ impl<T: Config> P2PSession<T> {
                 self.event_queue.push_back(GgrsEvent::Synchronized { addr });
             }
             // disconnect the player, then forward to user
-            Event::Disconnected => {
+            Event::Disconnected { reason } => {
                 for handle in player_handles {
                     let last_frame = if handle < self.num_players as PlayerHandle {
                         self.local_connect_status[handle].last_frame
@@ -924,7 +2119,8 @@ impl<T: Config> P2PSession<T> {
                     self.disconnect_player_at_frame(handle, last_frame);
                 }
 
-                self.event_queue.push_back(GgrsEvent::Disconnected { addr });
+                self.event_queue
+                    .push_back(GgrsEvent::Disconnected { addr, reason });
             }
             // add the input and all associated information
             Event::Input { input, player } => {
@@ -940,7 +2136,16 @@ impl<T: Config> P2PSession<T> {
                     // update our info
                     self.local_connect_status[player].last_frame = input.frame;
                     // add the remote input
-                    self.sync_layer.add_remote_input(player, input);
+                    if self.sync_layer.add_remote_input(player, input) {
+                        warn!(
+                            "Input queue of player {} is full; holding back inputs from frame {} on",
+                            player, input.frame
+                        );
+                        self.event_queue.push_back(GgrsEvent::InputQueueOverflow {
+                            player,
+                            frame: input.frame,
+                        });
+                    }
                 }
             }
         }
@@ -957,19 +2162,21 @@ impl<T: Config> P2PSession<T> {
                 for remote in self.player_reg.remotes.values_mut() {
                     let mut checked_frames = Vec::new();
 
-                    for (&remote_frame, &remote_checksum) in &remote.pending_checksums {
+                    for (&remote_frame, remote_report) in &remote.pending_checksums {
                         if remote_frame >= self.sync_layer.last_confirmed_frame() {
                             // we're still waiting for inputs for this frame
                             continue;
                         }
-                        if let Some(&local_checksum) =
-                            self.local_checksum_history.get(&remote_frame)
-                        {
-                            if local_checksum != remote_checksum {
+                        if let Some(local_report) = self.local_checksum_history.get(&remote_frame) {
+                            if local_report.checksum != remote_report.checksum
+                                || local_report.input_checksum != remote_report.input_checksum
+                            {
                                 self.event_queue.push_back(GgrsEvent::DesyncDetected {
                                     frame: remote_frame,
-                                    local_checksum,
-                                    remote_checksum,
+                                    local_checksum: local_report.checksum,
+                                    remote_checksum: remote_report.checksum,
+                                    local_input_checksum: local_report.input_checksum,
+                                    remote_input_checksum: remote_report.input_checksum,
                                     addr: remote.peer_addr(),
                                 });
                             }
@@ -1003,13 +2210,25 @@ impl<T: Config> P2PSession<T> {
                         .saved_state_by_frame(frame_to_send)
                         .unwrap_or_else(|| panic!("cell not found!: frame {frame_to_send}"));
 
-                    if let Some(checksum) = cell.checksum() {
+                    if let Some(checksum) = cell.desync_checksum() {
+                        // the inputs of all frames up to the last confirmed frame have been checksummed
+                        let input_checksum = self
+                            .local_input_checksums
+                            .remove(&frame_to_send)
+                            .unwrap_or_else(|| {
+                                panic!("input checksum not found!: frame {frame_to_send}")
+                            });
                         for remote in self.player_reg.remotes.values_mut() {
-                            remote.send_checksum_report(frame_to_send, checksum);
+                            remote.send_checksum_report(frame_to_send, checksum, input_checksum);
                         }
                         self.last_sent_checksum_frame = frame_to_send;
                         // collect locally for later comparison
-                        self.local_checksum_history.insert(frame_to_send, checksum);
+                        let report = ChecksumReport {
+                            frame: frame_to_send,
+                            checksum,
+                            input_checksum,
+                        };
+                        self.local_checksum_history.insert(frame_to_send, report);
                     }
 
                     if self.local_checksum_history.len() > MAX_CHECKSUM_HISTORY_SIZE {
@@ -1024,3 +2243,115 @@ impl<T: Config> P2PSession<T> {
         }
     }
 }
+
+// #########
+// # TESTS #
+// #########
+
+#[cfg(test)]
+mod p2p_session_tests {
+
+    use super::*;
+    use crate::sync_layer::MAX_FRAME;
+    use crate::{Message, MockClock, SessionBuilder};
+    use serde::{Deserialize, Serialize};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    #[repr(C)]
+    #[derive(Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+    struct TestInput {
+        inp: u8,
+    }
+
+    struct TestConfig;
+
+    impl Config for TestConfig {
+        type Input = TestInput;
+        type State = u8;
+        type Address = SocketAddr;
+    }
+
+    struct SilentSocket;
+
+    impl NonBlockingSocket<SocketAddr> for SilentSocket {
+        fn send_to(&mut self, _msg: &Message, _addr: &SocketAddr) {}
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn test_compaction_interval() {
+        let clock = MockClock::new();
+        let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+        let mut sess = SessionBuilder::<TestConfig>::new()
+            .with_clock(clock.clone())
+            .add_player(PlayerType::Local, 0)
+            .unwrap()
+            .add_player(PlayerType::Remote(remote_addr), 1)
+            .unwrap()
+            .start_p2p_session(SilentSocket)
+            .unwrap();
+
+        // a buffer that never grew beyond its usual size keeps its memory, even while only partially filled
+        sess.local_input_checksums
+            .extend((0..MAX_CHECKSUM_HISTORY_SIZE as Frame).map(|frame| (frame, 0)));
+        sess.local_input_checksums
+            .retain(|&frame, _| frame < MAX_CHECKSUM_HISTORY_SIZE as Frame / 4);
+        let usual_capacity = sess.local_input_checksums.capacity();
+        clock.advance(COMPACTION_INTERVAL);
+        sess.poll_remote_clients();
+        assert_eq!(sess.local_input_checksums.capacity(), usual_capacity);
+
+        // after a burst, it is shrunk again with the first poll once the interval has passed since the last compaction
+        sess.local_input_checksums
+            .extend((0..1000).map(|frame| (frame, 0)));
+        sess.local_input_checksums.retain(|&frame, _| frame < 10);
+        clock.advance(COMPACTION_INTERVAL / 2);
+        sess.poll_remote_clients();
+        assert!(sess.local_input_checksums.capacity() >= 1000);
+        clock.advance(COMPACTION_INTERVAL / 2);
+        sess.poll_remote_clients();
+        assert_eq!(sess.local_input_checksums.capacity(), usual_capacity);
+        assert_eq!(sess.local_input_checksums.len(), 10);
+    }
+
+    #[test]
+    fn test_frame_limit() {
+        let mut sess = SessionBuilder::<TestConfig>::new()
+            .with_input_delay(2)
+            .add_player(PlayerType::Local, 0)
+            .unwrap()
+            .add_player(PlayerType::Local, 1)
+            .unwrap()
+            .start_p2p_session(SilentSocket)
+            .unwrap();
+
+        // a session close to the last frame advances normally until it reaches it
+        let start_frame = MAX_FRAME - 3;
+        sess.sync_layer.start_at_frame(start_frame);
+        for status in &mut sess.local_connect_status {
+            status.last_frame = start_frame - 1;
+        }
+        for _ in 0..3 {
+            sess.add_local_input(0, TestInput { inp: 0 }).unwrap();
+            sess.add_local_input(1, TestInput { inp: 0 }).unwrap();
+            for request in sess.advance_frame().unwrap() {
+                if let GgrsRequest::SaveGameState { cell, frame } = request {
+                    cell.save(frame, Some(0), None);
+                }
+            }
+        }
+        assert_eq!(sess.current_frame(), MAX_FRAME);
+
+        // from then on, advancing is refused instead of overflowing the frame counter
+        sess.add_local_input(0, TestInput { inp: 0 }).unwrap();
+        sess.add_local_input(1, TestInput { inp: 0 }).unwrap();
+        assert!(matches!(
+            sess.advance_frame(),
+            Err(GgrsError::InvalidRequest { .. })
+        ));
+        assert_eq!(sess.current_frame(), MAX_FRAME);
+    }
+}
@@ -7,8 +7,8 @@ use crate::{
         protocol::{Event, UdpProtocol},
     },
     sessions::builder::MAX_EVENT_QUEUE_SIZE,
-    Config, Frame, GgrsError, GgrsEvent, GgrsRequest, InputStatus, NetworkStats, NonBlockingSocket,
-    SessionState, NULL_FRAME,
+    Capabilities, Config, Frame, GgrsError, GgrsEvent, GgrsRequest, InputStatus, NetworkStats,
+    NonBlockingSocket, SessionState, NULL_FRAME,
 };
 
 // The amount of frames the spectator advances in a single step if not too far behind
@@ -93,7 +93,9 @@ impl<T: Config> SpectatorSession<T> {
     }
 
     /// Returns all events that happened since last queried for events. If the number of stored events exceeds `MAX_EVENT_QUEUE_SIZE`, the oldest events will be discarded.
-    pub fn events(&mut self) -> Drain<GgrsEvent<T>> {
+    /// GGRS never calls back into your code. Events are queued while the session advances or polls and are handed out here as a single batch,
+    /// so you can handle them whenever it suits you without reentrancy issues.
+    pub fn events(&mut self) -> Drain<'_, GgrsEvent<T>> {
         self.event_queue.drain(..)
     }
 
@@ -102,7 +104,7 @@ impl<T: Config> SpectatorSession<T> {
     /// Failure to do so will cause panics later.
     /// # Errors
     /// - Returns [`NotSynchronized`] if the session is not yet ready to accept input.
-    /// In this case, you either need to start the session or wait for synchronization between clients.
+    ///   In this case, you either need to start the session or wait for synchronization between clients.
     ///
     /// [`Vec<GgrsRequest>`]: GgrsRequest
     /// [`NotSynchronized`]: GgrsError::NotSynchronized
@@ -131,7 +133,8 @@ impl<T: Config> SpectatorSession<T> {
                 inputs: synced_inputs,
             });
 
-            // advance the frame, but only if grabbing the inputs succeeded
+            // advance the frame, but only if grabbing the inputs succeeded. This needs no frame limit: the spectator only
+            // advances to frames the host sent inputs for, and the host stops advancing at the limit itself.
             self.current_frame += 1;
         }
 
@@ -162,7 +165,7 @@ impl<T: Config> SpectatorSession<T> {
         }
 
         // send out all pending UDP messages
-        self.host.send_all_messages(&mut self.socket);
+        self.host.send_all_messages(&mut *self.socket, None);
     }
 
     /// Returns the current frame of a session.
@@ -170,11 +173,31 @@ impl<T: Config> SpectatorSession<T> {
         self.current_frame
     }
 
+    /// Returns the operations this session supports.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::NETWORK_STATS | Capabilities::SUSPEND_TIMEOUTS
+    }
+
     /// Returns the number of players this session was constructed with.
     pub fn num_players(&self) -> usize {
         self.num_players
     }
 
+    /// Suspends the disconnect timeout of the host, e.g. while you are stepping through your game with a debugger.
+    /// See [`P2PSession::suspend_timeouts()`] for details. This is a debugging aid only and must not be used in production.
+    ///
+    /// [`P2PSession::suspend_timeouts()`]: crate::P2PSession::suspend_timeouts
+    pub fn suspend_timeouts(&mut self) {
+        self.host.suspend_timeouts();
+    }
+
+    /// Resumes the disconnect timeout of the host after [`suspend_timeouts()`].
+    ///
+    /// [`suspend_timeouts()`]: Self#method.suspend_timeouts
+    pub fn resume_timeouts(&mut self) {
+        self.host.resume_timeouts();
+    }
+
     fn inputs_at_frame(
         &self,
         frame_to_grab: Frame,
@@ -221,6 +244,24 @@ impl<T: Config> SpectatorSession<T> {
                 });
             }
             // forward to user
+            Event::SynchronizationFailed => {
+                self.state = SessionState::Failed;
+                self.event_queue
+                    .push_back(GgrsEvent::SynchronizationFailed { addr });
+            }
+            // forward to user
+            Event::IncompatiblePeer { reason } => {
+                self.event_queue
+                    .push_back(GgrsEvent::IncompatiblePeer { addr, reason });
+            }
+            // forward to user
+            Event::SuspiciousPeer { rejected_inputs } => {
+                self.event_queue.push_back(GgrsEvent::SuspiciousPeer {
+                    addr,
+                    rejected_inputs,
+                });
+            }
+            // forward to user
             Event::NetworkResumed => {
                 self.event_queue
                     .push_back(GgrsEvent::NetworkResumed { addr });
@@ -231,8 +272,9 @@ impl<T: Config> SpectatorSession<T> {
                 self.event_queue.push_back(GgrsEvent::Synchronized { addr });
             }
             // disconnect the player, then forward to user
-            Event::Disconnected => {
-                self.event_queue.push_back(GgrsEvent::Disconnected { addr });
+            Event::Disconnected { reason } => {
+                self.event_queue
+                    .push_back(GgrsEvent::Disconnected { addr, reason });
             }
             // add the input and all associated information
             Event::Input { input, player } => {
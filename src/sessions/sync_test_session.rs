@@ -1,10 +1,18 @@
 use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::debug;
 
 use crate::error::GgrsError;
 use crate::frame_info::PlayerInput;
+use crate::network::bit_packing;
 use crate::network::messages::ConnectionStatus;
-use crate::sync_layer::SyncLayer;
-use crate::{Config, Frame, GgrsRequest, PlayerHandle};
+use crate::sync_layer::{GameStateCell, SyncLayer};
+use crate::{Capabilities, Config, Frame, GgrsRequest, PlayerHandle};
+
+/// Version of the checkpoint format. Checkpoints with a different version cannot be restored.
+const CHECKPOINT_FORMAT_VERSION: u16 = 1;
 
 /// During a [`SyncTestSession`], GGRS will simulate a rollback every frame and resimulate the last n states, where n is the given check distance.
 /// The resimulated checksums will be compared with the original checksums and report if there was a mismatch.
@@ -19,6 +27,9 @@ where
     dummy_connect_status: Vec<ConnectionStatus>,
     checksum_history: HashMap<Frame, Option<u128>>,
     local_inputs: HashMap<PlayerHandle, PlayerInput<T::Input>>,
+    input_merge: fn(T::Input, T::Input) -> T::Input,
+    save_count: usize,
+    load_count: usize,
 }
 
 impl<T: Config> SyncTestSession<T> {
@@ -27,6 +38,8 @@ impl<T: Config> SyncTestSession<T> {
         max_prediction: usize,
         check_distance: usize,
         input_delay: usize,
+        input_merge: fn(T::Input, T::Input) -> T::Input,
+        max_saved_frames: Option<usize>,
     ) -> Self {
         let mut dummy_connect_status = Vec::new();
         for _ in 0..num_players {
@@ -34,10 +47,20 @@ impl<T: Config> SyncTestSession<T> {
         }
 
         let mut sync_layer = SyncLayer::new(num_players, max_prediction);
+        if let Some(frames) = max_saved_frames {
+            sync_layer.set_max_saved_frames(frames);
+        }
         for i in 0..num_players {
             sync_layer.set_frame_delay(i, input_delay);
         }
 
+        if check_distance > 0 {
+            debug!(
+                "Checksums are compared after {} frames have been advanced; desyncs in earlier frames are detected from then on.",
+                check_distance + 1
+            );
+        }
+
         Self {
             num_players,
             max_prediction,
@@ -46,17 +69,23 @@ impl<T: Config> SyncTestSession<T> {
             dummy_connect_status,
             checksum_history: HashMap::new(),
             local_inputs: HashMap::new(),
+            input_merge,
+            save_count: 0,
+            load_count: 0,
         }
     }
 
     /// Registers local input for a player for the current frame. This should be successfully called for every local player before calling [`advance_frame()`].
-    /// If this is called multiple times for the same player before advancing the frame, older given inputs will be overwritten.
+    /// If this is called multiple times for the same player before advancing the frame, older given inputs will be overwritten,
+    /// unless a merge function has been set with [`SessionBuilder::with_input_merge()`].
     /// In a sync test, all players are considered to be local, so you need to add input for all of them.
     ///
     /// # Errors
     /// - Returns [`InvalidRequest`] when the given handle is not valid (i.e. not between 0 and num_players).
+    /// - Returns [`InvalidRequest`] when the input does not fit into [`Config::INPUT_BITS`].
     ///
     /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`SessionBuilder::with_input_merge()`]: crate::SessionBuilder::with_input_merge
     /// [`InvalidRequest`]: GgrsError::InvalidRequest
     pub fn add_local_input(
         &mut self,
@@ -68,6 +97,11 @@ impl<T: Config> SyncTestSession<T> {
                 info: "The player handle you provided is not valid.".to_owned(),
             });
         }
+        let input = match self.local_inputs.get(&player_handle) {
+            Some(previous) => (self.input_merge)(previous.input, input),
+            None => input,
+        };
+        bit_packing::check_input::<T>(&input)?;
         let player_input = PlayerInput::<T::Input>::new(self.sync_layer.current_frame(), input);
         self.local_inputs.insert(player_handle, player_input);
         Ok(())
@@ -79,15 +113,18 @@ impl<T: Config> SyncTestSession<T> {
     ///
     /// # Errors
     /// - Returns [`MismatchedChecksum`] if checksums don't match after resimulation.
+    /// - Returns [`InvalidRequest`] if the session reached the last frame it can advance to, see [`Frame`].
     ///
     /// [`Vec<GgrsRequest>`]: GgrsRequest
     /// [`MismatchedChecksum`]: GgrsError::MismatchedChecksum
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
     pub fn advance_frame(&mut self) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        self.sync_layer.check_frame_limit()?;
         let mut requests = Vec::new();
 
         // if we advanced far enough into the game do comparisons and rollbacks
         let current_frame = self.sync_layer.current_frame();
-        if self.check_distance > 0 && current_frame > self.check_distance as i32 {
+        if self.comparisons_active() {
             // compare checksums of older frames to our checksum history (where only the first version of any checksum is recorded)
             let oldest_frame_to_check = current_frame - self.check_distance as Frame;
             let mismatched_frames: Vec<_> = (oldest_frame_to_check..=current_frame)
@@ -103,7 +140,7 @@ impl<T: Config> SyncTestSession<T> {
 
             // simulate rollbacks according to the check_distance
             let frame_to = self.sync_layer.current_frame() - self.check_distance as i32;
-            self.adjust_gamestate(frame_to, &mut requests);
+            self.adjust_gamestate(frame_to, &mut requests)?;
         }
 
         // we require inputs for all players
@@ -124,6 +161,7 @@ impl<T: Config> SyncTestSession<T> {
         // we can skip all the saving if the check_distance is 0
         if self.check_distance > 0 {
             requests.push(self.sync_layer.save_current_state());
+            self.save_count += 1;
         }
 
         // get the correct inputs for all players from the sync layer
@@ -149,11 +187,127 @@ impl<T: Config> SyncTestSession<T> {
         Ok(requests)
     }
 
+    /// Returns true if local input for the given player has been registered for the current frame and has not been used by [`advance_frame()`] yet.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    pub fn has_local_input(&self, player_handle: PlayerHandle) -> bool {
+        self.local_inputs.contains_key(&player_handle)
+    }
+
+    /// Returns how many more frames will use default input for the given player. With an input delay of `n` frames, inputs
+    /// added in frame `f` are used in frame `f + n`, so the player has no input for the first `n` frames of the session.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] when the given handle is not valid (i.e. not between 0 and num_players).
+    ///
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn frames_until_input_active(
+        &self,
+        player_handle: PlayerHandle,
+    ) -> Result<usize, GgrsError> {
+        if player_handle >= self.num_players {
+            return Err(GgrsError::InvalidRequest {
+                info: "The player handle you provided is not valid.".to_owned(),
+            });
+        }
+        Ok(self.sync_layer.frames_until_input_active(player_handle))
+    }
+
+    /// Convenience method for tests of your game logic: registers the given inputs for all players (the input at index `i` belongs to
+    /// player handle `i`) and then calls [`advance_frame()`]. This lets you drive deterministic multiplayer scenarios without setting up
+    /// any networking. Inputs registered through [`add_local_input()`] beforehand are replaced.
+    ///
+    /// # Errors
+    /// - Returns [`InvalidRequest`] if the number of inputs does not match the number of players.
+    /// - Returns [`MismatchedChecksum`] if checksums don't match after resimulation.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`add_local_input()`]: Self#method.add_local_input
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    /// [`MismatchedChecksum`]: GgrsError::MismatchedChecksum
+    pub fn advance_frame_with_inputs(
+        &mut self,
+        inputs: &[T::Input],
+    ) -> Result<Vec<GgrsRequest<T>>, GgrsError> {
+        if inputs.len() != self.num_players {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "Expected inputs for {} players, but got {}.",
+                    self.num_players,
+                    inputs.len()
+                ),
+            });
+        }
+        for (handle, &input) in inputs.iter().enumerate() {
+            self.add_local_input(handle, input)?;
+        }
+        self.advance_frame()
+    }
+
+    /// Checks that loading a gamestate restores everything that is saved, which catches a broken load before it causes
+    /// desyncs. The given closure receives the requests just like the ones returned by [`advance_frame()`], so you can pass your
+    /// usual request handler. It is asked to save the current gamestate, load it again and save it once more. The gamestate is
+    /// unchanged afterwards and the session is not affected, so this is a cheap sanity check at setup time.
+    /// # Errors
+    /// - Returns [`StateRoundtripMismatch`] if the checksums of both saves differ.
+    /// - Returns [`InvalidRequest`] if a save did not provide a checksum.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    /// [`StateRoundtripMismatch`]: GgrsError::StateRoundtripMismatch
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn verify_state_roundtrip(
+        &self,
+        mut handle_requests: impl FnMut(Vec<GgrsRequest<T>>),
+    ) -> Result<(), GgrsError> {
+        let frame = self.sync_layer.current_frame();
+        let saved_cell = GameStateCell::default();
+        let reloaded_cell = GameStateCell::default();
+        handle_requests(vec![
+            GgrsRequest::SaveGameState {
+                cell: saved_cell.clone(),
+                frame,
+            },
+            GgrsRequest::LoadGameState {
+                cell: saved_cell.clone(),
+                frame,
+            },
+            GgrsRequest::SaveGameState {
+                cell: reloaded_cell.clone(),
+                frame,
+            },
+        ]);
+
+        match (
+            saved_cell.desync_checksum(),
+            reloaded_cell.desync_checksum(),
+        ) {
+            (Some(saved), Some(reloaded)) if saved != reloaded => {
+                Err(GgrsError::StateRoundtripMismatch {
+                    frame,
+                    saved,
+                    reloaded,
+                })
+            }
+            (Some(_), Some(_)) => Ok(()),
+            _ => Err(GgrsError::InvalidRequest {
+                info: "Verifying the gamestate roundtrip requires checksums.".to_owned(),
+            }),
+        }
+    }
+
     /// Returns the current frame of a session.
     pub fn current_frame(&self) -> Frame {
         self.sync_layer.current_frame()
     }
 
+    /// Returns the operations this session supports.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::LOCAL_INPUT
+            | Capabilities::INPUT_DELAY
+            | Capabilities::DESYNC_DETECTION
+            | Capabilities::CHECKPOINTS
+    }
+
     /// Returns the number of players this session was constructed with.
     pub fn num_players(&self) -> usize {
         self.num_players
@@ -169,6 +323,97 @@ impl<T: Config> SyncTestSession<T> {
         self.check_distance
     }
 
+    /// Returns how many [`GgrsRequest::SaveGameState`] requests the session has issued so far. With a check distance of `k`,
+    /// every frame past frame `k` is saved `k` times: once when it is first simulated and `k - 1` times during resimulation.
+    pub fn save_count(&self) -> usize {
+        self.save_count
+    }
+
+    /// Returns how many [`GgrsRequest::LoadGameState`] requests the session has issued so far. Every advance past frame `k`
+    /// loads a single state to simulate a rollback of `k` frames.
+    pub fn load_count(&self) -> usize {
+        self.load_count
+    }
+
+    /// Returns true if the next call to [`advance_frame()`] simulates a rollback and compares checksums. This is only the case
+    /// once more than `check_distance` frames have been advanced, so no desyncs can be detected during the first frames of a session.
+    /// With a check distance of 0, comparisons are disabled altogether.
+    ///
+    /// [`advance_frame()`]: Self#method.advance_frame
+    pub fn comparisons_active(&self) -> bool {
+        self.check_distance > 0 && self.sync_layer.current_frame() > self.check_distance as Frame
+    }
+
+    /// Returns the oldest and newest frame for which a gamestate is currently saved, or `None` if no gamestate has been saved yet.
+    pub fn saved_frame_range(&self) -> Option<(Frame, Frame)> {
+        self.sync_layer.frame_range()
+    }
+
+    /// Returns the checksum that has been stored with the saved gamestate of the given frame, or `None` if that frame is not
+    /// saved (anymore) or has been saved without a checksum. Together with [`saved_frame_range()`], this allows to compare the
+    /// checksums of any saved frames while debugging.
+    ///
+    /// [`saved_frame_range()`]: Self#method.saved_frame_range
+    pub fn checksum_at(&self, frame: Frame) -> Option<u128> {
+        self.sync_layer.checksum_at(frame)
+    }
+
+    /// Attaches an application-defined label (e.g. a round number or checkpoint name) to the current frame, which helps to find
+    /// your way around long sessions while debugging. Tagging the same frame again replaces its label. Labels are kept for the
+    /// whole session, so you should not tag every frame.
+    pub fn tag_frame(&mut self, label: String) {
+        self.sync_layer.tag_frame(label);
+    }
+
+    /// Returns the label attached to the given frame through [`tag_frame()`], if any.
+    ///
+    /// [`tag_frame()`]: Self#method.tag_frame
+    pub fn frame_label(&self, frame: Frame) -> Option<&str> {
+        self.sync_layer
+            .frame_labels()
+            .get(&frame)
+            .map(String::as_str)
+    }
+
+    /// Returns all frame labels attached through [`tag_frame()`], ordered by frame.
+    ///
+    /// [`tag_frame()`]: Self#method.tag_frame
+    pub fn frame_labels(&self) -> impl Iterator<Item = (Frame, &str)> {
+        self.sync_layer
+            .frame_labels()
+            .iter()
+            .map(|(&frame, label)| (frame, label.as_str()))
+    }
+
+    /// Returns an independent copy of this session, e.g. to explore two different input continuations from the same frame
+    /// while hunting a desync. The copy includes all buffered inputs, the inputs registered for the current frame, the recorded
+    /// checksums and all saved gamestates, so advancing one of the sessions does not affect the other. As with checkpoints,
+    /// your game state for the current frame is not managed by GGRS, so you have to copy it alongside.
+    ///
+    /// Every fork deep-copies all saved gamestates, which are up to `max_prediction + 2` copies of your `Config::State` unless
+    /// set otherwise with [`SessionBuilder::with_max_saved_frames()`], and the input queues of all players, which hold 128 inputs each.
+    /// [`estimate_memory()`] with a queue length of 128 per player estimates the memory of a single fork, apart from the recorded checksums.
+    ///
+    /// [`estimate_memory()`]: crate::estimate_memory
+    /// [`SessionBuilder::with_max_saved_frames()`]: crate::SessionBuilder::with_max_saved_frames
+    pub fn fork(&self) -> Self
+    where
+        T::State: Clone,
+    {
+        Self {
+            num_players: self.num_players,
+            max_prediction: self.max_prediction,
+            check_distance: self.check_distance,
+            sync_layer: self.sync_layer.fork(),
+            dummy_connect_status: self.dummy_connect_status.clone(),
+            checksum_history: self.checksum_history.clone(),
+            local_inputs: self.local_inputs.clone(),
+            input_merge: self.input_merge,
+            save_count: self.save_count,
+            load_count: self.load_count,
+        }
+    }
+
     /// Updates the `checksum_history` and checks if the checksum is identical if it already has been recorded once
     fn checksums_consistent(&mut self, frame_to_check: Frame) -> bool {
         // remove entries older than the `check_distance`
@@ -177,11 +422,11 @@ impl<T: Config> SyncTestSession<T> {
             .retain(|&k, _| k >= oldest_allowed_frame);
 
         match self.sync_layer.saved_state_by_frame(frame_to_check) {
-            Some(latest_cell) => match self.checksum_history.get(&latest_cell.frame()) {
-                Some(&cs) => cs == latest_cell.checksum(),
+            Some(latest_cell) => match self.checksum_history.get(&frame_to_check) {
+                Some(&cs) => cs == latest_cell.desync_checksum(),
                 None => {
                     self.checksum_history
-                        .insert(latest_cell.frame(), latest_cell.checksum());
+                        .insert(frame_to_check, latest_cell.desync_checksum());
                     true
                 }
             },
@@ -189,30 +434,104 @@ impl<T: Config> SyncTestSession<T> {
         }
     }
 
-    fn adjust_gamestate(&mut self, frame_to: Frame, requests: &mut Vec<GgrsRequest<T>>) {
-        let start_frame = self.sync_layer.current_frame();
-        let count = start_frame - frame_to;
+    fn adjust_gamestate(
+        &mut self,
+        frame_to: Frame,
+        requests: &mut Vec<GgrsRequest<T>>,
+    ) -> Result<(), GgrsError> {
+        // rollback to the first incorrect state and step forward to the previous current state, saving every state except the
+        // one we just loaded
+        let count = self.sync_layer.rollback_to(
+            frame_to,
+            &self.dummy_connect_status,
+            |frame| frame != frame_to,
+            requests,
+        )?;
+        self.load_count += 1;
+        self.save_count += count - 1;
+        Ok(())
+    }
+}
 
-        // rollback to the first incorrect state
-        requests.push(self.sync_layer.load_frame(frame_to));
-        self.sync_layer.reset_prediction();
-        assert_eq!(self.sync_layer.current_frame(), frame_to);
+impl<T: Config> SyncTestSession<T>
+where
+    T::State: Serialize + DeserializeOwned,
+{
+    /// Writes a checkpoint of the session to the given writer, e.g. for crash recovery. The checkpoint contains the current frame,
+    /// the buffered inputs, all saved gamestates and the recorded checksums, which is enough to continue deterministically after
+    /// restoring it with [`restore_checkpoint()`]. Inputs registered for the current frame are not part of the checkpoint.
+    ///
+    /// Your game state for the current frame is not managed by GGRS, so you have to store it alongside the checkpoint.
+    /// Checkpoints are only available for sessions whose `Config::State` can be serialized. There is no equivalent for a [`P2PSession`],
+    /// since all peers would have to restore compatible checkpoints of the same frame at the same time.
+    ///
+    /// # Errors
+    /// - Returns [`CheckpointError`] if the checkpoint could not be written.
+    ///
+    /// [`restore_checkpoint()`]: Self#method.restore_checkpoint
+    /// [`P2PSession`]: crate::P2PSession
+    /// [`CheckpointError`]: GgrsError::CheckpointError
+    pub fn save_checkpoint(&self, writer: impl Write) -> Result<(), GgrsError> {
+        let checkpoint = (
+            CHECKPOINT_FORMAT_VERSION,
+            self.num_players,
+            self.max_prediction,
+            self.check_distance,
+            &self.checksum_history,
+            &self.sync_layer,
+        );
+        bincode::serialize_into(writer, &checkpoint).map_err(|e| GgrsError::CheckpointError {
+            info: format!("Could not write checkpoint: {}", e),
+        })
+    }
 
-        // step forward to the previous current state
-        for i in 0..count {
-            let inputs = self
-                .sync_layer
-                .synchronized_inputs(&self.dummy_connect_status);
+    /// Restores a checkpoint written by [`save_checkpoint()`], replacing the state of this session. The session needs to be configured
+    /// with the same number of players, prediction window and check distance as the session the checkpoint was taken from.
+    /// Afterwards, restore your game state of the checkpoint's frame and continue to advance as usual.
+    ///
+    /// # Errors
+    /// - Returns [`CheckpointError`] if the checkpoint could not be read or has a different format version.
+    /// - Returns [`InvalidRequest`] if the checkpoint was taken from a differently configured session.
+    ///
+    /// [`save_checkpoint()`]: Self#method.save_checkpoint
+    /// [`CheckpointError`]: GgrsError::CheckpointError
+    /// [`InvalidRequest`]: GgrsError::InvalidRequest
+    pub fn restore_checkpoint(&mut self, mut reader: impl Read) -> Result<(), GgrsError> {
+        let read_error = |e: bincode::Error| GgrsError::CheckpointError {
+            info: format!("Could not read checkpoint: {}", e),
+        };
+        // check the version first, the rest of a checkpoint in a different format cannot be interpreted
+        let version: u16 = bincode::deserialize_from(&mut reader).map_err(read_error)?;
+        if version != CHECKPOINT_FORMAT_VERSION {
+            return Err(GgrsError::CheckpointError {
+                info: format!(
+                    "The checkpoint has format version {}, but only version {} is supported.",
+                    version, CHECKPOINT_FORMAT_VERSION
+                ),
+            });
+        }
 
-            // first save (except in the first step, because we just loaded that state)
-            if i > 0 {
-                requests.push(self.sync_layer.save_current_state());
-            }
-            // then advance
-            self.sync_layer.advance_frame();
+        let (num_players, max_prediction, check_distance, checksum_history, sync_layer): (
+            usize,
+            usize,
+            usize,
+            HashMap<Frame, Option<u128>>,
+            SyncLayer<T>,
+        ) = bincode::deserialize_from(reader).map_err(read_error)?;
 
-            requests.push(GgrsRequest::AdvanceFrame { inputs });
+        if num_players != self.num_players
+            || max_prediction != self.max_prediction
+            || check_distance != self.check_distance
+        {
+            return Err(GgrsError::InvalidRequest {
+                info: "The checkpoint was taken from a session with a different configuration."
+                    .to_owned(),
+            });
         }
-        assert_eq!(self.sync_layer.current_frame(), start_frame);
+
+        self.checksum_history = checksum_history;
+        self.sync_layer = sync_layer;
+        self.local_inputs.clear();
+        Ok(())
     }
 }
@@ -1,11 +1,19 @@
 use parking_lot::{MappedMutexGuard, Mutex};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::ops::Deref;
 use std::sync::Arc;
+use tracing::debug;
 
+use crate::checksum::canonical_checksum;
 use crate::frame_info::{GameState, PlayerInput};
 use crate::input_queue::InputQueue;
 use crate::network::messages::ConnectionStatus;
-use crate::{Config, Frame, GgrsRequest, InputStatus, PlayerHandle, NULL_FRAME};
+use crate::{Config, Frame, GgrsError, GgrsRequest, InputStatus, PlayerHandle, NULL_FRAME};
+
+/// The last frame a session can advance to. The remaining range of [`Frame`] leaves room for the frame arithmetic
+/// of input delays and prediction windows, so it never overflows.
+pub(crate) const MAX_FRAME: Frame = Frame::MAX / 2;
 
 /// An [`Arc<Mutex>`] that you can [`save()`]/[`load()`] a `T` to/from. These will be handed to the user as part of a [`GgrsRequest`].
 ///
@@ -14,13 +22,91 @@ use crate::{Config, Frame, GgrsRequest, InputStatus, PlayerHandle, NULL_FRAME};
 pub struct GameStateCell<T>(Arc<Mutex<GameState<T>>>);
 
 impl<T> GameStateCell<T> {
-    /// Saves a `T` the user creates into the cell.
+    /// Saves a `T` the user creates into the cell. The checksum is discarded if [`checksums_enabled()`] returns false.
+    ///
+    /// [`checksums_enabled()`]: Self#method.checksums_enabled
     pub fn save(&self, frame: Frame, data: Option<T>, checksum: Option<u128>) {
         let mut state = self.0.lock();
         assert!(frame != NULL_FRAME);
         state.frame = frame;
         state.data = data;
-        state.checksum = checksum;
+        state.checksum = checksum.filter(|_| state.checksums_enabled);
+        state.gameplay_checksum = None;
+        state.unchanged = false;
+    }
+
+    /// Saves a `T` the user creates into the cell, like [`save()`], with an additional checksum covering only the
+    /// gameplay-relevant part of the state. Desync detection and the [`SyncTestSession`] then compare the gameplay checksum
+    /// instead of the full one, so fields that are nondeterministic but purely cosmetic (e.g. the random number generator
+    /// of a particle system) can be left out. The full checksum is still returned by `checksum_at()` of the sessions.
+    ///
+    /// Be careful with what you leave out: any field excluded from the gameplay checksum is no longer checked, so a bug
+    /// that makes it diverge between peers, or lets it leak into gameplay, goes unnoticed.
+    ///
+    /// [`save()`]: Self#method.save
+    /// [`SyncTestSession`]: crate::SyncTestSession
+    pub fn save_with_gameplay_checksum(
+        &self,
+        frame: Frame,
+        data: Option<T>,
+        checksum: Option<u128>,
+        gameplay_checksum: Option<u128>,
+    ) {
+        let mut state = self.0.lock();
+        assert!(frame != NULL_FRAME);
+        state.frame = frame;
+        state.data = data;
+        state.checksum = checksum.filter(|_| state.checksums_enabled);
+        state.gameplay_checksum = gameplay_checksum.filter(|_| state.checksums_enabled);
+        state.unchanged = false;
+    }
+
+    /// Saves a `T` into the cell by writing into the state that was previously stored in it, instead of handing over a newly
+    /// allocated `T`. Since cells are reused for later frames, this lets you reuse buffers (e.g. a `Vec<u8>` you serialize into)
+    /// and avoid an allocation for every save. The closure receives the state of an older frame, or `None` if there is none
+    /// yet, and returns the checksum of the new state.
+    ///
+    /// The previous state is stale and must be overwritten completely. If you don't need to reuse buffers, use [`save()`] instead.
+    ///
+    /// [`save()`]: Self#method.save
+    pub fn save_into(&self, frame: Frame, write: impl FnOnce(&mut Option<T>) -> Option<u128>) {
+        let mut state = self.0.lock();
+        assert!(frame != NULL_FRAME);
+        state.frame = frame;
+        let checksum = write(&mut state.data);
+        state.checksum = checksum.filter(|_| state.checksums_enabled);
+        state.gameplay_checksum = None;
+        state.unchanged = false;
+    }
+
+    /// Returns whether the session uses the checksums saved into this cell. If checksums have been disabled with
+    /// [`SessionBuilder::with_checksums()`], any checksum you save is discarded, so you can skip computing it.
+    ///
+    /// [`SessionBuilder::with_checksums()`]: crate::SessionBuilder#method.with_checksums
+    pub fn checksums_enabled(&self) -> bool {
+        self.0.lock().checksums_enabled
+    }
+
+    /// Reports that the game state of this frame is identical to the state of the previous frame, instead of saving it.
+    /// GGRS will then refer back to the previously saved state when this frame needs to be loaded. This is an opt-in
+    /// optimization for games that know their state did not change, e.g. because all inputs were identical and the
+    /// simulation is idle.
+    ///
+    /// The state of the previous frame must have been saved, so this cannot be used together with sparse saving. A
+    /// [`P2PSession`] in sparse saving mode returns an error from the next call to `advance_frame()` instead.
+    /// Afterwards, [`data()`] will return `None` for this cell.
+    ///
+    /// [`P2PSession`]: crate::P2PSession
+    ///
+    /// [`data()`]: Self#method.data
+    pub fn save_unchanged(&self, frame: Frame) {
+        let mut state = self.0.lock();
+        assert!(frame != NULL_FRAME);
+        state.frame = frame;
+        // the data is kept as a buffer for later calls to `save_into()`, but not handed out anymore
+        state.checksum = None;
+        state.gameplay_checksum = None;
+        state.unchanged = true;
     }
 
     /// Provides direct access to the `T` that the user previously saved into the cell (if there was
@@ -60,10 +146,10 @@ impl<T> GameStateCell<T> {
     /// If you really, really need mutable access to the `T`, then consider using the aptly named
     /// [GameStateAccessor::as_mut_dangerous()].
     pub fn data(&self) -> Option<GameStateAccessor<'_, T>> {
-        if let Ok(mapped_data) =
-            parking_lot::MutexGuard::try_map(self.0.lock(), |state| state.data.as_mut())
-        {
-            return Some(GameStateAccessor(mapped_data));
+        if let Ok(mapped_data) = parking_lot::MutexGuard::try_map(self.0.lock(), |state| {
+            state.data.as_mut().filter(|_| !state.unchanged)
+        }) {
+            Some(GameStateAccessor(mapped_data))
         } else {
             None
         }
@@ -76,6 +162,43 @@ impl<T> GameStateCell<T> {
     pub(crate) fn checksum(&self) -> Option<u128> {
         self.0.lock().checksum
     }
+
+    /// Returns the checksum to compare for desync detection: the gameplay checksum if one was saved, the full checksum otherwise.
+    pub(crate) fn desync_checksum(&self) -> Option<u128> {
+        let state = self.0.lock();
+        state.gameplay_checksum.or(state.checksum)
+    }
+
+    pub(crate) fn is_unchanged(&self) -> bool {
+        self.0.lock().unchanged
+    }
+
+    /// Prepares a cell that is no longer needed to be saved into again. The saved data is kept, so [`save_into()`] can reuse
+    /// its buffers. Returns `None` if the cell is still referenced elsewhere, e.g. by a request the user still holds.
+    ///
+    /// [`save_into()`]: Self#method.save_into
+    pub(crate) fn recycle(self) -> Option<Self> {
+        if Arc::strong_count(&self.0) > 1 {
+            return None;
+        }
+        {
+            let mut state = self.0.lock();
+            state.frame = NULL_FRAME;
+            state.checksum = None;
+            state.gameplay_checksum = None;
+            state.unchanged = false;
+        }
+        Some(self)
+    }
+
+    pub(crate) fn set_checksums_enabled(&self, enabled: bool) {
+        let mut state = self.0.lock();
+        state.checksums_enabled = enabled;
+        if !enabled {
+            state.checksum = None;
+            state.gameplay_checksum = None;
+        }
+    }
 }
 
 impl<T: Clone> GameStateCell<T> {
@@ -88,6 +211,13 @@ impl<T: Clone> GameStateCell<T> {
     }
 }
 
+impl<T: Clone> GameStateCell<T> {
+    /// Returns a new cell holding a copy of the saved state, which is not affected by later saves into this cell.
+    pub(crate) fn deep_clone(&self) -> Self {
+        Self(Arc::new(Mutex::new(self.0.lock().clone())))
+    }
+}
+
 impl<T> Default for GameStateCell<T> {
     fn default() -> Self {
         Self(Arc::new(Mutex::new(GameState::default())))
@@ -100,12 +230,26 @@ impl<T> Clone for GameStateCell<T> {
     }
 }
 
+impl<T: Serialize> Serialize for GameStateCell<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.lock().serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for GameStateCell<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = GameState::deserialize(deserializer)?;
+        Ok(Self(Arc::new(Mutex::new(state))))
+    }
+}
+
 impl<T> std::fmt::Debug for GameStateCell<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let inner = self.0.lock();
         f.debug_struct("GameStateCell")
             .field("frame", &inner.frame)
             .field("checksum", &inner.checksum)
+            .field("gameplay_checksum", &inner.gameplay_checksum)
             .finish_non_exhaustive()
     }
 }
@@ -141,8 +285,54 @@ impl<'c, T> GameStateAccessor<'c, T> {
     }
 }
 
+/// Returns an upper bound of the memory in bytes GGRS uses to store gamestates and inputs for a session with the given configuration,
+/// e.g. to estimate the capacity of a server hosting many sessions. `state_size_bytes` is the size of a single gamestate including
+/// any heap allocations it owns, `input_size` is the size of your `Config::Input` and `prediction_frames` the prediction window.
+/// `input_queue_lengths` holds the input queue length of every player, which is 128 unless set with
+/// [`SessionBuilder::with_input_queue_length()`].
+///
+/// The estimate is computed as follows:
+/// - `prediction_frames + 2` gamestates: one per frame in the prediction window, one for the current frame and one kept around
+///   for frames saved with [`GameStateCell::save_unchanged()`], each with some bookkeeping overhead.
+/// - one input queue per player with the given length plus a prediction, where every input is stored with its frame
+///   number and padded to 16 bytes.
+///
+/// Memory used for networking, events and checksums is not included, and neither are gamestates beyond the prediction window
+/// kept with [`SessionBuilder::with_max_saved_frames()`].
+///
+/// [`SessionBuilder::with_input_queue_length()`]: crate::SessionBuilder::with_input_queue_length
+/// [`SessionBuilder::with_max_saved_frames()`]: crate::SessionBuilder::with_max_saved_frames
+pub const fn estimate_memory(
+    state_size_bytes: usize,
+    input_size: usize,
+    input_queue_lengths: &[usize],
+    prediction_frames: usize,
+) -> usize {
+    let cell_overhead = std::mem::size_of::<Arc<Mutex<GameState<()>>>>()
+        + std::mem::size_of::<Mutex<GameState<()>>>()
+        + 2 * std::mem::size_of::<usize>(); // reference counts of the `Arc`
+    let state_bytes = (prediction_frames + 2) * (state_size_bytes + cell_overhead);
+
+    let padded_input_size = (input_size + std::mem::size_of::<Frame>()).div_ceil(16) * 16;
+    let mut input_bytes = 0;
+    let mut i = 0;
+    while i < input_queue_lengths.len() {
+        input_bytes += (input_queue_lengths[i] + 1) * padded_input_size;
+        i += 1;
+    }
+
+    state_bytes + input_bytes
+}
+
+#[derive(Serialize, Deserialize)]
 pub(crate) struct SavedStates<T> {
     pub states: Vec<GameStateCell<T>>,
+    /// An evicted cell that still holds the state for unchanged frames, together with the last frame it represents.
+    stash: Option<(Frame, GameStateCell<T>)>,
+    /// A former stash that is handed out again instead of a fresh cell, so its buffers can be reused.
+    #[serde(skip, default = "Option::default")]
+    spare: Option<GameStateCell<T>>,
+    checksums_enabled: bool,
 }
 
 impl<T> SavedStates<T> {
@@ -155,7 +345,19 @@ impl<T> SavedStates<T> {
             states.push(GameStateCell::default());
         }
 
-        Self { states }
+        Self {
+            states,
+            stash: None,
+            spare: None,
+            checksums_enabled: true,
+        }
+    }
+
+    fn set_checksums_enabled(&mut self, enabled: bool) {
+        self.checksums_enabled = enabled;
+        for cell in self.states.iter() {
+            cell.set_checksums_enabled(enabled);
+        }
     }
 
     fn get_cell(&self, frame: Frame) -> GameStateCell<T> {
@@ -163,8 +365,106 @@ impl<T> SavedStates<T> {
         let pos = frame as usize % self.states.len();
         self.states[pos].clone()
     }
+
+    /// Returns the cell to save `frame` into. If the evicted state is still needed by an unchanged successor,
+    /// it is moved to the stash and replaced by the former stash, or a fresh cell if there is none.
+    fn cell_for_saving(&mut self, frame: Frame) -> GameStateCell<T> {
+        assert!(frame >= 0);
+        let pos = frame as usize % self.states.len();
+        let evicted = self.states[pos].clone();
+        let evicted_frame = evicted.frame();
+
+        if evicted_frame != NULL_FRAME && evicted_frame < frame {
+            let successor_frame = evicted_frame + 1;
+            let successor = self.get_cell(successor_frame);
+            // the successor might not have been handled by the user yet, so we have to keep the state just in case
+            let successor_needs_state =
+                successor.frame() != successor_frame || successor.is_unchanged();
+
+            if evicted.is_unchanged() {
+                // the stash now also represents the evicted frame
+                if let Some((stash_frame, _)) = self.stash.as_mut() {
+                    if *stash_frame == evicted_frame - 1 {
+                        *stash_frame = evicted_frame;
+                    }
+                }
+            } else if successor_needs_state && frame > successor_frame {
+                self.release_stash();
+                self.states[pos] = self.spare.take().unwrap_or_else(|| {
+                    let cell = GameStateCell::default();
+                    cell.set_checksums_enabled(self.checksums_enabled);
+                    cell
+                });
+                self.stash = Some((evicted_frame, evicted));
+            } else {
+                self.release_stash();
+            }
+        }
+
+        self.states[pos].clone()
+    }
+
+    /// Drops the stash, keeping its cell as the spare if nobody else refers to it.
+    fn release_stash(&mut self) {
+        if let Some((_, cell)) = self.stash.take() {
+            self.spare = cell.recycle();
+        }
+    }
+
+    /// Returns the cell holding the state for `frame`, following unchanged frames back to the last actually saved state.
+    fn resolve_cell(&self, frame: Frame) -> Option<GameStateCell<T>> {
+        let mut frame = frame;
+        while frame >= 0 {
+            let cell = self.get_cell(frame);
+            if cell.frame() != frame {
+                return match &self.stash {
+                    Some((stash_frame, stash)) if *stash_frame == frame => Some(stash.clone()),
+                    _ => None,
+                };
+            }
+            if !cell.is_unchanged() {
+                return Some(cell);
+            }
+            frame -= 1;
+        }
+        None
+    }
+
+    /// Returns a copy of all saved states that is independent of the original cells.
+    fn deep_clone(&self) -> Self
+    where
+        T: Clone,
+    {
+        Self {
+            states: self.states.iter().map(GameStateCell::deep_clone).collect(),
+            stash: self
+                .stash
+                .as_ref()
+                .map(|(frame, cell)| (*frame, cell.deep_clone())),
+            spare: None,
+            checksums_enabled: self.checksums_enabled,
+        }
+    }
+
+    /// Returns the oldest and newest frame currently held by any of the cells.
+    fn frame_range(&self) -> Option<(Frame, Frame)> {
+        let mut frames = self
+            .states
+            .iter()
+            .map(|cell| cell.frame())
+            .filter(|&frame| frame != NULL_FRAME);
+        let first = frames.next()?;
+        Some(frames.fold((first, first), |(oldest, newest), frame| {
+            (oldest.min(frame), newest.max(frame))
+        }))
+    }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "T::State: Serialize",
+    deserialize = "T::State: Deserialize<'de>"
+))]
 pub(crate) struct SyncLayer<T>
 where
     T: Config,
@@ -176,6 +476,12 @@ where
     last_saved_frame: Frame,
     current_frame: Frame,
     input_queues: Vec<InputQueue<T>>,
+    /// Remote inputs that did not fit into the input queues yet, per player.
+    held_back_inputs: Vec<VecDeque<PlayerInput<T::Input>>>,
+    /// Number of frames confirmed inputs are kept around after they have been confirmed.
+    spectator_buffer_frames: usize,
+    /// Application-defined labels attached to frames.
+    frame_labels: BTreeMap<Frame, String>,
 }
 
 impl<T: Config> SyncLayer<T> {
@@ -194,29 +500,166 @@ impl<T: Config> SyncLayer<T> {
             current_frame: 0,
             saved_states: SavedStates::new(max_prediction),
             input_queues,
+            held_back_inputs: vec![VecDeque::new(); num_players],
+            spectator_buffer_frames: 0,
+            frame_labels: BTreeMap::new(),
+        }
+    }
+
+    /// Returns an independent copy of the sync layer, including copies of all saved gamestates.
+    pub(crate) fn fork(&self) -> Self
+    where
+        T::State: Clone,
+    {
+        Self {
+            num_players: self.num_players,
+            max_prediction: self.max_prediction,
+            saved_states: self.saved_states.deep_clone(),
+            last_confirmed_frame: self.last_confirmed_frame,
+            last_saved_frame: self.last_saved_frame,
+            current_frame: self.current_frame,
+            input_queues: self.input_queues.clone(),
+            held_back_inputs: self.held_back_inputs.clone(),
+            spectator_buffer_frames: self.spectator_buffer_frames,
+            frame_labels: self.frame_labels.clone(),
         }
     }
 
+    /// Limits the number of saved gamestates to `frames`. Must be called before any state is saved.
+    pub(crate) fn set_max_saved_frames(&mut self, frames: usize) {
+        assert!(frames > 0);
+        assert_eq!(self.last_saved_frame, NULL_FRAME);
+        let checksums_enabled = self.saved_states.checksums_enabled;
+        self.saved_states = SavedStates::new(frames - 1);
+        self.saved_states.set_checksums_enabled(checksums_enabled);
+    }
+
+    /// Disables or enables checksums for all saved gamestates. Must be called before any state is saved.
+    pub(crate) fn set_checksums_enabled(&mut self, enabled: bool) {
+        assert_eq!(self.last_saved_frame, NULL_FRAME);
+        self.saved_states.set_checksums_enabled(enabled);
+    }
+
+    /// Returns a fresh cell outside of the saved states, e.g. to save a bookmark into.
+    pub(crate) fn detached_cell(&self) -> GameStateCell<T::State> {
+        let cell = GameStateCell::default();
+        cell.set_checksums_enabled(self.saved_states.checksums_enabled);
+        cell
+    }
+
+    /// Keeps confirmed inputs for additional `frames` after they have been confirmed, e.g. to forward them to spectators.
+    pub(crate) fn set_spectator_buffer_frames(&mut self, frames: usize) {
+        self.spectator_buffer_frames = frames;
+    }
+
     pub(crate) fn current_frame(&self) -> Frame {
         self.current_frame
     }
 
+    /// Returns an error if the current frame is the last frame a session can advance to.
+    pub(crate) fn check_frame_limit(&self) -> Result<(), GgrsError> {
+        if self.current_frame >= MAX_FRAME {
+            return Err(GgrsError::InvalidRequest {
+                info: format!(
+                    "The session reached frame {MAX_FRAME} and cannot advance any further. Start a new session to continue."
+                ),
+            });
+        }
+        Ok(())
+    }
+
     pub(crate) fn advance_frame(&mut self) {
         self.current_frame += 1;
     }
 
+    /// Places the sync layer at the given frame without simulating the frames in between. For testing only,
+    /// e.g. to exercise the boundaries of [`load_frame()`](Self::load_frame) around frame 0.
+    #[cfg(test)]
+    pub(crate) fn set_frame(&mut self, frame: Frame) {
+        assert!(frame >= 0);
+        self.current_frame = frame;
+    }
+
+    /// Places the sync layer and its input queues at the given frame, as if the frames before had been simulated and confirmed.
+    /// For testing only and must be called before any input is added, e.g. to run a session close to [`MAX_FRAME`].
+    #[cfg(test)]
+    pub(crate) fn start_at_frame(&mut self, frame: Frame) {
+        assert!(frame > 0);
+        self.current_frame = frame;
+        self.last_confirmed_frame = frame - 1;
+        for queue in &mut self.input_queues {
+            queue.start_at_frame(frame);
+        }
+    }
+
     pub(crate) fn save_current_state(&mut self) -> GgrsRequest<T> {
         self.last_saved_frame = self.current_frame;
-        let cell = self.saved_states.get_cell(self.current_frame);
+        let cell = self.saved_states.cell_for_saving(self.current_frame);
         GgrsRequest::SaveGameState {
             cell,
             frame: self.current_frame,
         }
     }
 
+    /// Replaces the input queue of the given player by one holding `length` inputs. Must be called before any input is added.
+    pub(crate) fn set_input_queue_length(&mut self, player_handle: PlayerHandle, length: usize) {
+        assert!(player_handle < self.num_players as PlayerHandle);
+        assert_eq!(self.current_frame, 0);
+        let delay = self.input_queues[player_handle].frame_delay();
+        self.input_queues[player_handle] = InputQueue::with_length(length);
+        self.input_queues[player_handle].set_frame_delay(delay);
+    }
+
+    /// Returns the number of inputs the input queue of the given player can hold at the same time.
+    pub(crate) fn input_queue_length(&self, player_handle: PlayerHandle) -> usize {
+        self.input_queues[player_handle].capacity()
+    }
+
     pub(crate) fn set_frame_delay(&mut self, player_handle: PlayerHandle, delay: usize) {
         assert!(player_handle < self.num_players as PlayerHandle);
         self.input_queues[player_handle].set_frame_delay(delay);
+        if delay > 0 {
+            debug!(
+                "Player {} has an input delay of {} frames, so the first {} frames of the session use default input for this player.",
+                player_handle, delay, delay
+            );
+        }
+    }
+
+    /// Returns the largest input delay of the given player for which the prediction window and the spectator buffer still fit into its input queue.
+    pub(crate) fn max_frame_delay(&self, player_handle: PlayerHandle) -> usize {
+        self.input_queues[player_handle]
+            .capacity()
+            .saturating_sub(self.spectator_buffer_frames + self.max_prediction + 1)
+    }
+
+    /// Returns how many more frames will use default input for the given player because of the input delay.
+    pub(crate) fn frames_until_input_active(&self, player_handle: PlayerHandle) -> usize {
+        let delay = self.input_queues[player_handle].frame_delay() as Frame;
+        std::cmp::max(delay - self.current_frame, 0) as usize
+    }
+
+    /// Returns the latest confirmed input of the given player, or `None` if there is none yet.
+    pub(crate) fn last_confirmed_input(&self, player_handle: PlayerHandle) -> Option<T::Input> {
+        self.input_queues[player_handle]
+            .last_confirmed_input()
+            .map(|player_input| player_input.input)
+    }
+
+    /// Returns the confirmed input of the given player for the given frame, or `None` if it is not confirmed or already discarded.
+    pub(crate) fn confirmed_input(
+        &self,
+        player_handle: PlayerHandle,
+        frame: Frame,
+    ) -> Option<T::Input> {
+        self.input_queues[player_handle]
+            .confirmed_input(frame)
+            .map(|player_input| player_input.input)
+    }
+
+    /// Returns the share of recent predictions for the given player that turned out to be wrong.
+    pub(crate) fn misprediction_rate(&self, player_handle: PlayerHandle) -> f32 {
+        self.input_queues[player_handle].misprediction_rate()
     }
 
     pub(crate) fn reset_prediction(&mut self) {
@@ -226,7 +669,7 @@ impl<T: Config> SyncLayer<T> {
     }
 
     /// Loads the gamestate indicated by `frame_to_load`.
-    pub(crate) fn load_frame(&mut self, frame_to_load: Frame) -> GgrsRequest<T> {
+    pub(crate) fn load_frame(&mut self, frame_to_load: Frame) -> Result<GgrsRequest<T>, GgrsError> {
         // The state should not be the current state or the state should not be in the future or too far away in the past
         assert!(frame_to_load != NULL_FRAME, "cannot load null frame");
         assert!(
@@ -244,14 +687,56 @@ impl<T: Config> SyncLayer<T> {
             self.max_prediction
         );
 
-        let cell = self.saved_states.get_cell(frame_to_load);
-        assert_eq!(cell.0.lock().frame, frame_to_load);
+        // the state might have been evicted if fewer frames than the prediction window are saved
+        let cell =
+            self.saved_states
+                .resolve_cell(frame_to_load)
+                .ok_or(GgrsError::StateNotSaved {
+                    frame: frame_to_load,
+                })?;
         self.current_frame = frame_to_load;
 
-        GgrsRequest::LoadGameState {
+        Ok(GgrsRequest::LoadGameState {
             cell,
             frame: frame_to_load,
+        })
+    }
+
+    /// Rolls back to `frame_to_load` and resimulates up to the current frame with the inputs known by now, appending the
+    /// requests for the user to `requests`: the load of `frame_to_load`, followed by a save of every resimulated frame for which
+    /// `should_save` returns true and the advance of that frame. Returns the number of resimulated frames.
+    ///
+    /// Comparing the resimulated states is left to the caller, since their checksums are only known once the user has handled
+    /// the requests, and each session compares them to something else: the [`SyncTestSession`] to the checksums it recorded
+    /// during earlier simulations of these frames, the [`P2PSession`] to the checksum reports of remote clients.
+    ///
+    /// [`SyncTestSession`]: crate::SyncTestSession
+    /// [`P2PSession`]: crate::P2PSession
+    pub(crate) fn rollback_to(
+        &mut self,
+        frame_to_load: Frame,
+        connect_status: &[ConnectionStatus],
+        should_save: impl Fn(Frame) -> bool,
+        requests: &mut Vec<GgrsRequest<T>>,
+    ) -> Result<usize, GgrsError> {
+        let current_frame = self.current_frame;
+        let count = current_frame - frame_to_load;
+
+        // load the frame to resimulate from
+        requests.push(self.load_frame(frame_to_load)?);
+        self.reset_prediction();
+
+        // step forward to the previous current frame, but with updated inputs
+        for _ in 0..count {
+            let inputs = self.synchronized_inputs(connect_status);
+            if should_save(self.current_frame) {
+                requests.push(self.save_current_state());
+            }
+            self.advance_frame();
+            requests.push(GgrsRequest::AdvanceFrame { inputs });
         }
+        assert_eq!(self.current_frame, current_frame);
+        Ok(count as usize)
     }
 
     /// Adds local input to the corresponding input queue. Checks if the prediction threshold has been reached. Returns the frame number where the input is actually added to.
@@ -268,12 +753,20 @@ impl<T: Config> SyncLayer<T> {
 
     /// Adds remote input to the corresponding input queue.
     /// Unlike `add_local_input`, this will not check for correct conditions, as remote inputs have already been checked on another device.
+    /// Confirmed inputs are never dropped: if the queue is full, the input is held back until confirmed frames have been discarded.
+    /// Returns true if the queue overflowed with this input, i.e. if it is the first one held back.
     pub(crate) fn add_remote_input(
         &mut self,
         player_handle: PlayerHandle,
         input: PlayerInput<T::Input>,
-    ) {
-        self.input_queues[player_handle].add_input(input);
+    ) -> bool {
+        let held_back = &mut self.held_back_inputs[player_handle];
+        if held_back.is_empty() && self.input_queues[player_handle].has_room_for(input.frame) {
+            self.input_queues[player_handle].add_input(input);
+            return false;
+        }
+        held_back.push_back(input);
+        held_back.len() == 1
     }
 
     /// Returns inputs for all players for the current frame of the sync layer. If there are none for a specific player, return predictions.
@@ -283,7 +776,7 @@ impl<T: Config> SyncLayer<T> {
     ) -> Vec<(T::Input, InputStatus)> {
         let mut inputs = Vec::new();
         for (i, con_stat) in connect_status.iter().enumerate() {
-            if con_stat.disconnected && con_stat.last_frame < self.current_frame {
+            if con_stat.disconnected_at(self.current_frame) {
                 inputs.push((T::Input::default(), InputStatus::Disconnected));
             } else {
                 inputs.push(self.input_queues[i].input(self.current_frame));
@@ -292,6 +785,31 @@ impl<T: Config> SyncLayer<T> {
         inputs
     }
 
+    /// Returns the inputs `synchronized_inputs()` will return for the current frame once the given local inputs have been added,
+    /// without changing the input queues. A pending rollback does not change the inputs of the current frame.
+    pub(crate) fn preview_synchronized_inputs(
+        &self,
+        local_inputs: &HashMap<PlayerHandle, PlayerInput<T::Input>>,
+        connect_status: &[ConnectionStatus],
+    ) -> Vec<(T::Input, InputStatus)> {
+        let mut inputs = Vec::new();
+        for (i, con_stat) in connect_status.iter().enumerate() {
+            if con_stat.disconnected_at(self.current_frame) {
+                inputs.push((T::Input::default(), InputStatus::Disconnected));
+                continue;
+            }
+            let mut queue = self.input_queues[i].clone();
+            if queue.first_incorrect_frame() != NULL_FRAME {
+                queue.reset_prediction();
+            }
+            if let Some(&input) = local_inputs.get(&i) {
+                queue.add_input(input);
+            }
+            inputs.push(queue.input(self.current_frame));
+        }
+        inputs
+    }
+
     /// Returns confirmed inputs for all players for the current frame of the sync layer.
     pub(crate) fn confirmed_inputs(
         &self,
@@ -300,15 +818,33 @@ impl<T: Config> SyncLayer<T> {
     ) -> Vec<PlayerInput<T::Input>> {
         let mut inputs = Vec::new();
         for (i, con_stat) in connect_status.iter().enumerate() {
-            if con_stat.disconnected && con_stat.last_frame < frame {
+            if con_stat.disconnected_at(frame) {
                 inputs.push(PlayerInput::blank_input(NULL_FRAME));
             } else {
-                inputs.push(self.input_queues[i].confirmed_input(frame));
+                let input = self.input_queues[i]
+                    .confirmed_input(frame)
+                    .expect("There is no confirmed input for the requested frame");
+                inputs.push(input);
             }
         }
         inputs
     }
 
+    /// Returns a checksum over the serialized confirmed inputs of all players for the given frame. Inputs of disconnected
+    /// players count as empty.
+    pub(crate) fn input_checksum(&self, frame: Frame, connect_status: &[ConnectionStatus]) -> u128 {
+        let serialized: Vec<Vec<u8>> = self
+            .confirmed_inputs(frame, connect_status)
+            .iter()
+            .map(|input| match input.frame {
+                NULL_FRAME => Vec::new(),
+                _ => bincode::serialize(&input.input).expect("input serialization failed"),
+            })
+            .collect();
+        let fields: Vec<&[u8]> = serialized.iter().map(Vec::as_slice).collect();
+        canonical_checksum(&fields)
+    }
+
     /// Sets the last confirmed frame to a given frame. By raising the last confirmed frame, we can discard all previous frames, as they are no longer necessary.
     pub(crate) fn set_last_confirmed_frame(&mut self, mut frame: Frame, sparse_saving: bool) {
         // don't set the last confirmed frame after the first incorrect frame before a rollback has happened
@@ -332,9 +868,32 @@ impl<T: Config> SyncLayer<T> {
         assert!(first_incorrect == NULL_FRAME || first_incorrect >= frame);
 
         self.last_confirmed_frame = frame;
-        if self.last_confirmed_frame > 0 {
+        // keep the most recent confirmed inputs around a while longer, if requested
+        let discard_frame = frame - self.spectator_buffer_frames as Frame;
+        if discard_frame > 0 {
             for i in 0..self.num_players {
-                self.input_queues[i].discard_confirmed_frames(frame - 1);
+                self.input_queues[i].discard_confirmed_frames(discard_frame - 1);
+            }
+        }
+
+        // make use of the freed space
+        for (queue, held_back) in self.input_queues.iter_mut().zip(&mut self.held_back_inputs) {
+            while let Some(input) = held_back.front() {
+                if !queue.has_room_for(input.frame) {
+                    break;
+                }
+                queue.add_input(*input);
+                held_back.pop_front();
+            }
+        }
+    }
+
+    /// Releases the memory of inputs that have been held back while input queues were full, once all of them have been added.
+    /// The input queues themselves have a fixed length, so their memory never grows.
+    pub(crate) fn compact_inputs(&mut self) {
+        for held_back in self.held_back_inputs.iter_mut() {
+            if held_back.is_empty() {
+                held_back.shrink_to_fit();
             }
         }
     }
@@ -352,15 +911,28 @@ impl<T: Config> SyncLayer<T> {
         first_incorrect
     }
 
-    /// Returns a gamestate through given frame
+    /// Returns a gamestate through given frame. For frames reported as unchanged, this is the cell of the last saved state.
     pub(crate) fn saved_state_by_frame(&self, frame: Frame) -> Option<GameStateCell<T::State>> {
-        let cell = self.saved_states.get_cell(frame);
+        self.saved_states.resolve_cell(frame)
+    }
 
-        if cell.0.lock().frame == frame {
-            Some(cell)
-        } else {
-            None
-        }
+    /// Returns the checksum stored with the gamestate of the given frame, or `None` if the frame is not saved or has no checksum.
+    pub(crate) fn checksum_at(&self, frame: Frame) -> Option<u128> {
+        self.saved_states.resolve_cell(frame)?.checksum()
+    }
+
+    /// Attaches a label to the current frame, replacing any previous label of that frame.
+    pub(crate) fn tag_frame(&mut self, label: String) {
+        self.frame_labels.insert(self.current_frame, label);
+    }
+
+    pub(crate) fn frame_labels(&self) -> &BTreeMap<Frame, String> {
+        &self.frame_labels
+    }
+
+    /// Returns the oldest and newest frame for which a gamestate is saved, or `None` if nothing has been saved yet.
+    pub(crate) fn frame_range(&self) -> Option<(Frame, Frame)> {
+        self.saved_states.frame_range()
     }
 
     /// Returns the latest saved frame
@@ -368,6 +940,15 @@ impl<T: Config> SyncLayer<T> {
         self.last_saved_frame
     }
 
+    /// Returns true if the user reported the state of the last saved frame as unchanged instead of saving it.
+    pub(crate) fn last_saved_state_unchanged(&self) -> bool {
+        if self.last_saved_frame == NULL_FRAME {
+            return false;
+        }
+        let cell = self.saved_states.get_cell(self.last_saved_frame);
+        cell.frame() == self.last_saved_frame && cell.is_unchanged()
+    }
+
     /// Returns the latest confirmed frame
     pub(crate) fn last_confirmed_frame(&self) -> Frame {
         self.last_confirmed_frame
@@ -382,6 +963,7 @@ impl<T: Config> SyncLayer<T> {
 mod sync_layer_tests {
 
     use super::*;
+    use crate::input_queue::INPUT_QUEUE_LENGTH;
     use serde::{Deserialize, Serialize};
     use std::net::SocketAddr;
 
@@ -399,6 +981,60 @@ mod sync_layer_tests {
         type Address = SocketAddr;
     }
 
+    #[test]
+    fn test_estimate_memory() {
+        struct BufferConfig;
+
+        impl Config for BufferConfig {
+            type Input = TestInput;
+            type State = Vec<u8>;
+            type Address = SocketAddr;
+        }
+
+        let (state_size, max_prediction) = (1024, 8);
+        let queue_lengths = [INPUT_QUEUE_LENGTH, 64, 16];
+        let num_players = queue_lengths.len();
+        let mut sync_layer = SyncLayer::<BufferConfig>::new(num_players, max_prediction);
+        for (handle, &length) in queue_lengths.iter().enumerate() {
+            sync_layer.set_input_queue_length(handle, length);
+        }
+        for frame in 0..100 {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                cell.save(frame, Some(vec![0; state_size]), None);
+            }
+            for handle in 0..num_players {
+                sync_layer.add_remote_input(handle, PlayerInput::new(frame, TestInput { inp: 0 }));
+            }
+            sync_layer.advance_frame();
+        }
+
+        // measure the memory actually held by the saved states and input queues
+        let measured_states: usize = sync_layer
+            .saved_states
+            .states
+            .iter()
+            .map(|cell| {
+                let state = cell.0.lock();
+                std::mem::size_of_val(&*state) + state.data.as_ref().map_or(0, Vec::capacity)
+            })
+            .sum();
+        let measured_inputs: usize = sync_layer
+            .input_queues
+            .iter()
+            .map(|queue| (queue.capacity() + 1) * std::mem::size_of::<PlayerInput<TestInput>>())
+            .sum();
+        let measured = measured_states + measured_inputs;
+
+        let estimate = estimate_memory(
+            state_size,
+            std::mem::size_of::<TestInput>(),
+            &queue_lengths,
+            max_prediction,
+        );
+        assert!(estimate >= measured);
+        assert!(estimate < 2 * measured);
+    }
+
     #[test]
     fn test_different_delays() {
         let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
@@ -431,4 +1067,432 @@ mod sync_layer_tests {
             sync_layer.advance_frame();
         }
     }
+
+    #[test]
+    fn test_input_queue_length_per_player() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        sync_layer.set_frame_delay(1, 2);
+        sync_layer.set_input_queue_length(0, 16);
+        sync_layer.set_input_queue_length(1, 64);
+        assert_eq!(sync_layer.input_queue_length(0), 16);
+        assert_eq!(sync_layer.input_queue_length(1), 64);
+        assert_eq!(sync_layer.input_queues[1].frame_delay(), 2);
+
+        // without confirming any frames, only the short queue overflows
+        for i in 0..32 {
+            let game_input = PlayerInput::new(i, TestInput { inp: i as u8 });
+            assert_eq!(sync_layer.add_remote_input(0, game_input), i == 16);
+            assert!(!sync_layer.add_remote_input(1, game_input));
+            sync_layer.advance_frame();
+        }
+        assert!(sync_layer.input_queues[0].confirmed_input(15).is_some());
+        assert!(sync_layer.input_queues[0].confirmed_input(16).is_none());
+        assert!(sync_layer.input_queues[1].confirmed_input(33).is_some());
+
+        // confirming frames makes room for the held back inputs
+        sync_layer.set_last_confirmed_frame(20, false);
+        assert!(sync_layer.input_queues[0].confirmed_input(14).is_none());
+        assert!(sync_layer.input_queues[0].confirmed_input(30).is_some());
+    }
+
+    #[test]
+    fn test_compact_inputs() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        sync_layer.set_input_queue_length(0, 16);
+
+        // a remote far ahead fills the queue, so most of its inputs are held back
+        for i in 0..64 {
+            let game_input = PlayerInput::new(i, TestInput { inp: i as u8 });
+            sync_layer.add_remote_input(0, game_input);
+        }
+        assert!(sync_layer.held_back_inputs[0].capacity() >= 48);
+
+        // a burst of confirmations makes room for all of them, but the memory is kept until compaction
+        for frame in 0..64 {
+            sync_layer.advance_frame();
+            sync_layer.set_last_confirmed_frame(frame, false);
+        }
+        assert!(sync_layer.held_back_inputs[0].is_empty());
+        assert!(sync_layer.held_back_inputs[0].capacity() >= 48);
+        assert!(sync_layer.input_queues[0].confirmed_input(63).is_some());
+
+        sync_layer.compact_inputs();
+        assert_eq!(sync_layer.held_back_inputs[0].capacity(), 0);
+        assert!(sync_layer.input_queues[0].confirmed_input(63).is_some());
+    }
+
+    #[test]
+    fn test_spectator_buffer_frames() {
+        let retained = 10;
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        let mut unbuffered_layer = SyncLayer::<TestConfig>::new(2, 8);
+        sync_layer.set_spectator_buffer_frames(retained);
+
+        let mut connect_status = vec![ConnectionStatus::default(); 2];
+
+        for i in 0..40 {
+            let game_input = PlayerInput::new(i, TestInput { inp: i as u8 });
+            for layer in [&mut sync_layer, &mut unbuffered_layer] {
+                layer.add_remote_input(0, game_input);
+                layer.add_remote_input(1, game_input);
+                layer.synchronized_inputs(&connect_status);
+                layer.advance_frame();
+                layer.set_last_confirmed_frame(i, false);
+            }
+            connect_status[0].last_frame = i;
+            connect_status[1].last_frame = i;
+        }
+
+        // confirmed inputs are still available for the extended window...
+        let confirmed_frame = sync_layer.last_confirmed_frame();
+        for frame in (confirmed_frame - retained as Frame)..=confirmed_frame {
+            let inputs = sync_layer.confirmed_inputs(frame, &connect_status);
+            assert!(inputs.iter().all(|i| i.input.inp == frame as u8));
+        }
+        // ...but not any longer than that
+        let discarded_frame = confirmed_frame - retained as Frame - 2;
+        assert!(sync_layer.input_queues[0]
+            .confirmed_input(discarded_frame)
+            .is_none());
+
+        // without the option, confirmed inputs are discarded right away
+        assert!(unbuffered_layer.input_queues[0]
+            .confirmed_input(confirmed_frame - 2)
+            .is_none());
+    }
+
+    #[test]
+    fn test_frame_range_empty() {
+        let sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        assert_eq!(sync_layer.frame_range(), None);
+    }
+
+    #[test]
+    fn test_frame_range_wraparound() {
+        let max_prediction = 4;
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, max_prediction);
+
+        for frame in 0..20 {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                cell.save(frame, Some(frame as u8), None);
+            } else {
+                panic!("expected a save request");
+            }
+
+            // the buffer holds the current frame plus max_prediction frames before it
+            let oldest = std::cmp::max(0, frame - max_prediction as Frame);
+            assert_eq!(sync_layer.frame_range(), Some((oldest, frame)));
+
+            sync_layer.advance_frame();
+        }
+    }
+
+    #[test]
+    fn test_unchanged_frames_survive_eviction_and_rollback() {
+        let max_prediction = 4;
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, max_prediction);
+
+        // frames 6 to 15 are reported as unchanged, so they all refer back to the state of frame 5
+        let unchanged = |frame: Frame| (6..=15).contains(&frame);
+        let expected = |frame: Frame| if unchanged(frame) { 5 } else { frame as u8 };
+        let save = |sync_layer: &mut SyncLayer<TestConfig>| {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                if unchanged(frame) {
+                    cell.save_unchanged(frame);
+                } else {
+                    cell.save(frame, Some(frame as u8), Some(frame as u128));
+                }
+            } else {
+                panic!("expected a save request");
+            }
+        };
+
+        for frame in 0..20 {
+            save(&mut sync_layer);
+
+            let oldest = std::cmp::max(0, frame - max_prediction as Frame);
+            for saved_frame in oldest..=frame {
+                let cell = sync_layer.saved_state_by_frame(saved_frame).unwrap();
+                assert_eq!(cell.load(), Some(expected(saved_frame)));
+                assert_eq!(cell.checksum(), Some(expected(saved_frame) as u128));
+            }
+
+            // roll back in the middle of the unchanged frames, after frame 5 has been evicted from the ring buffer
+            if frame == 12 {
+                match sync_layer.load_frame(8).unwrap() {
+                    GgrsRequest::LoadGameState { cell, frame } => {
+                        assert_eq!(frame, 8);
+                        assert_eq!(cell.load(), Some(5));
+                    }
+                    _ => panic!("expected a load request"),
+                }
+                for _ in 8..12 {
+                    sync_layer.advance_frame();
+                    save(&mut sync_layer);
+                }
+            }
+
+            sync_layer.advance_frame();
+        }
+    }
+
+    #[test]
+    fn test_save_into_reuses_buffers() {
+        struct BufferConfig;
+
+        impl Config for BufferConfig {
+            type Input = TestInput;
+            type State = Vec<u8>;
+            type Address = SocketAddr;
+        }
+
+        let max_prediction = 4;
+        let mut sync_layer = SyncLayer::<BufferConfig>::new(2, max_prediction);
+        let mut allocations = 0;
+
+        for frame in 0..100 {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                cell.save_into(frame, |buffer| {
+                    let buffer = buffer.get_or_insert_with(|| {
+                        allocations += 1;
+                        Vec::with_capacity(16)
+                    });
+                    let capacity = buffer.capacity();
+                    buffer.clear();
+                    buffer.extend_from_slice(&frame.to_le_bytes());
+                    // no reallocation happened either
+                    assert_eq!(buffer.capacity(), capacity);
+                    Some(frame as u128)
+                });
+            } else {
+                panic!("expected a save request");
+            }
+
+            let cell = sync_layer.saved_state_by_frame(frame).unwrap();
+            assert_eq!(cell.load(), Some(frame.to_le_bytes().to_vec()));
+            assert_eq!(cell.checksum(), Some(frame as u128));
+
+            sync_layer.advance_frame();
+        }
+
+        // one buffer per cell, reused for all later frames
+        assert_eq!(allocations, max_prediction + 1);
+    }
+
+    #[test]
+    fn test_stashed_buffers_are_reused() {
+        struct BufferConfig;
+
+        impl Config for BufferConfig {
+            type Input = TestInput;
+            type State = Vec<u8>;
+            type Address = SocketAddr;
+        }
+
+        let max_prediction = 2;
+        let mut sync_layer = SyncLayer::<BufferConfig>::new(2, max_prediction);
+        let mut allocations = 0;
+
+        // every saved state is followed by unchanged frames that outlive it in the ring buffer, so it has to be stashed
+        for frame in 0..100 {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                if frame % 6 == 0 {
+                    cell.save_into(frame, |buffer| {
+                        let buffer = buffer.get_or_insert_with(|| {
+                            allocations += 1;
+                            Vec::with_capacity(16)
+                        });
+                        buffer.clear();
+                        buffer.extend_from_slice(&frame.to_le_bytes());
+                        None
+                    });
+                } else {
+                    cell.save_unchanged(frame);
+                }
+            } else {
+                panic!("expected a save request");
+            }
+
+            let saved_frame = frame - frame % 6;
+            let cell = sync_layer.saved_state_by_frame(frame).unwrap();
+            assert_eq!(cell.load(), Some(saved_frame.to_le_bytes().to_vec()));
+
+            sync_layer.advance_frame();
+        }
+
+        // one buffer for the saved state, one for the stashed state, swapped back and forth
+        assert_eq!(allocations, 2);
+    }
+
+    #[test]
+    fn test_load_frame_zero() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+            cell.save(frame, Some(0), None);
+        }
+
+        // frame 0 is in the past for every later frame inside the prediction window
+        for frame in 1..=8 {
+            sync_layer.set_frame(frame);
+            assert!(matches!(
+                sync_layer.load_frame(0),
+                Ok(GgrsRequest::LoadGameState { frame: 0, .. })
+            ));
+            assert_eq!(sync_layer.current_frame(), 0);
+        }
+    }
+
+    #[test]
+    fn test_load_frame_near_zero_not_saved() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        sync_layer.set_frame(2);
+        assert!(matches!(
+            sync_layer.load_frame(1),
+            Err(GgrsError::StateNotSaved { frame: 1 })
+        ));
+        assert!(matches!(
+            sync_layer.load_frame(0),
+            Err(GgrsError::StateNotSaved { frame: 0 })
+        ));
+        assert_eq!(sync_layer.current_frame(), 2);
+    }
+
+    #[test]
+    fn test_rollback_to() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        let connect_status = vec![ConnectionStatus::default(); 2];
+
+        // the input of the second player is known until frame 2 and predicted afterwards
+        for frame in 0..6 {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                cell.save(frame, Some(frame as u8), None);
+            }
+            sync_layer.add_remote_input(0, PlayerInput::new(frame, TestInput { inp: frame as u8 }));
+            if frame <= 2 {
+                sync_layer.add_remote_input(1, PlayerInput::new(frame, TestInput { inp: 2 }));
+            }
+            sync_layer.synchronized_inputs(&connect_status);
+            sync_layer.advance_frame();
+        }
+        for frame in 3..6 {
+            sync_layer.add_remote_input(1, PlayerInput::new(frame, TestInput { inp: 10 }));
+        }
+
+        let mut requests = Vec::new();
+        let count = sync_layer
+            .rollback_to(3, &connect_status, |frame| frame != 3, &mut requests)
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(sync_layer.current_frame(), 6);
+        assert_eq!(requests.len(), 6);
+        assert!(matches!(
+            requests[0],
+            GgrsRequest::LoadGameState { frame: 3, .. }
+        ));
+        for (i, frame) in (3..6).enumerate() {
+            if frame > 3 {
+                assert!(matches!(
+                    requests[2 * i],
+                    GgrsRequest::SaveGameState { frame: f, .. } if f == frame
+                ));
+            }
+            match &requests[2 * i + 1] {
+                GgrsRequest::AdvanceFrame { inputs } => {
+                    assert_eq!(inputs[0].0.inp, frame as u8);
+                    assert_eq!(inputs[1].0.inp, 10);
+                    assert_eq!(inputs[1].1, InputStatus::Confirmed);
+                }
+                _ => panic!("expected an advance of frame {frame}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rollback_to_state_not_saved() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        let connect_status = vec![ConnectionStatus::default(); 2];
+        sync_layer.set_frame(3);
+
+        let mut requests = Vec::new();
+        assert!(matches!(
+            sync_layer.rollback_to(1, &connect_status, |_| true, &mut requests),
+            Err(GgrsError::StateNotSaved { frame: 1 })
+        ));
+        assert!(requests.is_empty());
+        assert_eq!(sync_layer.current_frame(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "must load frame in the past")]
+    fn test_load_frame_zero_at_frame_zero() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        sync_layer.set_frame(0);
+        let _ = sync_layer.load_frame(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot load frame outside of prediction window")]
+    fn test_load_frame_zero_outside_prediction_window() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 8);
+        sync_layer.set_frame(9);
+        let _ = sync_layer.load_frame(0);
+    }
+
+    #[test]
+    fn test_checksums_disabled() {
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, 2);
+        sync_layer.set_max_saved_frames(2);
+        sync_layer.set_checksums_enabled(false);
+
+        // the checksum is discarded for every cell, including cells replacing stashed ones
+        for frame in 0..20 {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                assert!(!cell.checksums_enabled());
+                if frame % 3 == 1 {
+                    cell.save_unchanged(frame);
+                } else {
+                    cell.save(frame, Some(frame as u8), Some(frame as u128));
+                }
+            }
+            let cell = sync_layer.saved_state_by_frame(frame).unwrap();
+            assert_eq!(cell.checksum(), None);
+            sync_layer.advance_frame();
+        }
+    }
+
+    #[test]
+    fn test_saved_states_wraparound_matches_reference() {
+        use std::collections::VecDeque;
+
+        let max_prediction = 7;
+        let mut sync_layer = SyncLayer::<TestConfig>::new(2, max_prediction);
+        // reference of all frames that should still be saved, oldest first
+        let mut reference = VecDeque::new();
+
+        for frame in 0..100 {
+            if let GgrsRequest::SaveGameState { cell, frame } = sync_layer.save_current_state() {
+                cell.save(frame, Some(frame as u8), Some(frame as u128));
+            } else {
+                panic!("expected a save request");
+            }
+            reference.push_back(frame);
+            if reference.len() > max_prediction + 1 {
+                let evicted = reference.pop_front().unwrap();
+                assert!(sync_layer.saved_state_by_frame(evicted).is_none());
+            }
+
+            // the newest saved frame is the one we just saved, the oldest is the oldest of the reference
+            assert_eq!(sync_layer.last_saved_frame(), frame);
+            assert_eq!(
+                sync_layer.frame_range(),
+                Some((reference[0], *reference.back().unwrap()))
+            );
+            for &saved_frame in reference.iter() {
+                let cell = sync_layer.saved_state_by_frame(saved_frame).unwrap();
+                assert_eq!(cell.load(), Some(saved_frame as u8));
+            }
+
+            sync_layer.advance_frame();
+        }
+    }
 }
@@ -31,7 +31,7 @@ impl Config for StubConfig {
 }
 
 impl GameStub {
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::new_without_default)]
     pub fn new() -> GameStub {
         GameStub {
             gs: StateStub { frame: 0, state: 0 },
@@ -70,7 +70,7 @@ pub struct RandomChecksumGameStub {
 }
 
 impl RandomChecksumGameStub {
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::new_without_default)]
     pub fn new() -> RandomChecksumGameStub {
         RandomChecksumGameStub {
             gs: StateStub { frame: 0, state: 0 },
@@ -105,7 +105,7 @@ impl RandomChecksumGameStub {
     }
 }
 
-#[derive(Default, Copy, Clone, Hash)]
+#[derive(Default, Copy, Clone, Hash, Serialize, Deserialize)]
 pub struct StateStub {
     pub frame: i32,
     pub state: i32,
@@ -116,7 +116,7 @@ impl StateStub {
         let p0_inputs = inputs[0].0.inp;
         let p1_inputs = inputs[1].0.inp;
 
-        if (p0_inputs + p1_inputs) % 2 == 0 {
+        if (p0_inputs + p1_inputs).is_multiple_of(2) {
             self.state += 2;
         } else {
             self.state -= 1;
@@ -33,7 +33,7 @@ impl Config for StubEnumConfig {
 }
 
 impl GameStubEnum {
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::new_without_default)]
     pub fn new() -> GameStubEnum {
         GameStubEnum {
             gs: StateStubEnum { frame: 0, state: 0 },
@@ -1,11 +1,20 @@
 mod stubs;
 
 use ggrs::{
-    DesyncDetection, GgrsError, GgrsEvent, PlayerType, SessionBuilder, SessionState,
-    UdpNonBlockingSocket,
+    AdvanceDecision, Capabilities, Clock, Config, DesyncDetection, DisconnectReason, FrameEvent,
+    GgrsError, GgrsEvent, GgrsRequest, IncompatibilityReason, InputStatus, Message,
+    MissingLocalInput, MockClock, NetworkSim, NonBlockingSocket, P2PSession, PacketDirection,
+    PacketKind, PlayerType, SessionBuilder, SessionState, UdpNonBlockingSocket,
 };
+use serde::{Deserialize, Deserializer, Serialize};
 use serial_test::serial;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use stubs::{StubConfig, StubInput};
 
 #[test]
@@ -28,6 +37,37 @@ fn test_add_more_players() -> Result<(), GgrsError> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn test_start_session_on_port_in_use() -> Result<(), GgrsError> {
+    let _socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let result = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session_on_port(7777);
+    assert!(matches!(result, Err(GgrsError::SocketError { .. })));
+
+    // a free port works fine
+    let _sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session_on_port(8888)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_add_player_twice() -> Result<(), GgrsError> {
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+
+    let builder = SessionBuilder::<StubConfig>::new().add_player(PlayerType::Local, 0)?;
+    let result = builder.add_player(PlayerType::Remote(remote_addr), 0);
+    assert!(matches!(result, Err(GgrsError::InvalidRequest { .. })));
+    Ok(())
+}
+
 #[test]
 #[serial]
 fn test_start_session() -> Result<(), GgrsError> {
@@ -43,6 +83,78 @@ fn test_start_session() -> Result<(), GgrsError> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn test_spectator_buffer_frames() -> Result<(), GgrsError> {
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8090);
+
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let _sess = SessionBuilder::<StubConfig>::new()
+        .with_spectator_buffer_frames(60)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .add_player(PlayerType::Spectator(spec_addr), 2)?
+        .start_p2p_session(socket)?;
+
+    // does not fit into the input queue together with the prediction window
+    let socket = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let sess = SessionBuilder::<StubConfig>::new()
+        .with_spectator_buffer_frames(120)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .add_player(PlayerType::Spectator(spec_addr), 2)?
+        .start_p2p_session(socket);
+    assert!(matches!(sess, Err(GgrsError::InvalidRequest { .. })));
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_capabilities() -> Result<(), GgrsError> {
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+
+    let sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(socket)?;
+    let capabilities = sess.capabilities();
+
+    assert!(capabilities.contains(Capabilities::LOCAL_INPUT | Capabilities::DISCONNECT_PLAYER));
+    assert!(capabilities.contains(Capabilities::NETWORK_STATS));
+    assert!(capabilities.contains(Capabilities::INPUT_DELAY));
+    assert!(capabilities.contains(Capabilities::SUSPEND_TIMEOUTS));
+    assert!(capabilities.contains(Capabilities::DESYNC_DETECTION));
+    assert!(!capabilities.contains(Capabilities::CHECKPOINTS));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_add_local_input_for_non_local_player() -> Result<(), GgrsError> {
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8090);
+
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .add_player(PlayerType::Spectator(spec_addr), 2)?
+        .start_p2p_session(socket)?;
+
+    assert!(sess.add_local_input(0, StubInput { inp: 0 }).is_ok());
+    assert!(matches!(
+        sess.add_local_input(1, StubInput { inp: 0 }),
+        Err(GgrsError::InvalidRequest { .. })
+    )); // remote player
+    assert!(sess.add_local_input(2, StubInput { inp: 0 }).is_err()); // spectator
+    assert!(sess.add_local_input(5, StubInput { inp: 0 }).is_err()); // invalid handle
+
+    Ok(())
+}
+
 #[test]
 #[serial]
 fn test_disconnect_player() -> Result<(), GgrsError> {
@@ -67,7 +179,7 @@ fn test_disconnect_player() -> Result<(), GgrsError> {
 
 #[test]
 #[serial]
-fn test_synchronize_p2p_sessions() -> Result<(), GgrsError> {
+fn test_disconnect_reason_requested_and_shutdown() -> Result<(), GgrsError> {
     let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
     let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
 
@@ -79,32 +191,56 @@ fn test_synchronize_p2p_sessions() -> Result<(), GgrsError> {
 
     let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
     let mut sess2 = SessionBuilder::<StubConfig>::new()
-        .add_player(PlayerType::Local, 1)?
         .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
         .start_p2p_session(socket2)?;
 
-    assert!(sess1.current_state() == SessionState::Synchronizing);
-    assert!(sess2.current_state() == SessionState::Synchronizing);
-
     for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
     }
+    sess1.events().for_each(drop);
+    sess2.events().for_each(drop);
 
-    assert!(sess1.current_state() == SessionState::Running);
-    assert!(sess2.current_state() == SessionState::Running);
+    // the first session kicks the second one
+    sess1.disconnect_player(1)?;
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let events1: Vec<_> = sess1.events().collect();
+    assert_eq!(events1.len(), 1);
+    assert!(matches!(
+        events1[0],
+        GgrsEvent::Disconnected {
+            addr,
+            reason: DisconnectReason::Requested
+        } if addr == addr2
+    ));
+    let events2: Vec<_> = sess2.events().collect();
+    assert_eq!(events2.len(), 1);
+    assert!(matches!(
+        events2[0],
+        GgrsEvent::Disconnected {
+            addr,
+            reason: DisconnectReason::Shutdown
+        } if addr == addr1
+    ));
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn test_advance_frame_p2p_sessions() -> Result<(), GgrsError> {
+fn test_disconnect_reason_timeout() -> Result<(), GgrsError> {
     let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
     let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
 
     let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
     let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_disconnect_timeout(Duration::from_millis(100))
+        .with_disconnect_notify_delay(Duration::from_millis(50))
         .add_player(PlayerType::Local, 0)?
         .add_player(PlayerType::Remote(addr2), 1)?
         .start_p2p_session(socket1)?;
@@ -115,206 +251,4122 @@ fn test_advance_frame_p2p_sessions() -> Result<(), GgrsError> {
         .add_player(PlayerType::Local, 1)?
         .start_p2p_session(socket2)?;
 
-    assert!(sess1.current_state() == SessionState::Synchronizing);
-    assert!(sess2.current_state() == SessionState::Synchronizing);
-
     for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
     }
+    sess1.events().for_each(drop);
 
-    assert!(sess1.current_state() == SessionState::Running);
-    assert!(sess2.current_state() == SessionState::Running);
-
-    let mut stub1 = stubs::GameStub::new();
-    let mut stub2 = stubs::GameStub::new();
-    let reps = 10;
-    for i in 0..reps {
-        sess1.poll_remote_clients();
-        sess2.poll_remote_clients();
-
-        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
-        let requests1 = sess1.advance_frame().unwrap();
-        stub1.handle_requests(requests1);
-        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
-        let requests2 = sess2.advance_frame().unwrap();
-        stub2.handle_requests(requests2);
+    // the second session goes silent
+    std::thread::sleep(Duration::from_millis(150));
+    sess1.poll_remote_clients();
 
-        // gamestate evolves
-        assert_eq!(stub1.gs.frame, i as i32 + 1);
-        assert_eq!(stub2.gs.frame, i as i32 + 1);
-    }
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GgrsEvent::Disconnected {
+            addr,
+            reason: DisconnectReason::Timeout
+        } if addr == addr2
+    )));
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn test_desyncs_detected() -> Result<(), GgrsError> {
+fn test_disconnect_timeout_with_mock_clock() -> Result<(), GgrsError> {
     let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
     let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
-    let desync_mode = DesyncDetection::On { interval: 100 };
+    let clock = MockClock::new();
 
     let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
     let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_disconnect_timeout(Duration::from_millis(2000))
+        .with_disconnect_notify_delay(Duration::from_millis(500))
+        .with_clock(clock.clone())
         .add_player(PlayerType::Local, 0)?
         .add_player(PlayerType::Remote(addr2), 1)?
-        .with_desync_detection_mode(desync_mode)
         .start_p2p_session(socket1)?;
 
     let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
     let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
         .add_player(PlayerType::Remote(addr1), 0)?
         .add_player(PlayerType::Local, 1)?
-        .with_desync_detection_mode(desync_mode)
         .start_p2p_session(socket2)?;
 
-    while sess1.current_state() != SessionState::Running
-        && sess2.current_state() != SessionState::Running
-    {
+    // synchronization does not need any time to pass
+    for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
     }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    sess1.events().for_each(drop);
 
-    // drain events
-    assert!(sess1.events().chain(sess2.events()).all(|e| match e {
-        GgrsEvent::Synchronizing { .. } | GgrsEvent::Synchronized { .. } => true,
-        _ => false,
-    }));
+    // the second session goes silent, the timeouts trigger exactly when their thresholds are passed
+    clock.advance(Duration::from_millis(500));
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.events().len(), 0);
 
-    let mut stub1 = stubs::GameStub::new();
-    let mut stub2 = stubs::GameStub::new();
+    clock.advance(Duration::from_millis(1));
+    sess1.poll_remote_clients();
+    let events: Vec<_> = sess1.events().collect();
+    assert_eq!(events.len(), 1);
+    assert!(matches!(
+        events[0],
+        GgrsEvent::NetworkInterrupted {
+            addr,
+            disconnect_timeout: 1500
+        } if addr == addr2
+    ));
 
-    // run normally for some frames (past first desync interval)
-    for i in 0..110 {
-        sess1.poll_remote_clients();
-        sess2.poll_remote_clients();
+    clock.advance(Duration::from_millis(1499));
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.events().len(), 0);
 
-        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
-        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+    clock.advance(Duration::from_millis(1));
+    sess1.poll_remote_clients();
+    let events: Vec<_> = sess1.events().collect();
+    assert_eq!(events.len(), 1);
+    assert!(matches!(
+        events[0],
+        GgrsEvent::Disconnected {
+            addr,
+            reason: DisconnectReason::Timeout
+        } if addr == addr2
+    ));
 
-        let requests1 = sess1.advance_frame().unwrap();
-        let requests2 = sess2.advance_frame().unwrap();
+    Ok(())
+}
 
-        stub1.handle_requests(requests1);
-        stub2.handle_requests(requests2);
-    }
+#[test]
+#[serial]
+fn test_time_until_disconnect() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
 
-    // check that there are no unexpected events yet
-    assert_eq!(sess1.events().len(), 0);
-    assert_eq!(sess2.events().len(), 0);
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_disconnect_timeout(Duration::from_millis(2000))
+        .with_disconnect_notify_delay(Duration::from_millis(500))
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
 
-    // run for some more frames
-    for _ in 0..100 {
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess1.time_until_disconnect(0), None);
+    assert_eq!(sess1.time_until_disconnect(2), None);
+    assert_eq!(sess1.time_until_disconnect(1), None);
 
-        // mess up state for peer 1
-        stub1.gs.state = 1234;
+    // the second session goes silent, the countdown starts with the network interruption
+    clock.advance(Duration::from_millis(500));
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.time_until_disconnect(1), None);
 
-        // keep input steady (to avoid loads, which would restore valid state)
-        sess1.add_local_input(0, StubInput { inp: 0 }).unwrap();
-        sess2.add_local_input(1, StubInput { inp: 1 }).unwrap();
+    clock.advance(Duration::from_millis(100));
+    sess1.poll_remote_clients();
+    assert_eq!(
+        sess1.time_until_disconnect(1),
+        Some(Duration::from_millis(1400))
+    );
 
-        let requests1 = sess1.advance_frame().unwrap();
-        let requests2 = sess2.advance_frame().unwrap();
+    clock.advance(Duration::from_millis(1000));
+    sess1.poll_remote_clients();
+    assert_eq!(
+        sess1.time_until_disconnect(1),
+        Some(Duration::from_millis(400))
+    );
 
-        stub1.handle_requests(requests1);
-        stub2.handle_requests(requests2);
-    }
+    // once the peer is disconnected, there is nothing left to count down
+    clock.advance(Duration::from_millis(401));
+    sess1.poll_remote_clients();
+    assert!(sess1
+        .events()
+        .any(|event| matches!(event, GgrsEvent::Disconnected { .. })));
+    assert_eq!(sess1.time_until_disconnect(1), None);
 
-    // check that we got desync events
-    let sess1_events: Vec<_> = sess1.events().collect();
-    let sess2_events: Vec<_> = sess2.events().collect();
-    assert_eq!(sess1_events.len(), 1);
-    assert_eq!(sess2_events.len(), 1);
+    Ok(())
+}
 
-    let GgrsEvent::DesyncDetected {
-        frame: desync_frame1,
-        local_checksum: desync_local_checksum1,
-        remote_checksum: desync_remote_checksum1,
-        addr: desync_addr1,
-    } = sess1_events[0]
-    else {
-        panic!("no desync for peer 1");
-    };
-    assert_eq!(desync_frame1, 200);
-    assert_eq!(desync_addr1, addr2);
-    assert_ne!(desync_local_checksum1, desync_remote_checksum1);
+#[test]
+#[serial]
+fn test_disconnect_flags() -> Result<(), GgrsError> {
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let remote_addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+    let remote_addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8081);
 
-    let GgrsEvent::DesyncDetected {
-        frame: desync_frame2,
-        local_checksum: desync_local_checksum2,
-        remote_checksum: desync_remote_checksum2,
-        addr: desync_addr2,
-    } = sess2_events[0]
-    else {
-        panic!("no desync for peer 2");
-    };
-    assert_eq!(desync_frame2, 200);
-    assert_eq!(desync_addr2, addr1);
-    assert_ne!(desync_local_checksum2, desync_remote_checksum2);
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_num_players(3)
+        .add_player(PlayerType::Remote(remote_addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Remote(remote_addr2), 2)?
+        .start_p2p_session(socket)?;
 
-    // check that checksums match
-    assert_eq!(desync_remote_checksum1, desync_local_checksum2);
-    assert_eq!(desync_remote_checksum2, desync_local_checksum1);
+    assert_eq!(sess.disconnect_flags(0), 0);
+
+    sess.disconnect_player(0)?;
+    assert_eq!(sess.disconnect_flags(0), 0b001);
+    sess.disconnect_player(2)?;
+    assert_eq!(sess.disconnect_flags(0), 0b101);
 
     Ok(())
 }
 
 #[test]
 #[serial]
-fn test_desyncs_and_input_delay_no_panic() -> Result<(), GgrsError> {
+fn test_suspend_timeouts() -> Result<(), GgrsError> {
     let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
     let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
-    let desync_mode = DesyncDetection::On { interval: 100 };
 
     let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
     let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_disconnect_timeout(Duration::from_millis(100))
+        .with_disconnect_notify_delay(Duration::from_millis(50))
         .add_player(PlayerType::Local, 0)?
         .add_player(PlayerType::Remote(addr2), 1)?
-        .with_input_delay(5)
-        .with_desync_detection_mode(desync_mode)
         .start_p2p_session(socket1)?;
 
     let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
     let mut sess2 = SessionBuilder::<StubConfig>::new()
         .add_player(PlayerType::Remote(addr1), 0)?
         .add_player(PlayerType::Local, 1)?
-        .with_input_delay(5)
-        .with_desync_detection_mode(desync_mode)
         .start_p2p_session(socket2)?;
 
-    while sess1.current_state() != SessionState::Running
-        && sess2.current_state() != SessionState::Running
-    {
+    for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
     }
+    sess1.events().for_each(drop);
 
-    // drain events
-    assert!(sess1.events().chain(sess2.events()).all(|e| match e {
-        GgrsEvent::Synchronizing { .. } | GgrsEvent::Synchronized { .. } => true,
-        _ => false,
-    }));
+    // time passes while suspended, but the remote is not reported
+    sess1.suspend_timeouts();
+    std::thread::sleep(Duration::from_millis(150));
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.events().len(), 0);
 
-    let mut stub1 = stubs::GameStub::new();
-    let mut stub2 = stubs::GameStub::new();
+    // the suspended time does not count towards the timeout
+    sess1.resume_timeouts();
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.events().len(), 0);
 
-    // run normally for some frames (past first desync interval)
-    for i in 0..150 {
+    // after resuming, the timeout applies again
+    std::thread::sleep(Duration::from_millis(150));
+    sess1.poll_remote_clients();
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GgrsEvent::Disconnected {
+            reason: DisconnectReason::Timeout,
+            ..
+        }
+    )));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_resume_timeouts_keeps_silence_before_pause() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_disconnect_timeout(Duration::from_millis(2000))
+        .with_disconnect_notify_delay(Duration::from_millis(500))
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
         sess1.poll_remote_clients();
         sess2.poll_remote_clients();
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+    }
 
-        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
-        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+    // the remote has been silent for a second before the pause
+    clock.advance(Duration::from_millis(1000));
+    sess1.poll_remote_clients();
+    sess1.suspend_timeouts();
+    clock.advance(Duration::from_millis(5000));
+    sess1.resume_timeouts();
 
-        let requests1 = sess1.advance_frame().unwrap();
-        let requests2 = sess2.advance_frame().unwrap();
+    // the silence before the pause still counts
+    sess1.poll_remote_clients();
+    assert_eq!(
+        sess1.time_until_disconnect(1),
+        Some(Duration::from_millis(1000))
+    );
+    clock.advance(Duration::from_millis(1001));
+    sess1.poll_remote_clients();
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GgrsEvent::Disconnected {
+            reason: DisconnectReason::Timeout,
+            ..
+        }
+    )));
 
-        stub1.handle_requests(requests1);
-        stub2.handle_requests(requests2);
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_resume_timeouts_after_packet_during_pause() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_disconnect_timeout(Duration::from_millis(2000))
+        .with_disconnect_notify_delay(Duration::from_millis(500))
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_disconnect_timeout(Duration::from_secs(60))
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+    }
+
+    // the remote sends a keep alive late into the pause
+    sess1.suspend_timeouts();
+    clock.advance(Duration::from_millis(5000));
+    sess2.poll_remote_clients();
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+    }
+    sess1.resume_timeouts();
+
+    // the countdown starts at the packet received during the pause, not in the future
+    clock.advance(Duration::from_millis(600));
+    sess1.poll_remote_clients();
+    assert_eq!(
+        sess1.time_until_disconnect(1),
+        Some(Duration::from_millis(1400))
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_resume_timeouts_resets_frame_advantage() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first session runs a few frames ahead of the second one
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..6 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    for i in 0..60 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.frames_ahead() >= 3);
+
+    // after a pause, the advantage measured before does not lead to a stall recommendation
+    sess1.suspend_timeouts();
+    sess1.resume_timeouts();
+    assert_eq!(sess1.frames_ahead(), 0);
+    sess1.events().for_each(drop);
+
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert!(sess1.frames_ahead() < 3);
+    assert!(!sess1
+        .events()
+        .any(|event| matches!(event, GgrsEvent::WaitRecommendation { .. })));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_should_advance() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    assert_eq!(sess1.should_advance(), AdvanceDecision::WaitForSync);
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.should_advance(), AdvanceDecision::Advance);
+
+    // the first session runs ahead until its prediction window is used up
+    let mut stub1 = stubs::GameStub::new();
+    let mut frame = 0;
+    while sess1.should_advance() == AdvanceDecision::Advance {
+        sess1.add_local_input(0, StubInput { inp: frame })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        frame += 1;
+    }
+    assert_eq!(sess1.current_frame(), sess1.max_prediction() as i32);
+    assert_eq!(sess1.should_advance(), AdvanceDecision::Stall { frames: 1 });
+
+    // once the inputs of the second session arrive, it can advance again
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..2 {
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.should_advance(), AdvanceDecision::Advance);
+
+    // while the first session keeps running ahead, it is eventually told to let the second session catch up
+    let mut stall = None;
+    for i in 2..70 {
+        let decision = sess1.should_advance();
+        // asking is free of side effects
+        assert_eq!(sess1.should_advance(), decision);
+        if let AdvanceDecision::Stall { frames } = decision {
+            stall = Some(frames);
+            break;
+        }
+        sess1.add_local_input(0, StubInput { inp: frame })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        frame += 1;
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    let frames = stall.expect("the first session should have been told to stall");
+    assert!(frames >= 3);
+    assert!(!sess1
+        .events()
+        .any(|event| matches!(event, GgrsEvent::WaitRecommendation { .. })));
+
+    // advancing anyway queues the same recommendation as a wait recommendation event
+    sess1.add_local_input(0, StubInput { inp: frame })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    let recommendations: Vec<_> = sess1
+        .events()
+        .filter_map(|event| match event {
+            GgrsEvent::WaitRecommendation { skip_frames } => Some(skip_frames),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(recommendations, vec![frames]);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_input_compression_stats() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // identical inputs compress very well
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for _ in 0..60 {
+        sess1.add_local_input(0, StubInput { inp: 0 })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 0 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    // stats are only available after a second
+    clock.advance(Duration::from_secs(1));
+    let stats = sess1.network_stats(1)?;
+    assert!(stats.bytes_sent_raw > 0);
+    let ratio = stats.bytes_sent_compressed as f64 / stats.bytes_sent_raw as f64;
+    assert!(ratio < 0.5, "compression ratio {}", ratio);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+#[cfg(feature = "metrics")]
+fn test_metrics_prometheus() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..20 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    // network stats are only available after a second
+    clock.advance(Duration::from_secs(1));
+    let metrics = sess1.metrics_prometheus();
+
+    for line in metrics.lines() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            assert!(comment.starts_with("HELP ggrs_") || comment.starts_with("TYPE ggrs_"));
+            continue;
+        }
+        // every sample is a metric name with optional labels, followed by a number
+        let (name, value) = line.rsplit_once(' ').expect("sample without value");
+        assert!(value.parse::<f64>().is_ok(), "invalid value in {}", line);
+        let metric_name = name.split('{').next().unwrap();
+        assert!(metric_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_'));
+        let labels = &name[metric_name.len()..];
+        assert!(labels.is_empty() || (labels.starts_with('{') && labels.ends_with('}')));
+    }
+    assert!(metrics.contains("ggrs_current_frame 20"));
+    assert!(metrics.contains("ggrs_ping_milliseconds{player=\"1\"}"));
+    assert!(metrics.contains("ggrs_send_kbps{player=\"1\"}"));
+    assert!(metrics.contains("ggrs_recv_kbps{player=\"1\"}"));
+
+    Ok(())
+}
+
+#[test]
+fn test_local_players_only() -> Result<(), GgrsError> {
+    // a socket that counts the messages sent through it
+    struct CountingSocket(Arc<AtomicUsize>);
+
+    impl NonBlockingSocket<SocketAddr> for CountingSocket {
+        fn send_to(&mut self, _msg: &Message, _addr: &SocketAddr) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            Vec::new()
+        }
+    }
+
+    let messages_sent = Arc::new(AtomicUsize::new(0));
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(CountingSocket(messages_sent.clone()))?;
+
+    // no synchronization needed
+    assert_eq!(sess.current_state(), SessionState::Running);
+
+    let mut stub = stubs::GameStub::new();
+    for i in 0..100 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+        assert_eq!(stub.gs.frame, i as i32 + 1);
+    }
+
+    assert_eq!(sess.events().len(), 0);
+    assert_eq!(messages_sent.load(Ordering::SeqCst), 0);
+
+    Ok(())
+}
+
+fn start_local_session(policy: MissingLocalInput) -> Result<P2PSession<StubConfig>, GgrsError> {
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    SessionBuilder::<StubConfig>::new()
+        .with_missing_local_input(policy)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket)
+}
+
+/// Returns the inputs of the frame the requests advance to.
+fn advanced_inputs(requests: &[GgrsRequest<StubConfig>]) -> Vec<u32> {
+    match requests.last() {
+        Some(GgrsRequest::AdvanceFrame { inputs }) => inputs.iter().map(|(i, _)| i.inp).collect(),
+        _ => panic!("the last request should advance the frame"),
+    }
+}
+
+#[test]
+#[serial]
+fn test_missing_local_input_error() -> Result<(), GgrsError> {
+    let mut sess = start_local_session(MissingLocalInput::Error)?;
+    sess.add_local_input(0, StubInput { inp: 7 })?;
+    assert!(matches!(
+        sess.advance_frame(),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+    assert_eq!(sess.current_frame(), 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_missing_local_input_zero() -> Result<(), GgrsError> {
+    let mut sess = start_local_session(MissingLocalInput::Zero)?;
+    sess.add_local_input(0, StubInput { inp: 7 })?;
+    sess.add_local_input(1, StubInput { inp: 3 })?;
+    sess.advance_frame()?;
+
+    sess.add_local_input(0, StubInput { inp: 8 })?;
+    assert_eq!(advanced_inputs(&sess.advance_frame()?), vec![8, 0]);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_missing_local_input_repeat_last() -> Result<(), GgrsError> {
+    let mut sess = start_local_session(MissingLocalInput::RepeatLast)?;
+    // without any previous input, the default input is used
+    sess.add_local_input(0, StubInput { inp: 7 })?;
+    assert_eq!(advanced_inputs(&sess.advance_frame()?), vec![7, 0]);
+
+    sess.add_local_input(1, StubInput { inp: 3 })?;
+    assert_eq!(advanced_inputs(&sess.advance_frame()?), vec![7, 3]);
+    assert_eq!(advanced_inputs(&sess.advance_frame()?), vec![7, 3]);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_defaulted_local_players() -> Result<(), GgrsError> {
+    let mut sess = start_local_session(MissingLocalInput::Zero)?;
+    assert!(sess.defaulted_local_players().is_empty());
+
+    // an intentional default input is not reported
+    sess.add_local_input(0, StubInput { inp: 7 })?;
+    sess.add_local_input(1, StubInput::default())?;
+    assert_eq!(advanced_inputs(&sess.advance_frame()?), vec![7, 0]);
+    assert!(sess.defaulted_local_players().is_empty());
+
+    // a forgotten input is, even though the resulting input is the same
+    sess.add_local_input(0, StubInput { inp: 7 })?;
+    assert_eq!(advanced_inputs(&sess.advance_frame()?), vec![7, 0]);
+    assert_eq!(sess.defaulted_local_players(), &[1]);
+
+    assert_eq!(advanced_inputs(&sess.advance_frame()?), vec![0, 0]);
+    assert_eq!(sess.defaulted_local_players(), &[0, 1]);
+
+    sess.add_local_input(0, StubInput { inp: 1 })?;
+    sess.add_local_input(1, StubInput { inp: 2 })?;
+    sess.advance_frame()?;
+    assert!(sess.defaulted_local_players().is_empty());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_next_frame_inputs() -> Result<(), GgrsError> {
+    // a socket that leaves received messages queued while told to
+    struct GatedSocket {
+        inner: UdpNonBlockingSocket,
+        receiving: Arc<AtomicBool>,
+    }
+
+    impl NonBlockingSocket<SocketAddr> for GatedSocket {
+        fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+            self.inner.send_to(msg, addr);
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            if self.receiving.load(Ordering::SeqCst) {
+                self.inner.receive_all_messages()
+            } else {
+                Vec::new()
+            }
+        }
+    }
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let receiving1 = Arc::new(AtomicBool::new(true));
+    let receiving2 = Arc::new(AtomicBool::new(true));
+
+    let socket1 = GatedSocket {
+        inner: UdpNonBlockingSocket::bind_to_port(7777).unwrap(),
+        receiving: receiving1.clone(),
+    };
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = GatedSocket {
+        inner: UdpNonBlockingSocket::bind_to_port(8888).unwrap(),
+        receiving: receiving2.clone(),
+    };
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    assert!(matches!(
+        sess1.next_frame_inputs(),
+        Err(GgrsError::NotSynchronized)
+    ));
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // without local input, advancing the frame would fail as well
+    assert!(matches!(
+        sess1.next_frame_inputs(),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    // returns the inputs of the frame the requests advance to, together with their status
+    fn advanced_inputs_with_status(
+        requests: &[GgrsRequest<StubConfig>],
+    ) -> Vec<(u32, InputStatus)> {
+        match requests.last() {
+            Some(GgrsRequest::AdvanceFrame { inputs }) => {
+                inputs.iter().map(|(i, status)| (i.inp, *status)).collect()
+            }
+            _ => panic!("the last request should advance the frame"),
+        }
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut predicted_frames = 0;
+    for i in 0..40 {
+        for (sess, stub, receiving, handle, inp) in [
+            (&mut sess1, &mut stub1, &receiving1, 0, i),
+            (&mut sess2, &mut stub2, &receiving2, 1, i % 3),
+        ] {
+            // receive inputs only before the preview, so none arrive during advance_frame()
+            sess.poll_remote_clients();
+            receiving.store(false, Ordering::SeqCst);
+
+            sess.add_local_input(handle, StubInput { inp })?;
+            let preview: Vec<_> = sess
+                .next_frame_inputs()?
+                .iter()
+                .map(|(i, status)| (i.inp, *status))
+                .collect();
+            let requests = sess.advance_frame()?;
+            receiving.store(true, Ordering::SeqCst);
+            assert_eq!(preview, advanced_inputs_with_status(&requests));
+            stub.handle_requests(requests);
+            if preview
+                .iter()
+                .any(|(_, status)| *status == InputStatus::Predicted)
+            {
+                predicted_frames += 1;
+            }
+        }
+    }
+    // the previews covered predictions and pending rollbacks
+    assert!(predicted_frames > 0);
+    assert!(sess1.peak_rollback_depth() > 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_save_unchanged_rejected_in_sparse_saving_mode() -> Result<(), GgrsError> {
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_sparse_saving_mode(true)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket)?;
+
+    // the first frame is saved normally, later ones are reported as unchanged
+    let mut stub = stubs::GameStub::new();
+    for i in 0..20 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        match sess.advance_frame() {
+            Ok(requests) => {
+                for request in requests {
+                    match request {
+                        GgrsRequest::SaveGameState { cell, frame } if frame > 0 => {
+                            cell.save_unchanged(frame)
+                        }
+                        request => stub.handle_requests(vec![request]),
+                    }
+                }
+            }
+            Err(GgrsError::InvalidRequest { .. }) => {
+                assert!(sess.current_frame() > 0);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    panic!("save_unchanged() should have been rejected in sparse saving mode");
+}
+
+#[test]
+#[serial]
+fn test_latest_saved_state() -> Result<(), GgrsError> {
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket)?;
+    assert!(sess.latest_saved_state().is_none());
+
+    let mut stub = stubs::GameStub::new();
+    for i in 0..20 {
+        let before = stub.gs;
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        let requests = sess.advance_frame()?;
+        // the save request has not been handled yet
+        if i == 0 {
+            assert!(sess.latest_saved_state().is_none());
+        }
+        stub.handle_requests(requests);
+
+        // the latest saved state is the newest one, not the oldest one still kept
+        let (frame, saved) = sess.latest_saved_state().expect("a state should be saved");
+        assert_eq!(frame, i as i32);
+        assert_eq!(saved.frame, before.frame);
+        assert_eq!(saved.state, before.state);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_bookmark() -> Result<(), GgrsError> {
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket)?;
+    assert!(sess.bookmark().is_none());
+
+    let mut stub = stubs::GameStub::new();
+    for i in 0..5 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+
+    // saving a bookmark does not advance the frame
+    let bookmarked = stub.gs;
+    let request = sess.save_bookmark();
+    assert!(matches!(
+        request,
+        GgrsRequest::SaveGameState { frame: 5, .. }
+    ));
+    assert!(sess.bookmark().is_none());
+    stub.handle_requests(vec![request]);
+    assert_eq!(sess.current_frame(), 5);
+    assert_eq!(stub.gs.frame, 5);
+
+    // the bookmark survives advancing past the saved states used for rollbacks
+    for i in 5..30 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+    let (frame, cell) = sess.bookmark().expect("the bookmark should be saved");
+    assert_eq!(frame, 5);
+    let saved = cell.load().expect("the cell should hold a state");
+    assert_eq!(saved.frame, bookmarked.frame);
+    assert_eq!(saved.state, bookmarked.state);
+
+    Ok(())
+}
+
+#[test]
+fn test_checksums_disabled() -> Result<(), GgrsError> {
+    for checksums in [true, false] {
+        let mut sess = SessionBuilder::<StubConfig>::new()
+            .with_checksums(checksums)
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(SilentSocket)?;
+
+        // the game stub always saves a checksum, but it is only kept if checksums are enabled
+        let mut stub = stubs::GameStub::new();
+        for i in 0..50 {
+            sess.add_local_input(0, StubInput { inp: i })?;
+            sess.add_local_input(1, StubInput { inp: i })?;
+            stub.handle_requests(sess.advance_frame()?);
+        }
+        let (oldest, newest) = sess
+            .saved_frame_range()
+            .expect("states should have been saved");
+        for frame in oldest..=newest {
+            assert_eq!(
+                sess.checksum_at(frame).is_some(),
+                checksums,
+                "frame {frame}"
+            );
+        }
+    }
+
+    // without checksums, there is nothing to compare
+    let sess = SessionBuilder::<StubConfig>::new()
+        .with_checksums(false)
+        .with_desync_detection_mode(DesyncDetection::On { interval: 10 })
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(SilentSocket);
+    assert!(matches!(sess, Err(GgrsError::InvalidRequest { .. })));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_input_delay_bounds() -> Result<(), GgrsError> {
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let _sess = SessionBuilder::<StubConfig>::new()
+        .with_max_prediction_window(8)
+        .with_input_delay(119)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(socket)?;
+
+    let socket = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let sess = SessionBuilder::<StubConfig>::new()
+        .with_max_prediction_window(8)
+        .with_input_delay(120)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(socket);
+    assert!(matches!(sess, Err(GgrsError::InvalidRequest { .. })));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_input_queue_length_bounds() -> Result<(), GgrsError> {
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+
+    // every queue must fit the prediction window and input delay...
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let sess = SessionBuilder::<StubConfig>::new()
+        .with_max_prediction_window(8)
+        .with_input_delay(120)
+        .with_input_queue_length(0, 256)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(socket);
+    assert!(sess.is_err());
+
+    // ...so longer queues for all players allow a larger input delay
+    let socket = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let _sess = SessionBuilder::<StubConfig>::new()
+        .with_max_prediction_window(8)
+        .with_input_delay(120)
+        .with_input_queue_length(0, 256)
+        .with_input_queue_length(1, 256)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(socket)?;
+
+    let socket = UdpNonBlockingSocket::bind_to_port(9999).unwrap();
+    let sess = SessionBuilder::<StubConfig>::new()
+        .with_max_prediction_window(8)
+        .with_input_queue_length(1, 8)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(socket);
+    assert!(matches!(sess, Err(GgrsError::InvalidRequest { .. })));
+
+    // only players have input queues
+    let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let sess = SessionBuilder::<StubConfig>::new()
+        .with_input_queue_length(2, 64)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(socket);
+    assert!(matches!(sess, Err(GgrsError::InvalidRequest { .. })));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_sync_retries_after_dropped_handshake_packets() -> Result<(), GgrsError> {
+    // a socket that drops the first few messages sent through it
+    struct DroppingSocket {
+        socket: UdpNonBlockingSocket,
+        drops_left: usize,
+    }
+
+    impl NonBlockingSocket<SocketAddr> for DroppingSocket {
+        fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+            if self.drops_left > 0 {
+                self.drops_left -= 1;
+            } else {
+                self.socket.send_to(msg, addr);
+            }
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            self.socket.receive_all_messages()
+        }
+    }
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = DroppingSocket {
+        socket: UdpNonBlockingSocket::bind_to_port(7777).unwrap(),
+        drops_left: 4,
+    };
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_sync_retries(5)
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = DroppingSocket {
+        socket: UdpNonBlockingSocket::bind_to_port(8888).unwrap(),
+        drops_left: 4,
+    };
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_sync_retries(5)
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    // every step waits long enough for the next retry
+    for _ in 0..20 {
+        for _ in 0..10 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+        if sess1.current_state() == SessionState::Running
+            && sess2.current_state() == SessionState::Running
+        {
+            break;
+        }
+        clock.advance(Duration::from_millis(2000));
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+    assert!(!sess1
+        .events()
+        .any(|e| matches!(e, GgrsEvent::SynchronizationFailed { .. })));
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_retries_exhausted() -> Result<(), GgrsError> {
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_sync_retries(3)
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(SilentSocket)?;
+
+    // the remote never answers, so every retry goes unanswered
+    for _ in 0..3 {
+        clock.advance(Duration::from_millis(2000));
+        sess.poll_remote_clients();
+        assert_eq!(sess.current_state(), SessionState::Synchronizing);
+        assert_eq!(sess.events().len(), 0);
+    }
+
+    clock.advance(Duration::from_millis(2000));
+    sess.poll_remote_clients();
+    assert_eq!(sess.current_state(), SessionState::Failed);
+    let events: Vec<_> = sess.events().collect();
+    assert_eq!(events.len(), 1);
+    assert!(matches!(
+        events[0],
+        GgrsEvent::SynchronizationFailed { addr } if addr == remote_addr
+    ));
+
+    // the failed state is terminal
+    clock.advance(Duration::from_millis(2000));
+    sess.poll_remote_clients();
+    assert_eq!(sess.current_state(), SessionState::Failed);
+    assert!(matches!(
+        sess.advance_frame(),
+        Err(GgrsError::NotSynchronized)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_timeout() -> Result<(), GgrsError> {
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_sync_timeout(Duration::from_millis(1000))
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(SilentSocket)?;
+
+    clock.advance(Duration::from_millis(1000));
+    sess.poll_remote_clients();
+    assert_eq!(sess.current_state(), SessionState::Synchronizing);
+
+    clock.advance(Duration::from_millis(1));
+    sess.poll_remote_clients();
+    assert_eq!(sess.current_state(), SessionState::Failed);
+    assert!(sess
+        .events()
+        .any(|e| matches!(e, GgrsEvent::SynchronizationFailed { addr } if addr == remote_addr)));
+
+    Ok(())
+}
+
+// a socket that never receives anything
+struct SilentSocket;
+
+impl NonBlockingSocket<SocketAddr> for SilentSocket {
+    fn send_to(&mut self, _msg: &Message, _addr: &SocketAddr) {}
+
+    fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+        Vec::new()
+    }
+}
+
+#[test]
+#[serial]
+fn test_lost_input_is_resent() -> Result<(), GgrsError> {
+    // a socket that drops all messages sent through it while told to
+    struct DroppingSocket {
+        inner: UdpNonBlockingSocket,
+        dropping: Arc<AtomicBool>,
+    }
+
+    impl NonBlockingSocket<SocketAddr> for DroppingSocket {
+        fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+            if !self.dropping.load(Ordering::SeqCst) {
+                self.inner.send_to(msg, addr);
+            }
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            self.inner.receive_all_messages()
+        }
+    }
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let dropping = Arc::new(AtomicBool::new(false));
+    let socket2 = DroppingSocket {
+        inner: UdpNonBlockingSocket::bind_to_port(8888).unwrap(),
+        dropping: dropping.clone(),
+    };
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .with_packet_log(true)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the only input of the second session gets lost
+    sess2.drain_packet_events();
+    let mut stub2 = stubs::GameStub::new();
+    sess2.add_local_input(1, StubInput { inp: 1 })?;
+    dropping.store(true, Ordering::SeqCst);
+    stub2.handle_requests(sess2.advance_frame()?);
+    dropping.store(false, Ordering::SeqCst);
+    let dropped: Vec<_> = sess2
+        .drain_packet_events()
+        .into_iter()
+        .filter(|packet| packet.direction == PacketDirection::Sent)
+        .map(|packet| packet.kind)
+        .collect();
+    assert_eq!(dropped, vec![PacketKind::Input]);
+
+    // the first session predicts until its prediction window is used up and then waits
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..20 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_frame(), sess1.max_prediction() as i32);
+    assert_eq!(sess1.confirmed_frame(), -1);
+
+    // after a while, the unacknowledged input is sent again
+    clock.advance(Duration::from_millis(300));
+    sess2.poll_remote_clients();
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.confirmed_frame(), 0);
+
+    // the first session corrects its prediction and continues as soon as further inputs arrive
+    for i in 1..4 {
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    sess1.add_local_input(0, StubInput { inp: 20 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert!(sess1.last_rollback_frames() > 0);
+    assert_eq!(sess1.current_frame(), sess1.max_prediction() as i32 + 1);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_duplicated_and_reordered_packets() -> Result<(), GgrsError> {
+    // a socket that duplicates messages and swaps the order of consecutive messages
+    struct UnreliableSocket {
+        socket: UdpNonBlockingSocket,
+        held_back: Option<(Message, SocketAddr)>,
+    }
+
+    impl NonBlockingSocket<SocketAddr> for UnreliableSocket {
+        fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+            match self.held_back.take() {
+                Some((held_msg, held_addr)) => {
+                    self.socket.send_to(msg, addr);
+                    self.socket.send_to(&held_msg, &held_addr);
+                    self.socket.send_to(&held_msg, &held_addr);
+                    self.socket.send_to(msg, addr);
+                }
+                None => self.held_back = Some((msg.clone(), *addr)),
+            }
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            // don't hold back messages forever
+            if let Some((held_msg, held_addr)) = self.held_back.take() {
+                self.socket.send_to(&held_msg, &held_addr);
+            }
+            self.socket.receive_all_messages()
+        }
+    }
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UnreliableSocket {
+        socket: UdpNonBlockingSocket::bind_to_port(7777).unwrap(),
+        held_back: None,
+    };
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UnreliableSocket {
+        socket: UdpNonBlockingSocket::bind_to_port(8888).unwrap(),
+        held_back: None,
+    };
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..100 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..100 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i * 3 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    // let the last inputs arrive, then both sessions agree on all confirmed frames
+    for _ in 0..20 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    sess1.add_local_input(0, StubInput { inp: 100 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    sess2.add_local_input(1, StubInput { inp: 300 })?;
+    stub2.handle_requests(sess2.advance_frame()?);
+    assert_eq!(stub1.gs.frame, stub2.gs.frame);
+    assert_eq!(stub1.gs.state, stub2.gs.state);
+
+    // stats are only available after a second
+    clock.advance(Duration::from_secs(1));
+    let stats = sess1.network_stats(1)?;
+    assert!(stats.duplicates_discarded > 0);
+    assert!(stats.packets_reordered > 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_rollback_beyond_max_saved_frames() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_max_saved_frames(2)?
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first session predicts four frames, but only keeps the last two gamestates
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..4 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+
+    // the prediction for the first frame turns out to be wrong
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..4 {
+        sess2.add_local_input(1, StubInput { inp: i + 1 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the rollback to frame 0 is impossible, and the session does not advance
+    let confirmed_frame = sess1.confirmed_frame();
+    for _ in 0..2 {
+        sess1.add_local_input(0, StubInput { inp: 4 })?;
+        let result = sess1.advance_frame();
+        assert_eq!(result.err(), Some(GgrsError::StateNotSaved { frame: 0 }));
+
+        // the failed call left the session untouched, so the next call fails the same way
+        assert_eq!(sess1.current_frame(), 4);
+        assert_eq!(sess1.confirmed_frame(), confirmed_frame);
+        assert_eq!(sess1.last_rollback_frames(), 0);
+        assert_eq!(sess1.latest_saved_state().map(|(frame, _)| frame), Some(3));
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_confirmed_frame_is_minimum_over_players() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let addr3 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    let mut sessions = Vec::new();
+    for (local_handle, port) in [(0, 7777), (1, 8888), (2, 9999)] {
+        let mut builder = SessionBuilder::<StubConfig>::new().with_num_players(3);
+        for (handle, addr) in [(0, addr1), (1, addr2), (2, addr3)] {
+            let player_type = if handle == local_handle {
+                PlayerType::Local
+            } else {
+                PlayerType::Remote(addr)
+            };
+            builder = builder.add_player(player_type, handle)?;
+        }
+        let socket = UdpNonBlockingSocket::bind_to_port(port).unwrap();
+        sessions.push(builder.start_p2p_session(socket)?);
+    }
+
+    for _ in 0..50 {
+        sessions
+            .iter_mut()
+            .for_each(|sess| sess.poll_remote_clients());
+    }
+
+    // the players advance a different amount of frames
+    for (handle, frames) in [(0, 6), (1, 4), (2, 2)] {
+        for i in 0..frames {
+            sessions[handle].add_local_input(handle, StubInput { inp: i })?;
+            sessions[handle].advance_frame()?;
+        }
+    }
+    for _ in 0..10 {
+        sessions
+            .iter_mut()
+            .for_each(|sess| sess.poll_remote_clients());
+    }
+
+    let sess = &sessions[0];
+    assert_eq!(sess.player_confirmed_frame(0)?, 5);
+    assert_eq!(sess.player_confirmed_frame(1)?, 3);
+    assert_eq!(sess.player_confirmed_frame(2)?, 1);
+    assert_eq!(sess.confirmed_frame(), 1);
+    assert!(sess.player_confirmed_frame(3).is_err());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_last_confirmed_input() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.last_confirmed_input(1)?.is_none());
+    assert!(sess1.last_confirmed_input(2).is_err());
+
+    // the input of the second session only shows up once it has been received
+    let mut stub2 = stubs::GameStub::new();
+    sess2.add_local_input(1, StubInput { inp: 5 })?;
+    stub2.handle_requests(sess2.advance_frame()?);
+    assert!(sess1.last_confirmed_input(1)?.is_none());
+    sess1.poll_remote_clients();
+    assert!(sess1.last_confirmed_input(1)? == Some(StubInput { inp: 5 }));
+
+    // predicting further frames does not change it
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    assert!(sess1.last_confirmed_input(0)? == Some(StubInput { inp: 2 }));
+    assert!(sess1.last_confirmed_input(1)? == Some(StubInput { inp: 5 }));
+
+    sess2.add_local_input(1, StubInput { inp: 6 })?;
+    stub2.handle_requests(sess2.advance_frame()?);
+    sess1.poll_remote_clients();
+    assert!(sess1.last_confirmed_input(1)? == Some(StubInput { inp: 6 }));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_player_input_for_frame() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_spectator_buffer_frames(16)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 100 + i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // every confirmed frame reports exactly what each player submitted for it
+    let confirmed_frame = sess1.confirmed_frame();
+    assert_eq!(confirmed_frame, 9);
+    for frame in 0..=confirmed_frame {
+        let local = sess1.player_input_for_frame(0, frame)?.unwrap();
+        let remote = sess1.player_input_for_frame(1, frame)?.unwrap();
+        assert_eq!(local.inp, frame as u32);
+        assert_eq!(remote.inp, 100 + frame as u32);
+    }
+
+    // frames that are not confirmed yet have no input to report
+    assert!(sess1
+        .player_input_for_frame(0, confirmed_frame + 1)?
+        .is_none());
+    assert!(sess1.player_input_for_frame(2, 0).is_err());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_inputs_ordered_by_handle() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let addr3 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    let mut sessions = Vec::new();
+    for (local_handle, port) in [(0, 7777), (1, 8888), (2, 9999)] {
+        let mut builder = SessionBuilder::<StubConfig>::new().with_num_players(3);
+        for (handle, addr) in [(0, addr1), (1, addr2), (2, addr3)] {
+            let player_type = if handle == local_handle {
+                PlayerType::Local
+            } else {
+                PlayerType::Remote(addr)
+            };
+            builder = builder.add_player(player_type, handle)?;
+        }
+        let socket = UdpNonBlockingSocket::bind_to_port(port).unwrap();
+        sessions.push(builder.start_p2p_session(socket)?);
+    }
+
+    for _ in 0..50 {
+        sessions
+            .iter_mut()
+            .for_each(|sess| sess.poll_remote_clients());
+    }
+
+    // the first session receives the same inputs in a different order for frames 0 and 1
+    let mut game_stubs: Vec<_> = (0..3).map(|_| stubs::GameStub::new()).collect();
+    for order in [[2, 1], [1, 2]] {
+        for handle in order {
+            sessions[handle].add_local_input(handle, StubInput { inp: handle as u32 })?;
+            game_stubs[handle].handle_requests(sessions[handle].advance_frame()?);
+            sessions[0].poll_remote_clients();
+        }
+    }
+
+    let mut all_inputs = Vec::new();
+    for _ in 0..2 {
+        sessions[0].add_local_input(0, StubInput { inp: 0 })?;
+        let requests = sessions[0].advance_frame()?;
+        if let Some(GgrsRequest::AdvanceFrame { inputs }) = requests.last() {
+            all_inputs.push(inputs.clone());
+        }
+        game_stubs[0].handle_requests(requests);
+    }
+
+    let expected: Vec<_> = (0..3)
+        .map(|handle| (StubInput { inp: handle }, InputStatus::Confirmed))
+        .collect();
+    assert_eq!(all_inputs.len(), 2);
+    assert!(all_inputs[0] == expected);
+    assert!(all_inputs[1] == expected);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_synchronize_p2p_sessions() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .start_p2p_session(socket2)?;
+
+    assert!(sess1.current_state() == SessionState::Synchronizing);
+    assert!(sess2.current_state() == SessionState::Synchronizing);
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_packet_log_records_handshake() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_packet_log(true)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+
+    let packets = sess1.drain_packet_events();
+    assert!(packets.iter().all(|packet| packet.addr == addr2));
+    assert!(packets.iter().all(|packet| packet.size > 0));
+
+    // the handshake starts with a request, which the other side answers
+    assert_eq!(packets[0].direction, PacketDirection::Sent);
+    assert_eq!(packets[0].kind, PacketKind::SyncRequest);
+    let count = |direction: PacketDirection, kind: PacketKind| {
+        packets
+            .iter()
+            .filter(|packet| packet.direction == direction && packet.kind == kind)
+            .count()
+    };
+    let requests_sent = count(PacketDirection::Sent, PacketKind::SyncRequest);
+    assert!(requests_sent >= 5);
+    assert_eq!(
+        count(PacketDirection::Received, PacketKind::SyncReply),
+        requests_sent
+    );
+    assert_eq!(
+        count(PacketDirection::Sent, PacketKind::SyncReply),
+        count(PacketDirection::Received, PacketKind::SyncRequest)
+    );
+
+    // sent packets are numbered consecutively
+    let sequences: Vec<u16> = packets
+        .iter()
+        .filter(|packet| packet.direction == PacketDirection::Sent)
+        .map(|packet| packet.sequence)
+        .collect();
+    assert!(sequences.windows(2).all(|pair| pair[1] == pair[0] + 1));
+
+    assert!(sess1.drain_packet_events().is_empty());
+    assert!(sess2.drain_packet_events().is_empty());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_mismatched_protocol_ids_do_not_synchronize() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_protocol_id(1)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_protocol_id(2)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(sess1.current_state(), SessionState::Synchronizing);
+    assert_eq!(sess2.current_state(), SessionState::Synchronizing);
+
+    // the incompatible peer is only reported once
+    let events1: Vec<_> = sess1.events().collect();
+    assert_eq!(events1.len(), 1);
+    assert!(matches!(
+        events1[0],
+        GgrsEvent::IncompatiblePeer {
+            addr,
+            reason: IncompatibilityReason::ProtocolId
+        } if addr == addr2
+    ));
+    let events2: Vec<_> = sess2.events().collect();
+    assert_eq!(events2.len(), 1);
+    assert!(matches!(
+        events2[0],
+        GgrsEvent::IncompatiblePeer {
+            addr,
+            reason: IncompatibilityReason::ProtocolId
+        } if addr == addr1
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_mismatched_input_sizes_do_not_synchronize() -> Result<(), GgrsError> {
+    // the same game, but built with a wider input type
+    struct WideConfig;
+
+    impl Config for WideConfig {
+        type Input = u64;
+        type State = u64;
+        type Address = SocketAddr;
+    }
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<WideConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(sess1.current_state(), SessionState::Synchronizing);
+    assert_eq!(sess2.current_state(), SessionState::Synchronizing);
+
+    let events1: Vec<_> = sess1.events().collect();
+    assert_eq!(events1.len(), 1);
+    assert!(matches!(
+        events1[0],
+        GgrsEvent::IncompatiblePeer {
+            addr,
+            reason: IncompatibilityReason::InputSize {
+                local: 32,
+                remote: 64
+            }
+        } if addr == addr2
+    ));
+    let events2: Vec<_> = sess2.events().collect();
+    assert_eq!(events2.len(), 1);
+    assert!(matches!(
+        events2[0],
+        GgrsEvent::IncompatiblePeer {
+            addr,
+            reason: IncompatibilityReason::InputSize {
+                local: 64,
+                remote: 32
+            }
+        } if addr == addr1
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_mismatched_player_numbers_do_not_synchronize() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let addr3 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_num_players(3)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Remote(addr3), 2)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(sess1.current_state(), SessionState::Synchronizing);
+    assert!(sess1.events().any(|event| matches!(
+        event,
+        GgrsEvent::IncompatiblePeer {
+            reason: IncompatibilityReason::NumPlayers {
+                local: 2,
+                remote: 3
+            },
+            ..
+        }
+    )));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_events_are_batched() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // all events of the synchronization are delivered together
+    let events: Vec<_> = sess1.events().collect();
+    assert!(events.len() > 1);
+    assert!(events[..events.len() - 1]
+        .iter()
+        .all(|event| matches!(event, GgrsEvent::Synchronizing { .. })));
+    assert!(matches!(
+        events.last(),
+        Some(GgrsEvent::Synchronized { .. })
+    ));
+    assert_eq!(sess1.events().len(), 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_advance_frame_p2p_sessions() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    assert!(sess1.current_state() == SessionState::Synchronizing);
+    assert!(sess2.current_state() == SessionState::Synchronizing);
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert!(sess1.current_state() == SessionState::Running);
+    assert!(sess2.current_state() == SessionState::Running);
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let reps = 10;
+    for i in 0..reps {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
+        let requests1 = sess1.advance_frame().unwrap();
+        stub1.handle_requests(requests1);
+        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+        let requests2 = sess2.advance_frame().unwrap();
+        stub2.handle_requests(requests2);
+
+        // gamestate evolves
+        assert_eq!(stub1.gs.frame, i as i32 + 1);
+        assert_eq!(stub2.gs.frame, i as i32 + 1);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_remaining_prediction_frames() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let max_prediction = sess1.max_prediction() as u32;
+    assert_eq!(sess1.remaining_prediction_frames(), max_prediction);
+
+    // only the first session advances, so all of its frames are predicted
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
+        stub1.handle_requests(sess1.advance_frame()?);
+        assert_eq!(sess1.remaining_prediction_frames(), max_prediction - i - 1);
+    }
+
+    // the second session catches up and sends its inputs
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // frames 0 to 2 are now confirmed, which frees up the prediction window again
+    sess1.add_local_input(0, StubInput { inp: 3 }).unwrap();
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(sess1.current_frame(), 4);
+    assert_eq!(sess1.remaining_prediction_frames(), max_prediction - 2);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_bit_packed_inputs() -> Result<(), GgrsError> {
+    struct PackedConfig;
+
+    impl Config for PackedConfig {
+        type Input = StubInput;
+        type State = u64;
+        type Address = SocketAddr;
+        const INPUT_BITS: Option<usize> = Some(6);
+    }
+
+    fn handle_requests(state: &mut u64, requests: Vec<GgrsRequest<PackedConfig>>) {
+        for request in requests {
+            match request {
+                GgrsRequest::SaveGameState { cell, frame } => {
+                    cell.save(frame, Some(*state), Some(*state as u128))
+                }
+                GgrsRequest::LoadGameState { cell, .. } => *state = cell.load().unwrap(),
+                GgrsRequest::AdvanceFrame { inputs } => {
+                    for (input, _) in inputs {
+                        *state = state.wrapping_mul(31).wrapping_add(input.inp as u64);
+                    }
+                }
+            }
+        }
+    }
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<PackedConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<PackedConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // all six bits of the inputs arrive
+    let (mut state1, mut state2) = (0, 0);
+    for i in 0..64 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        handle_requests(&mut state1, sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 63 - i })?;
+        handle_requests(&mut state2, sess2.advance_frame()?);
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    for _ in 0..2 {
+        for _ in 0..10 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+        sess1.add_local_input(0, StubInput { inp: 0 })?;
+        handle_requests(&mut state1, sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 0 })?;
+        handle_requests(&mut state2, sess2.advance_frame()?);
+    }
+    assert_eq!(sess1.confirmed_frame(), 64);
+    assert_eq!(state1, state2);
+
+    Ok(())
+}
+
+#[test]
+fn test_bit_packed_inputs_out_of_range() -> Result<(), GgrsError> {
+    struct PackedConfig;
+
+    impl Config for PackedConfig {
+        type Input = StubInput;
+        type State = u64;
+        type Address = SocketAddr;
+        const INPUT_BITS: Option<usize> = Some(6);
+    }
+
+    struct WideConfig;
+
+    impl Config for WideConfig {
+        type Input = [u32; 3];
+        type State = u64;
+        type Address = SocketAddr;
+        const INPUT_BITS: Option<usize> = Some(32);
+    }
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+
+    // inputs serializing into more than 8 bytes are rejected in every builder path
+    let p2p = SessionBuilder::<WideConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(SilentSocket);
+    assert!(matches!(p2p, Err(GgrsError::InvalidRequest { .. })));
+    let spectator = SessionBuilder::<WideConfig>::new().start_spectator_session(addr, SilentSocket);
+    assert!(matches!(spectator, Err(GgrsError::InvalidRequest { .. })));
+    let synctest = SessionBuilder::<WideConfig>::new().start_synctest_session();
+    assert!(matches!(synctest, Err(GgrsError::InvalidRequest { .. })));
+
+    // inputs exceeding the bit width are reported instead of panicking when sent
+    let mut sess = SessionBuilder::<PackedConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(SilentSocket)?;
+    assert!(matches!(
+        sess.add_local_input(0, StubInput { inp: 64 }),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+    sess.add_local_input(0, StubInput { inp: 63 })?;
+    sess.add_local_input(1, StubInput { inp: 0 })?;
+    sess.advance_frame()?;
+
+    let mut sess = SessionBuilder::<PackedConfig>::new().start_synctest_session()?;
+    assert!(matches!(
+        sess.add_local_input(0, StubInput { inp: 64 }),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_redundant_inputs_ignored() -> Result<(), GgrsError> {
+    // a socket that holds back messages while told to, and delivers them late when released
+    struct DelayingSocket {
+        socket: UdpNonBlockingSocket,
+        hold: Arc<AtomicBool>,
+        release: Arc<AtomicBool>,
+        held_back: Vec<(Message, SocketAddr)>,
+    }
+
+    impl NonBlockingSocket<SocketAddr> for DelayingSocket {
+        fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+            if self.hold.load(Ordering::SeqCst) {
+                self.held_back.push((msg.clone(), *addr));
+            } else {
+                self.socket.send_to(msg, addr);
+            }
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            if self.release.load(Ordering::SeqCst) {
+                for (msg, addr) in self.held_back.drain(..) {
+                    self.socket.send_to(&msg, &addr);
+                }
+            }
+            self.socket.receive_all_messages()
+        }
+    }
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+    let hold = Arc::new(AtomicBool::new(false));
+    let release = Arc::new(AtomicBool::new(false));
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_rollback_events(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = DelayingSocket {
+        socket: UdpNonBlockingSocket::bind_to_port(8888).unwrap(),
+        hold: hold.clone(),
+        release: release.clone(),
+        held_back: Vec::new(),
+    };
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the inputs never change, so there is nothing to mispredict
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..15 {
+        // hold back the packet carrying the input of frame 5, later packets retransmit that input
+        hold.store(i == 5, Ordering::SeqCst);
+        sess1.add_local_input(0, StubInput { inp: 0 })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 0 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    sess1.events().for_each(drop);
+
+    // network stats are only available after a second
+    clock.advance(Duration::from_secs(1));
+    let redundant_before = sess1.network_stats(1)?.redundant_inputs;
+    let gs_before = stub1.gs;
+
+    // the old packet arrives late
+    release.store(true, Ordering::SeqCst);
+    sess2.poll_remote_clients();
+    sess1.poll_remote_clients();
+    assert!(sess1.network_stats(1)?.redundant_inputs > redundant_before);
+
+    // the old input neither changed the gamestate nor caused a rollback
+    assert_eq!(stub1.gs.frame, gs_before.frame);
+    assert_eq!(stub1.gs.state, gs_before.state);
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(sess1.last_rollback_frames(), 0);
+    assert!(!sess1
+        .events()
+        .any(|e| matches!(e, GgrsEvent::Rollback { .. })));
+    assert_eq!(stub1.gs.frame, 16);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_rollback_budget() -> Result<(), GgrsError> {
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_rollback_budget(0)
+        .is_err());
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_rollback_budget(2)?
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first session predicts the inputs of the second one for three frames
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+
+    // the actual inputs differ from the prediction
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess2.add_local_input(1, StubInput { inp: i + 5 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // resimulating three frames exceeds the budget of two
+    assert_eq!(sess1.rollbacks_over_budget(), 0);
+    sess1.add_local_input(0, StubInput { inp: 3 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(sess1.last_rollback_frames(), 3);
+    assert_eq!(sess1.rollbacks_over_budget(), 1);
+
+    // the game keeps running and the next frame does not need a rollback
+    sess1.add_local_input(0, StubInput { inp: 4 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(sess1.rollbacks_over_budget(), 1);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_peak_rollback_depth() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.peak_rollback_depth(), 0);
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut next_input = 0;
+    // the first session predicts `depth` frames of the second one, which then sends different inputs for them
+    let mut rollback = |depth: usize| -> Result<usize, GgrsError> {
+        for _ in 0..depth {
+            sess1.add_local_input(0, StubInput { inp: 0 })?;
+            stub1.handle_requests(sess1.advance_frame()?);
+        }
+        for _ in 0..depth {
+            next_input += 1;
+            sess2.add_local_input(1, StubInput { inp: next_input })?;
+            stub2.handle_requests(sess2.advance_frame()?);
+        }
+        for _ in 0..10 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+        sess1.add_local_input(0, StubInput { inp: 0 })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        // the second session catches up with the frame the first one just simulated
+        next_input += 1;
+        sess2.add_local_input(1, StubInput { inp: next_input })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        Ok(sess1.last_rollback_frames())
+    };
+
+    assert_eq!(rollback(3)?, 3);
+    assert_eq!(rollback(1)?, 1);
+    assert_eq!(rollback(2)?, 2);
+    assert_eq!(sess1.peak_rollback_depth(), 3);
+
+    sess1.reset_peak_rollback_depth();
+    assert_eq!(sess1.peak_rollback_depth(), 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_last_rollback_frames_sparse_saving() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_sparse_saving_mode(true)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first session uses up its prediction window, then learns that the first prediction was wrong
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let window = sess1.max_prediction();
+    for i in 0..window {
+        sess1.add_local_input(0, StubInput { inp: i as u32 })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    sess2.add_local_input(1, StubInput { inp: 7 })?;
+    stub2.handle_requests(sess2.advance_frame()?);
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // it rolls back to correct the prediction, and again to save a confirmed state before it loses the last saved one
+    sess1.add_local_input(0, StubInput { inp: 0 })?;
+    let requests = sess1.advance_frame()?;
+    let count = |is_match: fn(&GgrsRequest<StubConfig>) -> bool| {
+        requests.iter().filter(|request| is_match(request)).count()
+    };
+    assert_eq!(
+        count(|request| matches!(request, GgrsRequest::LoadGameState { .. })),
+        2
+    );
+    // the prediction window is still used up, so only resimulated frames are advanced
+    let resimulated = count(|request| matches!(request, GgrsRequest::AdvanceFrame { .. }));
+    assert_eq!(resimulated, 2 * window);
+    assert_eq!(sess1.last_rollback_frames(), resimulated);
+    assert_eq!(sess1.peak_rollback_depth(), resimulated);
+    stub1.handle_requests(requests);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_rollback_event() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_rollback_events(true)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    sess1.events().for_each(drop);
+
+    // the first session predicts the inputs of the second one for three frames
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+
+    // the actual inputs differ from the prediction
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess2.add_local_input(1, StubInput { inp: i + 5 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    assert_eq!(sess1.last_rollback_frames(), 0);
+    sess1.add_local_input(0, StubInput { inp: 3 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(sess1.last_rollback_frames(), 3);
+
+    let rollbacks: Vec<_> = sess1
+        .events()
+        .filter(|event| matches!(event, GgrsEvent::Rollback { .. }))
+        .collect();
+    assert_eq!(rollbacks.len(), 1);
+    assert!(matches!(
+        rollbacks[0],
+        GgrsEvent::Rollback {
+            from_frame: 0,
+            to_frame: 3
+        }
+    ));
+
+    // the next frame does not need a rollback
+    sess1.add_local_input(0, StubInput { inp: 4 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(sess1.last_rollback_frames(), 0);
+
+    // two more rollbacks which are not polled in between are merged into one event
+    for i in 0..2 {
+        sess2.add_local_input(1, StubInput { inp: i + 20 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        for _ in 0..10 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+        sess1.add_local_input(0, StubInput { inp: i + 5 })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        assert!(sess1.last_rollback_frames() > 0);
+    }
+    let rollbacks: Vec<_> = sess1
+        .events()
+        .filter(|event| matches!(event, GgrsEvent::Rollback { .. }))
+        .collect();
+    assert_eq!(rollbacks.len(), 1);
+    assert!(matches!(
+        rollbacks[0],
+        GgrsEvent::Rollback {
+            from_frame: 3,
+            to_frame: 6
+        }
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_frame_events() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert!(sess1.drain_frame_events().is_empty());
+
+    // the first session predicts the inputs of the second one for two frames
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..2 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    assert_eq!(
+        sess1.drain_frame_events(),
+        vec![
+            FrameEvent::Predicted { frame: 0 },
+            FrameEvent::Predicted { frame: 1 }
+        ]
+    );
+
+    // the actual inputs differ from the prediction
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..2 {
+        sess2.add_local_input(1, StubInput { inp: i + 5 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    sess1.add_local_input(0, StubInput { inp: 2 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(
+        sess1.drain_frame_events(),
+        vec![
+            FrameEvent::Corrected { frame: 0 },
+            FrameEvent::Confirmed { frame: 0 },
+            FrameEvent::Confirmed { frame: 1 },
+            FrameEvent::Predicted { frame: 2 },
+        ]
+    );
+    // draining clears the events
+    assert!(sess1.drain_frame_events().is_empty());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_send_interval_frames() -> Result<(), GgrsError> {
+    assert!(SessionBuilder::<StubConfig>::new()
+        .with_send_interval_frames(0)
+        .is_err());
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    // the interval must not exceed the prediction window
+    let sess = SessionBuilder::<StubConfig>::new()
+        .with_max_prediction_window(8)
+        .with_send_interval_frames(9)?
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(UdpNonBlockingSocket::bind_to_port(7777).unwrap());
+    assert!(matches!(sess, Err(GgrsError::InvalidRequest { .. })));
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_send_interval_frames(2)?
+        .with_packet_log(true)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    // the packet log tells which of the sent packets carry inputs
+    let inputs_sent = |sess: &mut P2PSession<StubConfig>| {
+        sess.drain_packet_events()
+            .iter()
+            .filter(|packet| {
+                packet.direction == PacketDirection::Sent && packet.kind == PacketKind::Input
+            })
+            .count()
+    };
+    assert_eq!(inputs_sent(&mut sess1), 0);
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..20 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i * 2 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // every packet carries the inputs of two frames
+    assert_eq!(inputs_sent(&mut sess1), 10);
+
+    // both sessions agree on the confirmed frames
+    let confirmed = sess2.confirmed_frame();
+    assert!(confirmed >= 10);
+    assert!(sess1.checksum_at(confirmed).is_some());
+    assert_eq!(sess1.checksum_at(confirmed), sess2.checksum_at(confirmed));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_frame_zero_without_remote_input() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // without any remote input, frame 0 is advanced with the default input as prediction
+    let mut stub1 = stubs::GameStub::new();
+    sess1.add_local_input(0, StubInput { inp: 1 })?;
+    let requests = sess1.advance_frame()?;
+    assert!(matches!(
+        requests[0],
+        GgrsRequest::SaveGameState { frame: 0, .. }
+    ));
+    match &requests[requests.len() - 1] {
+        GgrsRequest::AdvanceFrame { inputs } => {
+            assert!(inputs[1] == (StubInput::default(), InputStatus::Predicted));
+        }
+        _ => panic!("expected the frame to advance"),
+    }
+    stub1.handle_requests(requests);
+
+    // the actual input differs, so the session rolls back to frame 0
+    let mut stub2 = stubs::GameStub::new();
+    sess2.add_local_input(1, StubInput { inp: 5 })?;
+    stub2.handle_requests(sess2.advance_frame()?);
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    sess1.add_local_input(0, StubInput { inp: 1 })?;
+    let requests = sess1.advance_frame()?;
+    assert!(matches!(
+        requests[0],
+        GgrsRequest::LoadGameState { frame: 0, .. }
+    ));
+    match &requests[1] {
+        GgrsRequest::AdvanceFrame { inputs } => {
+            assert!(inputs[1] == (StubInput { inp: 5 }, InputStatus::Confirmed));
+        }
+        _ => panic!("expected frame 0 to be resimulated"),
+    }
+    stub1.handle_requests(requests);
+    assert_eq!(stub1.gs.frame, 2);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_input_queue_overflow() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let input_delay = 100;
+
+    // the first session keeps confirmed inputs around, so the inputs of the second session far in the future do not fit
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_spectator_buffer_frames(40)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_input_delay(input_delay)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    sess1.events().for_each(drop);
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut confirmed_inputs = HashMap::new();
+    let mut overflows = 0;
+    for i in 0..150 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        let requests = sess1.advance_frame()?;
+        // remember the confirmed inputs of the second player
+        let mut frame = sess1.current_frame();
+        for request in requests.iter().rev() {
+            match request {
+                GgrsRequest::AdvanceFrame { inputs } => {
+                    frame -= 1;
+                    if inputs[1].1 == InputStatus::Confirmed {
+                        confirmed_inputs.insert(frame, inputs[1].0.inp);
+                    }
+                }
+                GgrsRequest::LoadGameState { .. } => break,
+                GgrsRequest::SaveGameState { .. } => (),
+            }
+        }
+        stub1.handle_requests(requests);
+        overflows += sess1
+            .events()
+            .filter(|event| matches!(event, GgrsEvent::InputQueueOverflow { player: 1, .. }))
+            .count();
+
+        sess2.add_local_input(1, StubInput { inp: i + 1000 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+
+    // the overflow has been reported, but no confirmed input got lost
+    assert_eq!(overflows, 1);
+    assert!(sess1.current_frame() > input_delay as i32);
+    for frame in input_delay as i32..sess1.current_frame() {
+        let expected = (frame - input_delay as i32) as u32 + 1000;
+        assert_eq!(confirmed_inputs.get(&frame), Some(&expected));
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_sparse_saving_matches_dense_saving() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let mut checksums = Vec::new();
+    for sparse_saving in [false, true] {
+        let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+        let mut sess1 = SessionBuilder::<StubConfig>::new()
+            .with_sparse_saving_mode(sparse_saving)
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(socket1)?;
+
+        let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+        let mut sess2 = SessionBuilder::<StubConfig>::new()
+            .with_sparse_saving_mode(sparse_saving)
+            .add_player(PlayerType::Remote(addr1), 0)?
+            .add_player(PlayerType::Local, 1)?
+            .start_p2p_session(socket2)?;
+
+        for _ in 0..50 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+
+        // the first session is always ahead and mispredicts the changing inputs of the second session
+        let mut stub1 = stubs::GameStub::new();
+        let mut stub2 = stubs::GameStub::new();
+        for i in 0..40 {
+            sess1.add_local_input(0, StubInput { inp: i })?;
+            stub1.handle_requests(sess1.advance_frame()?);
+            sess2.add_local_input(1, StubInput { inp: i * 7 % 5 })?;
+            stub2.handle_requests(sess2.advance_frame()?);
+        }
+
+        // with repeated inputs, the final predictions are correct and every mispredicted frame has been corrected
+        for _ in 0..3 {
+            for _ in 0..10 {
+                sess1.poll_remote_clients();
+                sess2.poll_remote_clients();
+            }
+            sess1.add_local_input(0, StubInput { inp: 0 })?;
+            stub1.handle_requests(sess1.advance_frame()?);
+            sess2.add_local_input(1, StubInput { inp: 0 })?;
+            stub2.handle_requests(sess2.advance_frame()?);
+        }
+
+        assert_eq!(stub1.gs.frame, 43);
+        assert_eq!(stub2.gs.frame, 43);
+        let mut hasher = DefaultHasher::new();
+        stub1.gs.hash(&mut hasher);
+        checksums.push(hasher.finish());
+    }
+
+    assert_eq!(checksums[0], checksums[1]);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_state_cost_hints() -> Result<(), GgrsError> {
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let sparse_saving = |builder: SessionBuilder<StubConfig>| -> Result<bool, GgrsError> {
+        let socket = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+        let sess = builder
+            .add_player(PlayerType::Local, 0)?
+            .add_player(PlayerType::Remote(addr2), 1)?
+            .start_p2p_session(socket)?;
+        Ok(sess.in_sparse_saving_mode())
+    };
+
+    // expensive saves are avoided with sparse saving, otherwise every frame is saved
+    assert!(sparse_saving(
+        SessionBuilder::new().with_state_cost_hints(10, 1)
+    )?);
+    assert!(!sparse_saving(
+        SessionBuilder::new().with_state_cost_hints(1, 10)
+    )?);
+    assert!(!sparse_saving(
+        SessionBuilder::new().with_state_cost_hints(5, 5)
+    )?);
+
+    // whichever of the hints and the explicit mode is set last wins
+    assert!(!sparse_saving(
+        SessionBuilder::new()
+            .with_sparse_saving_mode(true)
+            .with_state_cost_hints(1, 10)
+    )?);
+    assert!(!sparse_saving(
+        SessionBuilder::new()
+            .with_state_cost_hints(10, 1)
+            .with_sparse_saving_mode(false)
+    )?);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_total_confirmed_frames() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.total_confirmed_frames(), 0);
+
+    // the first session predicts the inputs of the second one for four frames
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..4 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    assert_eq!(sess1.current_frame(), 4);
+    assert_eq!(sess1.total_confirmed_frames(), 0);
+
+    // the second session catches up, confirming the predicted frames
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..4 {
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    sess1.add_local_input(0, StubInput { inp: 4 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(sess1.current_frame(), 5);
+    assert_eq!(sess1.total_confirmed_frames(), 4);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_confirmation_info() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the input of the second session is delayed by three frames
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        clock.advance(Duration::from_millis(16));
+    }
+    assert!(sess1.confirmation_info(0).is_none());
+
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let confirmed_at = clock.now();
+    sess1.add_local_input(0, StubInput { inp: 3 })?;
+    stub1.handle_requests(sess1.advance_frame()?);
+
+    for frame in 0..3 {
+        let info = sess1
+            .confirmation_info(frame)
+            .expect("frame should be confirmed");
+        assert_eq!(info.confirmed_at, confirmed_at);
+        assert_eq!(info.confirmed_in_frame, 3);
+        assert_eq!(info.prediction_frames, (3 - frame) as usize);
+    }
+    assert!(sess1.confirmation_info(3).is_none());
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_frame_advantage() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.local_frame_advantage(), 0);
+    assert_eq!(sess1.remote_frame_advantage(1)?, 0);
+    assert!(sess1.remote_frame_advantage(0).is_err());
+
+    // both sessions run in lockstep for a while, then the first session runs ahead
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..10 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for i in 10..15 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+
+    // exchange inputs and quality reports
+    for _ in 0..3 {
+        clock.advance(Duration::from_millis(250));
+        for _ in 0..10 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+    }
+
+    assert!(sess1.local_frame_advantage() > 0);
+    assert!(sess2.local_frame_advantage() < 0);
+    assert!(sess1.remote_frame_advantage(1)? < 0);
+    assert!(sess2.remote_frame_advantage(0)? > 0);
+    // both sides agree on the gap
+    assert_eq!(
+        sess1.local_frame_advantage(),
+        sess2.remote_frame_advantage(0)?
+    );
+    assert_eq!(
+        sess2.local_frame_advantage(),
+        sess1.remote_frame_advantage(1)?
+    );
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_misprediction_stall() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .with_misprediction_stall_threshold(0.5)?
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first session is always one step ahead and has to predict, but the input of the second session changes every frame
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut predicted_frames = 0;
+    let mut stalled_frames = 0;
+    for i in 0..20 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        let requests = sess1.advance_frame()?;
+        match requests.last() {
+            Some(GgrsRequest::AdvanceFrame { inputs }) => {
+                if inputs[1].1 == InputStatus::Predicted {
+                    // once mispredictions pile up, the session stops predicting
+                    assert_eq!(stalled_frames, 0);
+                    predicted_frames += 1;
+                }
+            }
+            _ => stalled_frames += 1,
+        }
+        stub1.handle_requests(requests);
+
+        sess2.add_local_input(1, StubInput { inp: i * 7 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    assert!(predicted_frames > 0);
+    assert!(stalled_frames > 0);
+
+    // the misprediction rate is reported with the network stats
+    clock.advance(Duration::from_secs(1));
+    sess1.poll_remote_clients();
+    assert!(sess1.network_stats(1)?.misprediction_rate > 0.5);
+
+    // the input of the second session becomes predictable again, while the first session tries to get ahead of it
+    let mut resumed = false;
+    for i in 20..100 {
+        sess2.add_local_input(1, StubInput { inp: 0 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        for _ in 0..2 {
+            sess1.add_local_input(0, StubInput { inp: i })?;
+            let requests = sess1.advance_frame()?;
+            if let Some(GgrsRequest::AdvanceFrame { inputs }) = requests.last() {
+                resumed |= inputs[1].1 == InputStatus::Predicted;
+            }
+            stub1.handle_requests(requests);
+        }
+        if resumed {
+            break;
+        }
+    }
+
+    // the stalling session has noticed and predicts again
+    assert!(resumed);
+    assert!(sess1.network_stats(1)?.misprediction_rate <= 0.5);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_desyncs_detected() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let desync_mode = DesyncDetection::On { interval: 100 };
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket2)?;
+
+    while sess1.current_state() != SessionState::Running
+        && sess2.current_state() != SessionState::Running
+    {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // drain events
+    assert!(sess1.events().chain(sess2.events()).all(|e| matches!(
+        e,
+        GgrsEvent::Synchronizing { .. } | GgrsEvent::Synchronized { .. }
+    )));
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+
+    // run normally for some frames (past first desync interval)
+    for i in 0..110 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
+        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+
+        let requests1 = sess1.advance_frame().unwrap();
+        let requests2 = sess2.advance_frame().unwrap();
+
+        stub1.handle_requests(requests1);
+        stub2.handle_requests(requests2);
+    }
+
+    // check that there are no unexpected events yet
+    assert_eq!(sess1.events().len(), 0);
+    assert_eq!(sess2.events().len(), 0);
+
+    // run for some more frames
+    for _ in 0..100 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        // mess up state for peer 1
+        stub1.gs.state = 1234;
+
+        // keep input steady (to avoid loads, which would restore valid state)
+        sess1.add_local_input(0, StubInput { inp: 0 }).unwrap();
+        sess2.add_local_input(1, StubInput { inp: 1 }).unwrap();
+
+        let requests1 = sess1.advance_frame().unwrap();
+        let requests2 = sess2.advance_frame().unwrap();
+
+        stub1.handle_requests(requests1);
+        stub2.handle_requests(requests2);
+    }
+
+    // check that we got desync events
+    let sess1_events: Vec<_> = sess1.events().collect();
+    let sess2_events: Vec<_> = sess2.events().collect();
+    assert_eq!(sess1_events.len(), 1);
+    assert_eq!(sess2_events.len(), 1);
+
+    let GgrsEvent::DesyncDetected {
+        frame: desync_frame1,
+        local_checksum: desync_local_checksum1,
+        remote_checksum: desync_remote_checksum1,
+        local_input_checksum: local_input_checksum1,
+        remote_input_checksum: remote_input_checksum1,
+        addr: desync_addr1,
+    } = sess1_events[0]
+    else {
+        panic!("no desync for peer 1");
+    };
+    assert_eq!(desync_frame1, 200);
+    assert_eq!(desync_addr1, addr2);
+    assert_ne!(desync_local_checksum1, desync_remote_checksum1);
+    // the inputs match, so the simulation diverged
+    assert_eq!(local_input_checksum1, remote_input_checksum1);
+
+    let GgrsEvent::DesyncDetected {
+        frame: desync_frame2,
+        local_checksum: desync_local_checksum2,
+        remote_checksum: desync_remote_checksum2,
+        local_input_checksum: local_input_checksum2,
+        remote_input_checksum: remote_input_checksum2,
+        addr: desync_addr2,
+    } = sess2_events[0]
+    else {
+        panic!("no desync for peer 2");
+    };
+    assert_eq!(desync_frame2, 200);
+    assert_eq!(desync_addr2, addr1);
+    assert_ne!(desync_local_checksum2, desync_remote_checksum2);
+    // the inputs match, so the simulation diverged
+    assert_eq!(local_input_checksum2, remote_input_checksum2);
+
+    // check that checksums match
+    assert_eq!(desync_remote_checksum1, desync_local_checksum2);
+    assert_eq!(desync_remote_checksum2, desync_local_checksum1);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_input_desyncs_detected() -> Result<(), GgrsError> {
+    #[derive(Copy, Clone, PartialEq, Default, Serialize)]
+    struct ClampedInput {
+        inp: u8,
+    }
+
+    // a buggy deserialization, so remote peers see different inputs than the local peer
+    impl<'de> Deserialize<'de> for ClampedInput {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let inp = u8::deserialize(deserializer)?;
+            Ok(ClampedInput { inp: inp.min(100) })
+        }
+    }
+
+    struct ClampedConfig;
+
+    impl Config for ClampedConfig {
+        type Input = ClampedInput;
+        type State = u64;
+        type Address = SocketAddr;
+    }
+
+    fn handle_requests(state: &mut u64, requests: Vec<GgrsRequest<ClampedConfig>>) {
+        for request in requests {
+            match request {
+                GgrsRequest::SaveGameState { cell, frame } => {
+                    cell.save(frame, Some(*state), Some(*state as u128))
+                }
+                GgrsRequest::LoadGameState { cell, .. } => *state = cell.load().unwrap(),
+                GgrsRequest::AdvanceFrame { inputs } => {
+                    for (input, _) in inputs {
+                        *state = state.wrapping_mul(31).wrapping_add(input.inp as u64);
+                    }
+                }
+            }
+        }
+    }
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let desync_mode = DesyncDetection::On { interval: 100 };
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<ClampedConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<ClampedConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket2)?;
+
+    while sess1.current_state() != SessionState::Running
+        || sess2.current_state() != SessionState::Running
+    {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    let mut state1 = 0;
+    let mut state2 = 0;
+    for _ in 0..210 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        // peer 2 receives 100 instead of 150 for player 0
+        sess1.add_local_input(0, ClampedInput { inp: 150 })?;
+        sess2.add_local_input(1, ClampedInput { inp: 1 })?;
+
+        handle_requests(&mut state1, sess1.advance_frame()?);
+        handle_requests(&mut state2, sess2.advance_frame()?);
+    }
+
+    for sess in [&mut sess1, &mut sess2] {
+        let desyncs: Vec<_> = sess
+            .events()
+            .filter_map(|event| match event {
+                GgrsEvent::DesyncDetected {
+                    frame,
+                    local_input_checksum,
+                    remote_input_checksum,
+                    ..
+                } => Some((frame, local_input_checksum, remote_input_checksum)),
+                _ => None,
+            })
+            .collect();
+        assert!(!desyncs.is_empty());
+        // the inputs differ, so the desync is not caused by the simulation
+        for (frame, local_input_checksum, remote_input_checksum) in desyncs {
+            assert_eq!(frame % 100, 0);
+            assert_ne!(local_input_checksum, remote_input_checksum);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_desyncs_and_input_delay_no_panic() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let desync_mode = DesyncDetection::On { interval: 100 };
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_input_delay(5)
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_input_delay(5)
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket2)?;
+
+    while sess1.current_state() != SessionState::Running
+        && sess2.current_state() != SessionState::Running
+    {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // drain events
+    assert!(sess1.events().chain(sess2.events()).all(|e| matches!(
+        e,
+        GgrsEvent::Synchronizing { .. } | GgrsEvent::Synchronized { .. }
+    )));
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+
+    // run normally for some frames (past first desync interval)
+    for i in 0..150 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+
+        sess1.add_local_input(0, StubInput { inp: i }).unwrap();
+        sess2.add_local_input(1, StubInput { inp: i }).unwrap();
+
+        let requests1 = sess1.advance_frame().unwrap();
+        let requests2 = sess2.advance_frame().unwrap();
+
+        stub1.handle_requests(requests1);
+        stub2.handle_requests(requests2);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_rollback_into_input_delay_transition() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let input_delay = 2;
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_input_delay(input_delay)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_input_delay(input_delay)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the inputs each player submits on a given frame, which take effect `input_delay` frames later
+    let input0 = |i: u32| StubInput { inp: i };
+    let input1 = |i: u32| StubInput { inp: 2 * i + 1 };
+
+    // the first session runs ahead, predicting blank inputs for the second one
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..5 {
+        sess1.add_local_input(0, input0(i))?;
+        stub1.handle_requests(sess1.advance_frame()?);
+    }
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..5 {
+        sess2.add_local_input(1, input1(i))?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the first real input of the second player arrives for the frame right after the delayed null frames
+    sess1.add_local_input(0, input0(5))?;
+    stub1.handle_requests(sess1.advance_frame()?);
+    assert_eq!(sess1.last_rollback_frames(), 5 - input_delay);
+
+    let mut compared_frames = 0;
+    for i in 5..20 {
+        if i > 5 {
+            sess1.add_local_input(0, input0(i))?;
+            stub1.handle_requests(sess1.advance_frame()?);
+        }
+        sess2.add_local_input(1, input1(i))?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        for _ in 0..2 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+
+        // resimulated frames match the frames of the other session
+        // with input delay, inputs can be confirmed for frames that have not been simulated yet
+        let frame = [
+            sess1.confirmed_frame(),
+            sess2.confirmed_frame(),
+            sess1.current_frame() - 1,
+            sess2.current_frame() - 1,
+        ]
+        .into_iter()
+        .min()
+        .unwrap();
+        if let (Some(checksum1), Some(checksum2)) =
+            (sess1.checksum_at(frame), sess2.checksum_at(frame))
+        {
+            assert_eq!(checksum1, checksum2);
+            compared_frames += 1;
+        }
+    }
+    assert!(compared_frames > 0);
+
+    // with the input delay covering the round trip, the last frames needed no predictions, so both sessions end up with the
+    // state of a run that had all inputs available right away
+    let mut expected = stubs::GameStub::new();
+    for frame in 0..20 {
+        let (inp0, inp1) = if frame < input_delay as u32 {
+            (StubInput::default(), StubInput::default())
+        } else {
+            let i = frame - input_delay as u32;
+            (input0(i), input1(i))
+        };
+        expected.handle_requests(vec![GgrsRequest::AdvanceFrame {
+            inputs: vec![
+                (inp0, InputStatus::Confirmed),
+                (inp1, InputStatus::Confirmed),
+            ],
+        }]);
+    }
+    assert_eq!(stub1.gs.frame, 20);
+    assert_eq!(stub2.gs.frame, 20);
+    assert_eq!(stub1.gs.state, expected.gs.state);
+    assert_eq!(stub2.gs.state, expected.gs.state);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_set_input_delay_while_running() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let desync_mode = DesyncDetection::On { interval: 1 };
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .with_input_delay(2)
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .with_spectator_buffer_frames(64)
+        .with_input_delay(2)
+        .with_desync_detection_mode(desync_mode)
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // the delay must fit into the input queue together with the prediction window
+    assert!(sess1.set_input_delay(120).is_err());
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..60 {
+        match i {
+            10 => sess1.set_input_delay(5)?,
+            20 => sess2.set_input_delay(4)?,
+            30 => sess1.set_input_delay(1)?,
+            40 => sess2.set_input_delay(0)?,
+            _ => (),
+        }
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 2 * i + 1 })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        for _ in 0..2 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+    }
+    assert_eq!(stub1.gs.frame, 60);
+    assert_eq!(stub2.gs.frame, 60);
+
+    // both sessions simulated the transitions the same way
+    assert!(sess1.confirmed_frame() >= 50);
+    assert!(sess2.confirmed_frame() >= 50);
+    assert!(!sess1
+        .events()
+        .chain(sess2.events())
+        .any(|event| matches!(event, GgrsEvent::DesyncDetected { .. })));
+    let frame = sess1.confirmed_frame().min(sess2.confirmed_frame());
+    assert_eq!(sess1.checksum_at(frame), sess2.checksum_at(frame));
+    assert!(sess1.checksum_at(frame).is_some());
+
+    // the increased delay repeated the last input before the change, the decreased delay dropped inputs
+    assert_eq!(sess2.player_input_for_frame(0, 12)?.unwrap().inp, 9);
+    assert_eq!(sess2.player_input_for_frame(0, 15)?.unwrap().inp, 10);
+    assert_eq!(sess2.player_input_for_frame(0, 34)?.unwrap().inp, 29);
+    assert_eq!(sess2.player_input_for_frame(0, 35)?.unwrap().inp, 34);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_rollback_across_disconnect_frame() -> Result<(), GgrsError> {
+    // a socket that stops sending to a given address once told to
+    struct PartitionedSocket {
+        inner: UdpNonBlockingSocket,
+        blocked_addr: SocketAddr,
+        blocked: Arc<AtomicBool>,
+    }
+
+    impl NonBlockingSocket<SocketAddr> for PartitionedSocket {
+        fn send_to(&mut self, msg: &Message, addr: &SocketAddr) {
+            if *addr == self.blocked_addr && self.blocked.load(Ordering::SeqCst) {
+                return;
+            }
+            self.inner.send_to(msg, addr);
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(SocketAddr, Message)> {
+            self.inner.receive_all_messages()
+        }
+    }
+
+    // handles the requests and remembers the inputs each frame has last been simulated with
+    fn handle_requests(
+        stub: &mut stubs::GameStub,
+        simulated_inputs: &mut HashMap<i32, Vec<(u32, InputStatus)>>,
+        requests: Vec<GgrsRequest<StubConfig>>,
+    ) {
+        for request in requests {
+            if let GgrsRequest::AdvanceFrame { inputs } = &request {
+                let inputs = inputs.iter().map(|(input, status)| (input.inp, *status));
+                simulated_inputs.insert(stub.gs.frame, inputs.collect());
+            }
+            stub.handle_requests(vec![request]);
+        }
+    }
+
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let addr3 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+    let partitioned = Arc::new(AtomicBool::new(false));
+
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_num_players(3)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .add_player(PlayerType::Remote(addr3), 2)?
+        .start_p2p_session(UdpNonBlockingSocket::bind_to_port(7777).unwrap())?;
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_num_players(3)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Remote(addr3), 2)?
+        .start_p2p_session(UdpNonBlockingSocket::bind_to_port(8888).unwrap())?;
+    let socket3 = PartitionedSocket {
+        inner: UdpNonBlockingSocket::bind_to_port(9999).unwrap(),
+        blocked_addr: addr2,
+        blocked: partitioned.clone(),
+    };
+    let mut sess3 = SessionBuilder::<StubConfig>::new()
+        .with_num_players(3)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .add_player(PlayerType::Local, 2)?
+        .start_p2p_session(socket3)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        sess3.poll_remote_clients();
+    }
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    let mut stub3 = stubs::GameStub::new();
+    let mut inputs1 = HashMap::new();
+    let mut inputs2 = HashMap::new();
+    let mut inputs3 = HashMap::new();
+    for i in 0..5 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        handle_requests(&mut stub1, &mut inputs1, sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 10 + i })?;
+        handle_requests(&mut stub2, &mut inputs2, sess2.advance_frame()?);
+        sess3.add_local_input(2, StubInput { inp: 20 + i })?;
+        handle_requests(&mut stub3, &mut inputs3, sess3.advance_frame()?);
+        for _ in 0..2 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+            sess3.poll_remote_clients();
+        }
+    }
+
+    // the third player's last inputs only reach the first session before it drops out
+    partitioned.store(true, Ordering::SeqCst);
+    for i in 5..7 {
+        sess3.add_local_input(2, StubInput { inp: 20 + i })?;
+        handle_requests(&mut stub3, &mut inputs3, sess3.advance_frame()?);
+    }
+    sess1.poll_remote_clients();
+    assert_eq!(sess1.player_confirmed_frame(2)?, 6);
+    assert_eq!(sess2.player_confirmed_frame(2)?, 4);
+
+    // the second session disconnects the third player after the last input it received, which the first session adopts
+    sess2.disconnect_player(2)?;
+    for i in 5..15 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        handle_requests(&mut stub1, &mut inputs1, sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: 10 + i })?;
+        handle_requests(&mut stub2, &mut inputs2, sess2.advance_frame()?);
+        for _ in 0..2 {
+            sess1.poll_remote_clients();
+            sess2.poll_remote_clients();
+        }
+    }
+    assert_eq!(sess1.player_confirmed_frame(2)?, 4);
+
+    // both sessions resimulated the frames after the disconnect with the same neutral input
+    for frame in 0..12 {
+        assert_eq!(inputs1[&frame], inputs2[&frame], "frame {frame}");
+    }
+    for frame in 5..12 {
+        assert_eq!(inputs1[&frame][2], (0, InputStatus::Disconnected));
+    }
+    assert_eq!(sess1.checksum_at(11), sess2.checksum_at(11));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_network_simulation_with_packet_loss() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        // a session that finished synchronizing disconnects the other one if it hears nothing for the default 2 seconds,
+        // and with half the packets lost, the sync requests of the other one, retried at up to 1.6 second intervals,
+        // can miss that window
+        .with_disconnect_timeout(Duration::from_secs(10))
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        // same as above
+        .with_disconnect_timeout(Duration::from_secs(10))
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    let sim = |seed| NetworkSim {
+        latency_ms: 20,
+        jitter_ms: 10,
+        loss_pct: 50,
+        seed,
+    };
+    sess1.set_network_simulation(sim(1))?;
+    sess2.set_network_simulation(sim(2))?;
+    assert!(matches!(
+        sess1.set_network_simulation(NetworkSim {
+            loss_pct: 101,
+            ..Default::default()
+        }),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    // the handshake is retried until enough sync packets get through
+    for _ in 0..5000 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        clock.advance(Duration::from_millis(20));
+        if sess1.current_state() == SessionState::Running
+            && sess2.current_state() == SessionState::Running
+        {
+            break;
+        }
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    // lost inputs are resent until acknowledged, so both sessions keep confirming frames
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..1000 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        if sess1.add_local_input(0, StubInput { inp: i }).is_ok() {
+            if let Ok(requests) = sess1.advance_frame() {
+                stub1.handle_requests(requests);
+            }
+        }
+        if sess2.add_local_input(1, StubInput { inp: i }).is_ok() {
+            if let Ok(requests) = sess2.advance_frame() {
+                stub2.handle_requests(requests);
+            }
+        }
+        clock.advance(Duration::from_millis(16));
+        if sess1.confirmed_frame() >= 100 && sess2.confirmed_frame() >= 100 {
+            break;
+        }
+    }
+    assert!(sess1.confirmed_frame() >= 100);
+    assert!(sess2.confirmed_frame() >= 100);
+    assert!(sess1
+        .events()
+        .chain(sess2.events())
+        .all(|event| !matches!(event, GgrsEvent::Disconnected { .. })));
+
+    // network stats need at least a second to pass
+    clock.advance(Duration::from_secs(1));
+    assert!(sess1.network_stats(1)?.simulated_packets_dropped > 0);
+    assert!(sess2.network_stats(0)?.simulated_packets_dropped > 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_local_addr_of_ephemeral_port() -> Result<(), GgrsError> {
+    let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let socket = UdpNonBlockingSocket::bind_to_port(0).unwrap();
+    let sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(remote_addr), 1)?
+        .start_p2p_session(socket)?;
+
+    let local_addr = sess.local_addr().unwrap();
+    assert_ne!(local_addr.port(), 0);
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_input_lag_frames() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .with_input_delay(2)
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .with_input_delay(2)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    // every packet is held back for 50ms and sent with the first poll after that, 4 frames of 16ms later
+    let sim = NetworkSim {
+        latency_ms: 50,
+        ..Default::default()
+    };
+    sess1.set_network_simulation(sim)?;
+    sess2.set_network_simulation(sim)?;
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..60 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+        clock.advance(Duration::from_millis(16));
+    }
+
+    // the input travels for 4 frames and its acknowledgement another 4 frames, independent of the input delay. The loopback
+    // socket may deliver a packet only with a later poll, so allow for a frame of slack in each direction.
+    clock.advance(Duration::from_secs(1));
+    for lag in [
+        sess1.network_stats(1)?.input_lag_frames,
+        sess2.network_stats(0)?.input_lag_frames,
+    ] {
+        assert!((8..=10).contains(&lag), "input lag of {lag} frames");
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_far_future_inputs_are_rejected() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+    let clock = MockClock::new();
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    // the inputs of the second session are far ahead of the input queue of the first session
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .with_clock(clock.clone())
+        .with_input_delay(300)
+        .with_input_queue_length(0, 1024)
+        .with_input_queue_length(1, 1024)
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.current_state(), SessionState::Running);
+    assert_eq!(sess2.current_state(), SessionState::Running);
+
+    let mut stub1 = stubs::GameStub::new();
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..100 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        if let Ok(requests) = sess1.advance_frame() {
+            stub1.handle_requests(requests);
+        }
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        if let Ok(requests) = sess2.advance_frame() {
+            stub2.handle_requests(requests);
+        }
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+
+    // none of the inputs has been accepted, so nothing is held back and the first session waits at its prediction window
+    assert_eq!(sess1.current_frame(), sess1.max_prediction() as i32);
+    assert_eq!(sess1.confirmed_frame(), -1);
+    let events: Vec<_> = sess1.events().collect();
+    assert!(!events
+        .iter()
+        .any(|event| matches!(event, GgrsEvent::InputQueueOverflow { .. })));
+    let suspicious: Vec<_> = events
+        .iter()
+        .filter(|event| matches!(event, GgrsEvent::SuspiciousPeer { .. }))
+        .collect();
+    assert_eq!(suspicious.len(), 1);
+    assert!(matches!(
+        suspicious[0],
+        GgrsEvent::SuspiciousPeer { addr, rejected_inputs } if *addr == addr2 && *rejected_inputs > 0
+    ));
+
+    clock.advance(Duration::from_secs(1));
+    assert!(sess1.network_stats(1)?.inputs_rejected > 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_local_input_order_does_not_matter() -> Result<(), GgrsError> {
+    fn advance(
+        sess: &mut P2PSession<StubConfig>,
+        inputs: &[(usize, u32)],
+    ) -> Result<Vec<u32>, GgrsError> {
+        for &(handle, inp) in inputs {
+            sess.add_local_input(handle, StubInput { inp })?;
+        }
+        let mut advanced = Vec::new();
+        for request in sess.advance_frame()? {
+            if let GgrsRequest::AdvanceFrame { inputs } = request {
+                advanced = inputs.iter().map(|(input, _)| input.inp).collect();
+            }
+        }
+        Ok(advanced)
+    }
+
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(SilentSocket)?;
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(SilentSocket)?;
+
+    for i in 0..20 {
+        let in_order = advance(&mut sess1, &[(0, i), (1, i * 3)])?;
+        let reversed = advance(&mut sess2, &[(1, i * 3), (0, i)])?;
+        assert_eq!(in_order, vec![i, i * 3]);
+        assert_eq!(in_order, reversed);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_render_frame() -> Result<(), GgrsError> {
+    let addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut sess1 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Remote(addr2), 1)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut sess2 = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Remote(addr1), 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket2)?;
+
+    for _ in 0..50 {
+        sess1.poll_remote_clients();
+        sess2.poll_remote_clients();
+    }
+    assert_eq!(sess1.render_frame(), 0);
+    assert_eq!(sess1.simulation_frame(), 0);
+
+    // without remote inputs, every frame is predicted and the render frame stays behind
+    let mut stub1 = stubs::GameStub::new();
+    for i in 0..5 {
+        sess1.add_local_input(0, StubInput { inp: i })?;
+        stub1.handle_requests(sess1.advance_frame()?);
+        assert_eq!(sess1.simulation_frame(), i as i32 + 1);
+        assert_eq!(sess1.render_frame(), 0);
+    }
+
+    // the remote catches up for three frames, so only the last two frames are predicted
+    let mut stub2 = stubs::GameStub::new();
+    for i in 0..3 {
+        sess2.add_local_input(1, StubInput { inp: i })?;
+        stub2.handle_requests(sess2.advance_frame()?);
+    }
+    for _ in 0..10 {
+        sess2.poll_remote_clients();
+        sess1.poll_remote_clients();
     }
+    assert_eq!(sess1.confirmed_frame(), 2);
+    assert_eq!(sess1.render_frame(), 3);
+    assert_eq!(sess1.simulation_frame() - sess1.render_frame(), 2);
 
     Ok(())
 }
@@ -1,19 +1,46 @@
 mod stubs;
 
-use ggrs::{GgrsError, PlayerType, SessionBuilder, SessionState, UdpNonBlockingSocket};
+use ggrs::{
+    Capabilities, DisconnectReason, GgrsError, GgrsEvent, PlayerType, SessionBuilder, SessionState,
+    UdpNonBlockingSocket,
+};
 use serial_test::serial;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use stubs::StubConfig;
+use stubs::{StubConfig, StubInput};
 
 #[test]
 #[serial]
 fn test_start_session() {
     let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
     let socket = UdpNonBlockingSocket::bind_to_port(9999).unwrap();
-    let spec_sess = SessionBuilder::<StubConfig>::new().start_spectator_session(host_addr, socket);
+    let spec_sess = SessionBuilder::<StubConfig>::new()
+        .start_spectator_session(host_addr, socket)
+        .unwrap();
     assert!(spec_sess.current_state() == SessionState::Synchronizing);
 }
 
+#[test]
+#[serial]
+fn test_capabilities() {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let socket = UdpNonBlockingSocket::bind_to_port(9999).unwrap();
+    let spec_sess = SessionBuilder::<StubConfig>::new()
+        .start_spectator_session(host_addr, socket)
+        .unwrap();
+    let capabilities = spec_sess.capabilities();
+
+    assert!(capabilities.contains(Capabilities::NETWORK_STATS | Capabilities::SUSPEND_TIMEOUTS));
+    assert!(!capabilities.contains(Capabilities::LOCAL_INPUT));
+    assert!(!capabilities.contains(Capabilities::DISCONNECT_PLAYER));
+    assert!(!capabilities.contains(Capabilities::INPUT_DELAY));
+    assert_eq!(
+        capabilities,
+        Capabilities::empty()
+            .union(Capabilities::NETWORK_STATS)
+            .union(Capabilities::SUSPEND_TIMEOUTS)
+    );
+}
+
 #[test]
 #[serial]
 fn test_synchronize_with_host() -> Result<(), GgrsError> {
@@ -28,8 +55,9 @@ fn test_synchronize_with_host() -> Result<(), GgrsError> {
         .start_p2p_session(socket1)?;
 
     let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
-    let mut spec_sess =
-        SessionBuilder::<StubConfig>::new().start_spectator_session(host_addr, socket2);
+    let mut spec_sess = SessionBuilder::<StubConfig>::new()
+        .with_num_players(1)
+        .start_spectator_session(host_addr, socket2)?;
 
     assert_eq!(spec_sess.current_state(), SessionState::Synchronizing);
     assert_eq!(host_sess.current_state(), SessionState::Synchronizing);
@@ -44,3 +72,118 @@ fn test_synchronize_with_host() -> Result<(), GgrsError> {
 
     Ok(())
 }
+
+#[test]
+#[serial]
+fn test_disconnect_reason_protocol_error() -> Result<(), GgrsError> {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut host_sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .add_player(PlayerType::Spectator(spec_addr), 2)?
+        .start_p2p_session(socket1)?;
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut spec_sess =
+        SessionBuilder::<StubConfig>::new().start_spectator_session(host_addr, socket2)?;
+
+    for _ in 0..50 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(host_sess.current_state(), SessionState::Running);
+    host_sess.events().for_each(drop);
+
+    // the spectator stops acknowledging inputs, so they pile up on the host
+    let mut stub = stubs::GameStub::new();
+    for i in 0..200 {
+        host_sess.add_local_input(0, StubInput { inp: i })?;
+        host_sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(host_sess.advance_frame()?);
+    }
+
+    let disconnects: Vec<_> = host_sess
+        .events()
+        .filter(|event| matches!(event, GgrsEvent::Disconnected { .. }))
+        .collect();
+    assert_eq!(disconnects.len(), 1);
+    assert!(matches!(
+        disconnects[0],
+        GgrsEvent::Disconnected {
+            addr,
+            reason: DisconnectReason::ProtocolError
+        } if addr == spec_addr
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn test_add_spectator_to_running_host() -> Result<(), GgrsError> {
+    let host_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7777);
+    let spec_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8888);
+
+    let socket1 = UdpNonBlockingSocket::bind_to_port(7777).unwrap();
+    let mut host_sess = SessionBuilder::<StubConfig>::new()
+        .add_player(PlayerType::Local, 0)?
+        .add_player(PlayerType::Local, 1)?
+        .start_p2p_session(socket1)?;
+    assert_eq!(host_sess.current_state(), SessionState::Running);
+
+    let spec_handle = host_sess.add_spectator(spec_addr)?;
+    assert_eq!(spec_handle, 2);
+    assert_eq!(host_sess.num_spectators(), 1);
+    assert_eq!(host_sess.current_state(), SessionState::Synchronizing);
+    assert!(matches!(
+        host_sess.add_spectator(spec_addr),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    let socket2 = UdpNonBlockingSocket::bind_to_port(8888).unwrap();
+    let mut spec_sess =
+        SessionBuilder::<StubConfig>::new().start_spectator_session(host_addr, socket2)?;
+
+    for _ in 0..50 {
+        spec_sess.poll_remote_clients();
+        host_sess.poll_remote_clients();
+    }
+    assert_eq!(spec_sess.current_state(), SessionState::Running);
+    assert_eq!(host_sess.current_state(), SessionState::Running);
+
+    let mut host_stub = stubs::GameStub::new();
+    let mut spec_stub = stubs::GameStub::new();
+    let mut host_states = vec![host_stub.gs.state];
+    for i in 0..30 {
+        host_sess.add_local_input(0, StubInput { inp: i })?;
+        host_sess.add_local_input(1, StubInput { inp: i * 3 })?;
+        host_stub.handle_requests(host_sess.advance_frame()?);
+        host_states.push(host_stub.gs.state);
+        spec_sess.poll_remote_clients();
+    }
+
+    // the spectator catches up with all confirmed frames relayed by the host, the last frame is not confirmed yet
+    for _ in 0..100 {
+        host_sess.poll_remote_clients();
+        if let Ok(requests) = spec_sess.advance_frame() {
+            spec_stub.handle_requests(requests);
+        }
+        if spec_stub.gs.frame == 29 {
+            break;
+        }
+    }
+    assert_eq!(spec_stub.gs.frame, 29);
+    assert_eq!(spec_stub.gs.state, host_states[29]);
+
+    // spectators can't join a game in progress
+    let late_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+    assert!(matches!(
+        host_sess.add_spectator(late_addr),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    Ok(())
+}
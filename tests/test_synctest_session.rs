@@ -1,7 +1,11 @@
 mod stubs;
 mod stubs_enum;
 
-use ggrs::{GgrsError, GgrsRequest, SessionBuilder};
+use ggrs::{Capabilities, Config, GgrsError, GgrsRequest, SessionBuilder};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
 use stubs::{StubConfig, StubInput};
 
 #[test]
@@ -11,6 +15,54 @@ fn test_create_session() {
         .is_ok());
 }
 
+#[test]
+fn test_check_distance_too_big() {
+    // only as many gamestates as the prediction window are saved
+    let result = SessionBuilder::<StubConfig>::new()
+        .with_max_prediction_window(8)
+        .with_check_distance(8)
+        .start_synctest_session();
+    assert!(matches!(result, Err(GgrsError::InvalidRequest { .. })));
+
+    let sess = SessionBuilder::<StubConfig>::new()
+        .with_max_prediction_window(8)
+        .with_check_distance(7)
+        .start_synctest_session()
+        .unwrap();
+    assert_eq!(sess.check_distance(), 7);
+
+    // the rollbacks also need to fit into the saved frames
+    let result = SessionBuilder::<StubConfig>::new()
+        .with_max_saved_frames(2)
+        .unwrap()
+        .with_check_distance(2)
+        .start_synctest_session();
+    assert!(matches!(result, Err(GgrsError::InvalidRequest { .. })));
+}
+
+#[test]
+fn test_input_delay_bounds() -> Result<(), GgrsError> {
+    // the largest input delay that fits into the input queue together with the prediction window
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_max_prediction_window(8)
+        .with_input_delay(119)
+        .start_synctest_session()?;
+    for i in 0..200 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+
+    let result = SessionBuilder::<StubConfig>::new()
+        .with_max_prediction_window(8)
+        .with_input_delay(120)
+        .start_synctest_session();
+    assert!(matches!(result, Err(GgrsError::InvalidRequest { .. })));
+
+    Ok(())
+}
+
 #[test]
 fn test_advance_frame_no_rollbacks() -> Result<(), GgrsError> {
     let check_distance = 0;
@@ -64,6 +116,113 @@ fn test_advance_frame_with_rollbacks() -> Result<(), GgrsError> {
     Ok(())
 }
 
+#[test]
+fn test_save_and_load_counts() -> Result<(), GgrsError> {
+    let check_distance = 3;
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(check_distance)
+        .start_synctest_session()?;
+
+    for i in 0..50 {
+        let (saves, loads) = (sess.save_count(), sess.load_count());
+        let requests = sess.advance_frame_with_inputs(&[StubInput { inp: i }; 2])?;
+        let advances = requests
+            .iter()
+            .filter(|request| matches!(request, GgrsRequest::AdvanceFrame { .. }))
+            .count();
+        stub.handle_requests(requests);
+
+        if i as usize <= check_distance {
+            // no rollback yet
+            assert_eq!(sess.save_count() - saves, 1);
+            assert_eq!(sess.load_count() - loads, 0);
+            assert_eq!(advances, 1);
+        } else {
+            // one load, then `check_distance` resimulated frames and the new frame
+            assert_eq!(sess.save_count() - saves, check_distance);
+            assert_eq!(sess.load_count() - loads, 1);
+            assert_eq!(advances, check_distance + 1);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_state_roundtrip() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new().start_synctest_session()?;
+    for i in 0..10 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+
+    sess.verify_state_roundtrip(|requests| stub.handle_requests(requests))?;
+    assert_eq!(stub.gs.frame, 10);
+
+    // a broken load that forgets part of the gamestate
+    let result = sess.verify_state_roundtrip(|requests| {
+        for request in requests {
+            match request {
+                GgrsRequest::LoadGameState { cell, .. } => {
+                    stub.gs.frame = cell.load().unwrap().frame;
+                    stub.gs.state = 0;
+                }
+                request => stub.handle_requests(vec![request]),
+            }
+        }
+    });
+    assert!(matches!(
+        result,
+        Err(GgrsError::StateRoundtripMismatch { frame: 10, .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_capabilities() -> Result<(), GgrsError> {
+    let sess = SessionBuilder::<StubConfig>::new().start_synctest_session()?;
+    let capabilities = sess.capabilities();
+
+    assert!(capabilities.contains(Capabilities::LOCAL_INPUT | Capabilities::CHECKPOINTS));
+    assert!(capabilities.contains(Capabilities::INPUT_DELAY));
+    assert!(capabilities.contains(Capabilities::DESYNC_DETECTION));
+    assert!(!capabilities.contains(Capabilities::DISCONNECT_PLAYER));
+    assert!(!capabilities.contains(Capabilities::NETWORK_STATS));
+    assert!(!capabilities.contains(Capabilities::SUSPEND_TIMEOUTS));
+
+    Ok(())
+}
+
+#[test]
+fn test_comparisons_active() -> Result<(), GgrsError> {
+    let check_distance = 4;
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(check_distance)
+        .start_synctest_session()?;
+
+    for i in 0..10 {
+        assert_eq!(sess.comparisons_active(), i > check_distance as u32);
+        let requests = sess.advance_frame_with_inputs(&[StubInput { inp: i }; 2])?;
+        // comparisons go along with simulated rollbacks
+        let rolled_back = matches!(requests[0], GgrsRequest::LoadGameState { .. });
+        assert_eq!(rolled_back, i > check_distance as u32);
+        stub.handle_requests(requests);
+    }
+
+    // without a check distance, there is nothing to compare
+    let sess = SessionBuilder::<StubConfig>::new()
+        .with_check_distance(0)
+        .start_synctest_session()?;
+    assert!(!sess.comparisons_active());
+
+    Ok(())
+}
+
 #[test]
 fn test_advance_frames_with_delayed_input() -> Result<(), GgrsError> {
     let check_distance = 7;
@@ -84,6 +243,363 @@ fn test_advance_frames_with_delayed_input() -> Result<(), GgrsError> {
     Ok(())
 }
 
+#[test]
+fn test_saved_frame_range() -> Result<(), GgrsError> {
+    let check_distance = 2;
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(check_distance)
+        .start_synctest_session()?;
+
+    assert_eq!(sess.saved_frame_range(), None);
+
+    for i in 0..20 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        let requests = sess.advance_frame()?;
+        stub.handle_requests(requests);
+
+        let (oldest, newest) = sess.saved_frame_range().expect("frames should be saved");
+        assert_eq!(newest, i as i32);
+        assert!(oldest <= newest - check_distance as i32 || oldest == 0);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_checksum_at() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(2)
+        .start_synctest_session()?;
+    assert_eq!(sess.checksum_at(0), None);
+
+    // the stub saves the hash of its state as checksum
+    let mut checksums = HashMap::new();
+    for i in 0..20 {
+        let mut hasher = DefaultHasher::new();
+        stub.gs.hash(&mut hasher);
+        checksums.insert(i as i32, hasher.finish() as u128);
+
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+
+    let (oldest, newest) = sess.saved_frame_range().expect("frames should be saved");
+    for frame in oldest..=newest {
+        assert_eq!(sess.checksum_at(frame), Some(checksums[&frame]));
+    }
+    assert_eq!(sess.checksum_at(oldest - 1), None);
+    assert_eq!(sess.checksum_at(newest + 1), None);
+    assert_eq!(sess.checksum_at(-1), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_local_inputs_cleared_after_advance() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new().start_synctest_session()?;
+
+    for i in 0..20 {
+        assert!(!sess.has_local_input(0));
+        assert!(!sess.has_local_input(1));
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        assert!(sess.has_local_input(0));
+        assert!(sess.has_local_input(1));
+        stub.handle_requests(sess.advance_frame()?);
+    }
+
+    // a missing input is not replaced by the one of the previous frame
+    sess.add_local_input(0, StubInput { inp: 20 })?;
+    assert!(sess.advance_frame().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_advance_frame_with_inputs() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(2)
+        .start_synctest_session()?;
+
+    for i in 0..50 {
+        let requests =
+            sess.advance_frame_with_inputs(&[StubInput { inp: i }, StubInput { inp: 0 }])?;
+        stub.handle_requests(requests);
+        assert_eq!(stub.gs.frame, i as i32 + 1);
+    }
+
+    // inputs for every player are required
+    assert!(sess
+        .advance_frame_with_inputs(&[StubInput { inp: 0 }])
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_tag_frame() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new().start_synctest_session()?;
+
+    for i in 0..30 {
+        if i % 10 == 0 {
+            sess.tag_frame(format!("round {}", i / 10));
+        }
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+    // tagging again replaces the label
+    sess.tag_frame("end".to_owned());
+    sess.tag_frame("game over".to_owned());
+
+    assert_eq!(sess.frame_label(10), Some("round 1"));
+    assert_eq!(sess.frame_label(11), None);
+    let labels: Vec<_> = sess.frame_labels().collect();
+    assert_eq!(
+        labels,
+        vec![
+            (0, "round 0"),
+            (10, "round 1"),
+            (20, "round 2"),
+            (30, "game over")
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_boxed_slice_state() -> Result<(), GgrsError> {
+    // the state can be any type, e.g. a boxed slice instead of a Vec
+    struct BoxedStateConfig;
+
+    impl Config for BoxedStateConfig {
+        type Input = StubInput;
+        type State = Box<[u8]>;
+        type Address = SocketAddr;
+    }
+
+    // frame number followed by the sum of all inputs
+    let mut state: Box<[u8]> = vec![0; 8].into_boxed_slice();
+    let mut sess = SessionBuilder::<BoxedStateConfig>::new()
+        .with_check_distance(4)
+        .start_synctest_session()?;
+
+    for i in 0..100 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i * 2 })?;
+        for request in sess.advance_frame()? {
+            match request {
+                GgrsRequest::SaveGameState { cell, frame } => {
+                    assert_eq!(state[..4], frame.to_le_bytes());
+                    let checksum = state.iter().map(|&b| b as u128).sum();
+                    cell.save(frame, Some(state.clone()), Some(checksum));
+                }
+                GgrsRequest::LoadGameState { cell, .. } => {
+                    state = cell.load().expect("state should have been saved");
+                }
+                GgrsRequest::AdvanceFrame { inputs } => {
+                    let frame = i32::from_le_bytes(state[..4].try_into().unwrap()) + 1;
+                    let sum = u32::from_le_bytes(state[4..].try_into().unwrap())
+                        + inputs.iter().map(|(input, _)| input.inp).sum::<u32>();
+                    state[..4].copy_from_slice(&frame.to_le_bytes());
+                    state[4..].copy_from_slice(&sum.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    assert_eq!(state[..4], 100i32.to_le_bytes());
+    assert_eq!(state[4..], (3 * (0..100).sum::<u32>()).to_le_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn test_input_merge() -> Result<(), GgrsError> {
+    // returns the inputs of the first frame after adding two inputs per player
+    fn first_frame_inputs(
+        builder: SessionBuilder<StubConfig>,
+    ) -> Result<Vec<StubInput>, GgrsError> {
+        let mut sess = builder.start_synctest_session()?;
+        for handle in 0..2 {
+            sess.add_local_input(handle, StubInput { inp: 0b01 })?;
+            sess.add_local_input(handle, StubInput { inp: 0b10 })?;
+        }
+        for request in sess.advance_frame()? {
+            if let GgrsRequest::AdvanceFrame { inputs } = request {
+                return Ok(inputs.into_iter().map(|(input, _)| input).collect());
+            }
+        }
+        panic!("no AdvanceFrame request");
+    }
+
+    // per default, the later input replaces the earlier one
+    let replaced = first_frame_inputs(SessionBuilder::new())?;
+    assert!(replaced.iter().all(|input| input.inp == 0b10));
+
+    // with a merge function, both inputs are combined
+    let merged = first_frame_inputs(SessionBuilder::new().with_input_merge(
+        |prev: StubInput, new: StubInput| StubInput {
+            inp: prev.inp | new.inp,
+        },
+    ))?;
+    assert!(merged.iter().all(|input| input.inp == 0b11));
+
+    Ok(())
+}
+
+#[test]
+fn test_frames_until_input_active() -> Result<(), GgrsError> {
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_check_distance(0)
+        .with_input_delay(2)
+        .start_synctest_session()?;
+
+    for i in 0..5 {
+        let remaining = sess.frames_until_input_active(0)?;
+        assert_eq!(remaining, 2_usize.saturating_sub(i as usize));
+
+        sess.add_local_input(0, StubInput { inp: i + 1 })?;
+        sess.add_local_input(1, StubInput { inp: i + 1 })?;
+        for request in sess.advance_frame()? {
+            if let GgrsRequest::AdvanceFrame { inputs } = request {
+                // while inputs are not active yet, players have default input
+                let expected = if remaining > 0 { 0 } else { i - 1 };
+                assert!(inputs.iter().all(|(input, _)| input.inp == expected));
+            }
+        }
+    }
+
+    assert!(sess.frames_until_input_active(2).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_checkpoint() -> Result<(), GgrsError> {
+    let check_distance = 4;
+    let input = |i: u32| [StubInput { inp: i }, StubInput { inp: i / 3 }];
+
+    // the reference run without interruption
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::new()
+        .with_check_distance(check_distance)
+        .start_synctest_session()?;
+    for i in 0..100 {
+        stub.handle_requests(sess.advance_frame_with_inputs(&input(i))?);
+    }
+
+    // take a checkpoint at frame 50 and continue in a fresh session
+    let mut interrupted_stub = stubs::GameStub::new();
+    let mut interrupted_sess = SessionBuilder::new()
+        .with_check_distance(check_distance)
+        .start_synctest_session()?;
+    for i in 0..50 {
+        interrupted_stub.handle_requests(interrupted_sess.advance_frame_with_inputs(&input(i))?);
+    }
+    let mut checkpoint = Vec::new();
+    interrupted_sess.save_checkpoint(&mut checkpoint)?;
+    let saved_game_state = interrupted_stub.gs;
+
+    let mut restored_stub = stubs::GameStub::new();
+    restored_stub.gs = saved_game_state;
+    let mut restored_sess = SessionBuilder::new()
+        .with_check_distance(check_distance)
+        .start_synctest_session()?;
+    restored_sess.restore_checkpoint(checkpoint.as_slice())?;
+    assert_eq!(restored_sess.current_frame(), 50);
+    for i in 50..100 {
+        // rollbacks into frames before the checkpoint and checksum comparisons keep working
+        restored_stub.handle_requests(restored_sess.advance_frame_with_inputs(&input(i))?);
+    }
+
+    assert_eq!(restored_sess.current_frame(), sess.current_frame());
+    assert_eq!(restored_stub.gs.frame, stub.gs.frame);
+    assert_eq!(restored_stub.gs.state, stub.gs.state);
+
+    // checkpoints of differently configured sessions are rejected
+    let mut other_sess = SessionBuilder::<StubConfig>::new()
+        .with_check_distance(2)
+        .start_synctest_session()?;
+    assert!(matches!(
+        other_sess.restore_checkpoint(checkpoint.as_slice()),
+        Err(GgrsError::InvalidRequest { .. })
+    ));
+
+    // checkpoints in a different format or cut short are rejected as well
+    let mut future_checkpoint = checkpoint.clone();
+    future_checkpoint[..2].copy_from_slice(&2u16.to_le_bytes());
+    assert!(matches!(
+        restored_sess.restore_checkpoint(future_checkpoint.as_slice()),
+        Err(GgrsError::CheckpointError { .. })
+    ));
+    assert!(matches!(
+        restored_sess.restore_checkpoint(&checkpoint[..checkpoint.len() / 2]),
+        Err(GgrsError::CheckpointError { .. })
+    ));
+    assert_eq!(restored_sess.current_frame(), 100);
+
+    // failing to write the checkpoint is reported
+    struct FailingWriter;
+    impl std::io::Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::Other.into())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    assert!(matches!(
+        sess.save_checkpoint(FailingWriter),
+        Err(GgrsError::CheckpointError { .. })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_load_of_unsaved_state_returns_none() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_check_distance(2)
+        .start_synctest_session()?;
+
+    for i in 0..10 {
+        stub.handle_requests(sess.advance_frame_with_inputs(&[StubInput { inp: i }; 2])?);
+    }
+
+    // the game fails to save the state of the current frame, e.g. because serializing it failed
+    let unsaved_frame = sess.current_frame();
+    for i in 10..20 {
+        for request in sess.advance_frame_with_inputs(&[StubInput { inp: i }; 2])? {
+            match request {
+                GgrsRequest::SaveGameState { cell, frame } if frame == unsaved_frame => {
+                    // the checksum is still reported, so the resimulation of this frame is not flagged as a desync
+                    let mut hasher = DefaultHasher::new();
+                    stub.gs.hash(&mut hasher);
+                    cell.save(frame, None, Some(hasher.finish() as u128));
+                }
+                GgrsRequest::LoadGameState { cell, frame } if frame == unsaved_frame => {
+                    // the rollback hands out the cell without data instead of panicking, so the game can handle it
+                    assert!(cell.load().is_none());
+                    return Ok(());
+                }
+                request => stub.handle_requests(vec![request]),
+            }
+        }
+    }
+
+    panic!("the unsaved frame should have been loaded");
+}
+
 #[test]
 #[should_panic]
 fn test_advance_frames_with_random_checksums() {
@@ -101,3 +617,158 @@ fn test_advance_frames_with_random_checksums() {
         assert_eq!(stub.gs.frame, i as i32 + 1);
     }
 }
+
+#[test]
+fn test_gameplay_checksum_ignores_cosmetic_state() -> Result<(), GgrsError> {
+    // the gameplay state plus a cosmetic random seed that is not restored on load
+    #[derive(Clone, Copy, Default, Hash)]
+    struct CosmeticState {
+        gameplay: stubs::StateStub,
+        particle_seed: u64,
+    }
+
+    struct CosmeticConfig;
+
+    impl Config for CosmeticConfig {
+        type Input = StubInput;
+        type State = CosmeticState;
+        type Address = SocketAddr;
+    }
+
+    fn hash<T: Hash>(t: &T) -> u128 {
+        let mut hasher = DefaultHasher::new();
+        t.hash(&mut hasher);
+        hasher.finish() as u128
+    }
+
+    fn handle_requests(gs: &mut CosmeticState, requests: Vec<GgrsRequest<CosmeticConfig>>) {
+        for request in requests {
+            match request {
+                GgrsRequest::SaveGameState { cell, frame } => {
+                    gs.particle_seed = rand::random();
+                    let checksum = hash(gs);
+                    let gameplay_checksum = hash(&gs.gameplay);
+                    cell.save_with_gameplay_checksum(
+                        frame,
+                        Some(*gs),
+                        Some(checksum),
+                        Some(gameplay_checksum),
+                    );
+                }
+                GgrsRequest::LoadGameState { cell, .. } => {
+                    gs.gameplay = cell.load().expect("state should have been saved").gameplay;
+                }
+                GgrsRequest::AdvanceFrame { .. } => gs.gameplay.frame += 1,
+            }
+        }
+    }
+
+    let mut gs = CosmeticState::default();
+    let mut sess = SessionBuilder::<CosmeticConfig>::new()
+        .with_check_distance(4)
+        .start_synctest_session()?;
+
+    // the full checksums of resimulated frames differ, but only the gameplay checksums are compared
+    for i in 0..50 {
+        let requests = sess.advance_frame_with_inputs(&[StubInput { inp: i }; 2])?;
+        handle_requests(&mut gs, requests);
+    }
+    assert_eq!(gs.gameplay.frame, 50);
+    let frame = sess.current_frame() - 1;
+    assert_ne!(sess.checksum_at(frame), Some(hash(&gs.gameplay)));
+
+    Ok(())
+}
+
+#[test]
+fn test_distinct_player_inputs_with_rollbacks() -> Result<(), GgrsError> {
+    let input_delay = 2;
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_check_distance(7)
+        .with_input_delay(input_delay)
+        .start_synctest_session()?;
+
+    // every resimulated frame must use the same distinct inputs per player as the first simulation,
+    // otherwise the checksums of the resimulated frames would not match
+    for i in 0..100u32 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i * 7 + 3 })?;
+        for request in sess.advance_frame()? {
+            if let GgrsRequest::AdvanceFrame { inputs } = &request {
+                let inputs: Vec<_> = inputs.iter().map(|(input, _)| input.inp).collect();
+                let expected = match (stub.gs.frame as u32).checked_sub(input_delay as u32) {
+                    Some(added_in) => vec![added_in, added_in * 7 + 3],
+                    None => vec![0, 0],
+                };
+                assert_eq!(inputs, expected);
+            }
+            stub.handle_requests(vec![request]);
+        }
+        assert_eq!(stub.gs.frame, i as i32 + 1);
+    }
+    assert!(sess.checksum_at(sess.current_frame() - 1).is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_fork() -> Result<(), GgrsError> {
+    let mut stub = stubs::GameStub::new();
+    let mut sess = SessionBuilder::<StubConfig>::new()
+        .with_check_distance(2)
+        .start_synctest_session()?;
+    for i in 0..20 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+
+    // the inputs registered for the current frame are forked as well
+    sess.add_local_input(0, StubInput { inp: 20 })?;
+    sess.add_local_input(1, StubInput { inp: 20 })?;
+    let mut fork_a = sess.fork();
+    let mut stub_a = stubs::GameStub { gs: stub.gs };
+    let mut fork_b = sess.fork();
+    let mut stub_b = stubs::GameStub { gs: stub.gs };
+
+    let parent_range = sess.saved_frame_range();
+    let parent_checksums: Vec<_> = (0..20).map(|frame| sess.checksum_at(frame)).collect();
+
+    stub_a.handle_requests(fork_a.advance_frame()?);
+    stub_b.handle_requests(fork_b.advance_frame()?);
+    for i in 21..30 {
+        fork_a.add_local_input(0, StubInput { inp: i })?;
+        fork_a.add_local_input(1, StubInput { inp: i })?;
+        stub_a.handle_requests(fork_a.advance_frame()?);
+
+        fork_b.add_local_input(0, StubInput { inp: i })?;
+        fork_b.add_local_input(1, StubInput { inp: i + 1 })?;
+        stub_b.handle_requests(fork_b.advance_frame()?);
+    }
+
+    // the branches diverged
+    assert_eq!(fork_a.current_frame(), 30);
+    assert_eq!(fork_b.current_frame(), 30);
+    assert_eq!(stub_a.gs.frame, stub_b.gs.frame);
+    assert_ne!(stub_a.gs.state, stub_b.gs.state);
+    assert_ne!(fork_a.checksum_at(29), fork_b.checksum_at(29));
+
+    // the parent is unchanged
+    assert_eq!(sess.current_frame(), 20);
+    assert_eq!(sess.saved_frame_range(), parent_range);
+    let checksums: Vec<_> = (0..20).map(|frame| sess.checksum_at(frame)).collect();
+    assert_eq!(checksums, parent_checksums);
+
+    // and continues exactly like the branch given the same inputs
+    stub.handle_requests(sess.advance_frame()?);
+    for i in 21..30 {
+        sess.add_local_input(0, StubInput { inp: i })?;
+        sess.add_local_input(1, StubInput { inp: i })?;
+        stub.handle_requests(sess.advance_frame()?);
+    }
+    assert_eq!(stub.gs.state, stub_a.gs.state);
+    assert_eq!(sess.checksum_at(29), fork_a.checksum_at(29));
+
+    Ok(())
+}